@@ -0,0 +1,534 @@
+use crate::parser::{Limits, ParseError};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+
+/// chunk 大小行的最大长度，包括 chunk 扩展
+const MAX_SIZE_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// 等待下一个 chunk 的大小行
+    Size,
+    /// 当前 chunk 还剩下的数据字节数
+    Data(u64),
+    /// chunk 数据之后的 `\r\n`
+    DataEnd,
+    /// 已经读完最后一个 chunk 和 trailer
+    Done,
+}
+
+/// 按 RFC 9112 第 7.1 节解码 chunked 请求体的 [`Read`] 适配器
+///
+/// 只读取属于请求体的字节，读到 `0` 之后连接上剩余的数据属于下一个请求。
+/// 格式错误时返回包含 [`ParseError::BadRequest`] 的 `InvalidData` 错误，
+/// 解码后超过请求体上限返回 [`ParseError::PayloadTooLarge`]，
+/// trailer 超过请求头上限返回 [`ParseError::HeadersTooLarge`]；
+/// 数据不完整时返回 `UnexpectedEof`。
+pub struct ChunkedReader<R> {
+    inner: R,
+    state: State,
+    decoded: u64,
+    max_body: u64,
+    /// trailer 还能使用的字节数
+    trailer_budget: usize,
+    max_trailers: usize,
+    trailers: HashMap<String, String>,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R, limits: &Limits) -> Self {
+        Self {
+            inner,
+            state: State::Size,
+            decoded: 0,
+            max_body: limits.max_body() as u64,
+            trailer_budget: limits.max_header_bytes(),
+            max_trailers: limits.max_headers(),
+            trailers: HashMap::new(),
+        }
+    }
+
+    /// 是否已经读完整个请求体
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// 请求体之后的 trailer 字段，读完请求体后才完整
+    pub fn trailers(&self) -> &HashMap<String, String> {
+        &self.trailers
+    }
+
+    pub fn into_trailers(self) -> HashMap<String, String> {
+        self.trailers
+    }
+
+    /// 读取 chunk 大小行，返回 chunk 的大小
+    fn read_size(&mut self) -> io::Result<u64> {
+        let line = read_line(&mut self.inner, MAX_SIZE_LINE, ParseError::BadRequest)?;
+        Ok(parse_size(&line)?)
+    }
+
+    /// 读取最后一个 chunk 之后的 trailer，直到空行
+    fn read_trailers(&mut self) -> io::Result<()> {
+        loop {
+            let line = read_line(
+                &mut self.inner,
+                self.trailer_budget,
+                ParseError::HeadersTooLarge,
+            )?;
+            if line.is_empty() {
+                return Ok(());
+            }
+            self.trailer_budget = self.trailer_budget.saturating_sub(line.len() + 2);
+            if self.trailers.len() >= self.max_trailers {
+                return Err(ParseError::HeadersTooLarge.into());
+            }
+            let (name, value) = parse_trailer(&line)?;
+            self.trailers.insert(name, value);
+        }
+    }
+}
+
+/// 增量解码 chunked 请求体，用于非阻塞连接
+///
+/// 和 [`ChunkedReader`] 的格式检查和上限相同，但不需要一次拿到完整的数据：
+/// 每次把连接上新读到的字节交给 [`feed`](Self::feed)，解码结果累积在内部，
+/// 不完整的行保留在调用方的缓冲区中等待更多数据。
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: Decode,
+    body: Vec<u8>,
+    max_body: usize,
+    trailer_budget: usize,
+    max_trailers: usize,
+    trailers: HashMap<String, String>,
+    /// 当前行已经查找过行尾的字节数，避免每次从行首重新查找
+    line_scanned: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decode {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+impl ChunkedDecoder {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            state: Decode::Size,
+            body: Vec::new(),
+            max_body: limits.max_body(),
+            trailer_budget: limits.max_header_bytes(),
+            max_trailers: limits.max_headers(),
+            trailers: HashMap::new(),
+            line_scanned: 0,
+        }
+    }
+
+    /// 解码 `input` 开头的数据，返回消耗的字节数
+    ///
+    /// `input` 从上次消耗到的位置开始；没有消耗的字节（不完整的行）下次需要再次传入。
+    pub fn feed(&mut self, input: &[u8]) -> Result<usize, ParseError> {
+        let mut position = 0;
+        loop {
+            let rest = &input[position..];
+            match self.state {
+                Decode::Done => return Ok(position),
+                Decode::Size => {
+                    let Some(line) = self.take_line(rest, MAX_SIZE_LINE, ParseError::BadRequest)?
+                    else {
+                        return Ok(position);
+                    };
+                    let size = parse_size(line)?;
+                    position += line.len() + 2;
+                    self.state = if size == 0 {
+                        Decode::Trailers
+                    } else if size > (self.max_body - self.body.len()) as u64 {
+                        return Err(ParseError::PayloadTooLarge);
+                    } else {
+                        Decode::Data(size as usize)
+                    };
+                }
+                Decode::Data(remaining) => {
+                    if rest.is_empty() {
+                        return Ok(position);
+                    }
+                    let n = rest.len().min(remaining);
+                    self.body.extend_from_slice(&rest[..n]);
+                    position += n;
+                    self.state = match remaining - n {
+                        0 => Decode::DataEnd,
+                        remaining => Decode::Data(remaining),
+                    };
+                }
+                Decode::DataEnd => {
+                    if rest.len() < 2 {
+                        return Ok(position);
+                    }
+                    if &rest[..2] != b"\r\n" {
+                        return Err(ParseError::BadRequest);
+                    }
+                    position += 2;
+                    self.state = Decode::Size;
+                }
+                Decode::Trailers => {
+                    let budget = self.trailer_budget;
+                    let Some(line) = self.take_line(rest, budget, ParseError::HeadersTooLarge)?
+                    else {
+                        return Ok(position);
+                    };
+                    position += line.len() + 2;
+                    if line.is_empty() {
+                        self.state = Decode::Done;
+                        continue;
+                    }
+                    self.trailer_budget = budget.saturating_sub(line.len() + 2);
+                    if self.trailers.len() >= self.max_trailers {
+                        return Err(ParseError::HeadersTooLarge);
+                    }
+                    let (name, value) = parse_trailer(line)?;
+                    self.trailers.insert(name, value);
+                }
+            }
+        }
+    }
+
+    /// 是否已经读完整个请求体和 trailer
+    pub fn is_done(&self) -> bool {
+        self.state == Decode::Done
+    }
+
+    /// 解码后的请求体和 trailer
+    pub fn into_parts(self) -> (Vec<u8>, HashMap<String, String>) {
+        (self.body, self.trailers)
+    }
+
+    /// `input` 开头的一行（不含 `\r\n`），还没有读到行尾时返回 `None`
+    fn take_line<'a>(
+        &mut self,
+        input: &'a [u8],
+        limit: usize,
+        error: ParseError,
+    ) -> Result<Option<&'a [u8]>, ParseError> {
+        let from = self.line_scanned.min(input.len());
+        let Some(end) = input[from..].iter().position(|&b| b == b'\n') else {
+            if input.len() > limit.saturating_add(1) {
+                return Err(error);
+            }
+            self.line_scanned = input.len();
+            return Ok(None);
+        };
+        self.line_scanned = 0;
+        let end = from + end;
+        // 不允许只有 `\n` 的行尾
+        if end == 0 || input[end - 1] != b'\r' {
+            return Err(ParseError::BadRequest);
+        }
+        let line = &input[..end - 1];
+        if line.len() > limit {
+            return Err(error);
+        }
+        Ok(Some(line))
+    }
+}
+
+/// 解析 chunk 大小行，返回 chunk 的大小
+fn parse_size(line: &[u8]) -> Result<u64, ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::BadRequest)?;
+    let (size, extensions) = match line.split_once(';') {
+        Some((size, extensions)) => (size, Some(extensions)),
+        None => (line, None),
+    };
+    // 分号前允许有空白（BWS），数字本身不允许
+    let size = size.trim_end_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::BadRequest);
+    }
+    if let Some(extensions) = extensions {
+        check_extensions(extensions).map_err(|_| ParseError::BadRequest)?;
+    }
+    let size = size.trim_start_matches('0');
+    if size.len() > 16 {
+        return Err(ParseError::PayloadTooLarge);
+    }
+    // 前面已经检查过都是十六进制数字，去掉前导零后为空表示大小为 0
+    Ok(u64::from_str_radix(size, 16).unwrap_or(0))
+}
+
+/// 解析一个 trailer 字段
+fn parse_trailer(line: &[u8]) -> Result<(String, String), ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::BadRequest)?;
+    let (name, value) = line.split_once(':').ok_or(ParseError::BadRequest)?;
+    // 字段名和冒号之间不允许有空白，也不支持 obs-fold
+    if !is_token(name) {
+        return Err(ParseError::BadRequest);
+    }
+    Ok((
+        name.to_string(),
+        value.trim_matches([' ', '\t']).to_string(),
+    ))
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Size => {
+                    let size = self.read_size()?;
+                    if size == 0 {
+                        self.read_trailers()?;
+                        self.state = State::Done;
+                        return Ok(0);
+                    }
+                    if size > self.max_body - self.decoded {
+                        return Err(ParseError::PayloadTooLarge.into());
+                    }
+                    self.state = State::Data(size);
+                }
+                State::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                    let n = self.inner.read(&mut buf[..max])?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.decoded += n as u64;
+                    self.state = match remaining - n as u64 {
+                        0 => State::DataEnd,
+                        remaining => State::Data(remaining),
+                    };
+                    return Ok(n);
+                }
+                State::DataEnd => {
+                    let mut end = [0u8; 2];
+                    self.inner.read_exact(&mut end)?;
+                    if &end != b"\r\n" {
+                        return Err(bad_request());
+                    }
+                    self.state = State::Size;
+                }
+            }
+        }
+    }
+}
+
+/// 读取一行并去掉结尾的 `\r\n`，不允许只有 `\n` 的行尾
+fn read_line<R: BufRead>(reader: &mut R, limit: usize, error: ParseError) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    let max = limit.saturating_add(2) as u64;
+    let n = reader.take(max).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        if n as u64 == max {
+            return Err(error.into());
+        }
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if !line.ends_with(b"\r\n") {
+        return Err(bad_request());
+    }
+    line.truncate(line.len() - 2);
+    if line.len() > limit {
+        return Err(error.into());
+    }
+    Ok(line)
+}
+
+/// 检查 chunk 扩展的格式：`*( BWS ";" BWS name [ BWS "=" BWS value ] )`，扩展的内容被忽略
+fn check_extensions(extensions: &str) -> io::Result<()> {
+    for extension in split_extensions(extensions)? {
+        let (name, value) = match extension.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim_matches([' ', '\t']))),
+            None => (extension, None),
+        };
+        if !is_token(name.trim_matches([' ', '\t'])) {
+            return Err(bad_request());
+        }
+        match value {
+            Some(value) if !is_token(value) && !is_quoted_string(value) => {
+                return Err(bad_request());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 按分号切分扩展，引号内的分号不切分
+fn split_extensions(extensions: &str) -> io::Result<Vec<&str>> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in extensions.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(&extensions[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return Err(bad_request());
+    }
+    parts.push(&extensions[start..]);
+    Ok(parts)
+}
+
+/// RFC 9110 中的 token
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn is_quoted_string(value: &str) -> bool {
+    value.len() >= 2
+        && value.starts_with('"')
+        && value.ends_with('"')
+        && value[1..value.len() - 1]
+            .bytes()
+            .all(|b| b == b'\t' || (b' '..=b'~').contains(&b) || b >= 0x80)
+}
+
+fn bad_request() -> io::Error {
+    ParseError::BadRequest.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 请求体、trailer 和消耗的字节数
+    type Decoded = (Vec<u8>, HashMap<String, String>, usize);
+
+    fn decode(raw: &[u8]) -> io::Result<Decoded> {
+        let limits = Limits::new().body(64).header_bytes(64);
+        let mut cursor = raw;
+        let mut reader = ChunkedReader::new(&mut cursor, &limits);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        let trailers = reader.into_trailers();
+        Ok((body, trailers, raw.len() - cursor.len()))
+    }
+
+    fn error(raw: &[u8]) -> Option<ParseError> {
+        let e = decode(raw).unwrap_err();
+        e.get_ref()?.downcast_ref::<ParseError>().copied()
+    }
+
+    #[test]
+    fn test_decode_chunks_with_extensions_and_trailers() {
+        let raw = b"5;name=value;flag\r\nhello\r\n6 ; q=\"a;b\"\r\n world\r\n0\r\nExpires: never\r\nX-Checksum:  abc \r\n\r\nGET /next";
+        let (body, trailers, consumed) = decode(raw).unwrap();
+        assert_eq!(body, b"hello world");
+        assert_eq!(trailers.get("Expires").map(String::as_str), Some("never"));
+        assert_eq!(trailers.get("X-Checksum").map(String::as_str), Some("abc"));
+        // 之后的数据属于下一个请求
+        assert_eq!(&raw[consumed..], b"GET /next");
+    }
+
+    #[test]
+    fn test_reject_malformed_framing() {
+        assert_eq!(
+            error(b"zz\r\nhello\r\n0\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        assert_eq!(error(b"\r\n"), Some(ParseError::BadRequest));
+        assert_eq!(
+            error(b"+5\r\nhello\r\n0\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        // 只有 \n 的行尾
+        assert_eq!(
+            error(b"5\nhello\r\n0\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        // 数据比声明的长
+        assert_eq!(
+            error(b"3\r\nhello\r\n0\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        assert_eq!(
+            error(b"5;=x\r\nhello\r\n0\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        assert_eq!(
+            error(b"5;a=\"x\r\nhello\r\n0\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        assert_eq!(
+            error(b"0\r\nBad Name: x\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        assert_eq!(
+            error(b"0\r\nX: 1\r\n folded\r\n\r\n"),
+            Some(ParseError::BadRequest)
+        );
+        // 超过上限
+        assert_eq!(error(b"41\r\n"), Some(ParseError::PayloadTooLarge));
+        assert_eq!(
+            error(b"ffffffffffffffffff\r\n"),
+            Some(ParseError::PayloadTooLarge)
+        );
+        let trailer = format!("0\r\nX: {}\r\n\r\n", "a".repeat(80));
+        assert_eq!(error(trailer.as_bytes()), Some(ParseError::HeadersTooLarge));
+    }
+
+    /// 一次只追加一个字节，和连接上数据陆续到达的情况相同
+    fn decode_incrementally(raw: &[u8]) -> Result<Decoded, ParseError> {
+        let limits = Limits::new().body(64).header_bytes(64);
+        let mut decoder = ChunkedDecoder::new(&limits);
+        let mut consumed = 0;
+        for end in 1..=raw.len() {
+            consumed += decoder.feed(&raw[consumed..end])?;
+            if decoder.is_done() {
+                let (body, trailers) = decoder.into_parts();
+                return Ok((body, trailers, consumed));
+            }
+        }
+        Err(ParseError::BadRequest)
+    }
+
+    #[test]
+    fn test_decoder_matches_reader_when_fed_byte_by_byte() {
+        let raw = b"5;name=value;flag\r\nhello\r\n6 ; q=\"a;b\"\r\n world\r\n0\r\nExpires: never\r\nX-Checksum:  abc \r\n\r\nGET /next";
+        assert_eq!(decode_incrementally(raw).ok(), decode(raw).ok());
+
+        let trailer = format!("0\r\nX: {}\r\n\r\n", "a".repeat(80));
+        for raw in [
+            &b"zz\r\nhello\r\n0\r\n\r\n"[..],
+            b"5\nhello\r\n0\r\n\r\n",
+            b"3\r\nhello\r\n0\r\n\r\n",
+            b"0\r\nBad Name: x\r\n\r\n",
+            b"41\r\n",
+            b"ffffffffffffffffff\r\n",
+            trailer.as_bytes(),
+        ] {
+            assert_eq!(decode_incrementally(raw).err(), error(raw), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_incomplete_body_is_unexpected_eof() {
+        for raw in [
+            &b"5\r\nhel"[..],
+            b"5\r\nhello",
+            b"5\r\nhello\r\n0\r\n",
+            b"5",
+        ] {
+            let e = decode(raw).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
@@ -1,26 +1,51 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum HttpMethod {
     GET,
+    HEAD,
     POST,
+    PUT,
+    DELETE,
+    PATCH,
     UNINITIALIZED,
 }
 
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::UNINITIALIZED => "UNINITIALIZED",
+        }
+    }
+}
+
 impl From<&str> for HttpMethod {
     fn from(method: &str) -> Self {
         match method {
             "GET" => HttpMethod::GET,
+            "HEAD" => HttpMethod::HEAD,
             "POST" => HttpMethod::POST,
+            "PUT" => HttpMethod::PUT,
+            "DELETE" => HttpMethod::DELETE,
+            "PATCH" => HttpMethod::PATCH,
             _ => HttpMethod::UNINITIALIZED,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum HttpVersion {
     HTTP10,
     HTTP11,
+    /// 由 HTTP/2 连接上的请求转换而来
+    HTTP20,
     UNINITIALIZED,
 }
 
@@ -29,6 +54,7 @@ impl From<&str> for HttpVersion {
         match version {
             "HTTP/1.0" => HttpVersion::HTTP10,
             "HTTP/1.1" => HttpVersion::HTTP11,
+            "HTTP/2" => HttpVersion::HTTP20,
             _ => HttpVersion::UNINITIALIZED,
         }
     }
@@ -39,33 +65,161 @@ impl From<&HttpVersion> for String {
         match version {
             HttpVersion::HTTP10 => "HTTP/1.0".to_string(),
             HttpVersion::HTTP11 => "HTTP/1.1".to_string(),
+            HttpVersion::HTTP20 => "HTTP/2".to_string(),
             HttpVersion::UNINITIALIZED => "UNINITIALIZED".to_string(),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum HttpResource {
     PATH(String),
     UNINITIALIZED,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct HttpRequest {
     method: HttpMethod,
     resource: HttpResource,
     version: HttpVersion,
     headers: HashMap<String, String>,
-    body: String,
+    body: Vec<u8>,
+    params: HashMap<String, String>,
+    trailers: HashMap<String, String>,
+    mount_prefix: String,
 }
 
 impl HttpRequest {
+    /// 由已经拆分好的各部分直接构造请求，不经过文本解析，请求体原样保留
+    pub fn new(
+        method: HttpMethod,
+        path: &str,
+        version: HttpVersion,
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    ) -> Self {
+        HttpRequest {
+            method,
+            resource: HttpResource::PATH(path.to_string()),
+            version,
+            headers,
+            body,
+            params: HashMap::new(),
+            trailers: HashMap::new(),
+            mount_prefix: String::new(),
+        }
+    }
+
+    pub fn method(&self) -> &HttpMethod {
+        &self.method
+    }
+
+    pub fn version(&self) -> &HttpVersion {
+        &self.version
+    }
+
     pub fn resource_path(&self) -> &str {
         match &self.resource {
             HttpResource::PATH(path) => path,
             HttpResource::UNINITIALIZED => "",
         }
     }
+
+    /// 替换请求路径，路由挂载时用于去掉前缀
+    pub fn set_resource_path(&mut self, path: String) {
+        self.resource = HttpResource::PATH(path);
+    }
+
+    /// 路由挂载时去掉的前缀，多层挂载时依次累加，未经过挂载时为空
+    pub fn mount_prefix(&self) -> &str {
+        &self.mount_prefix
+    }
+
+    /// 进入挂载点时追加去掉的前缀，`prefix` 不以 `/` 结尾
+    pub fn push_mount_prefix(&mut self, prefix: &str) {
+        self.mount_prefix.push_str(prefix);
+    }
+
+    /// 去掉挂载前缀之前、客户端请求的原始路径（不带查询参数）
+    pub fn original_path(&self) -> String {
+        match (self.mount_prefix.is_empty(), self.path()) {
+            (true, path) => path.to_string(),
+            (false, "/") => self.mount_prefix.clone(),
+            (false, path) => format!("{}{path}", self.mount_prefix),
+        }
+    }
+
+    /// 不带查询参数的路径
+    pub fn path(&self) -> &str {
+        let path = self.resource_path();
+        path.split('?').next().unwrap_or(path)
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.resource_path().split_once('?').map(|(_, query)| query)
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// 按名称查找请求头，忽略大小写
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 设置请求头，替换已有的同名请求头（忽略大小写）
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case(key));
+        self.headers.insert(key.to_string(), value.to_string());
+    }
+
+    /// 请求体的文本形式，不是合法 UTF-8 的字节会被替换为 U+FFFD
+    pub fn body(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+
+    /// 请求体的原始字节，上传二进制内容时使用
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    /// chunked 请求体之后的 trailer 字段，不会合并到请求头中
+    pub fn trailers(&self) -> &HashMap<String, String> {
+        &self.trailers
+    }
+
+    /// 按名称查找 trailer 字段，忽略大小写
+    pub fn trailer(&self, name: &str) -> Option<&str> {
+        self.trailers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_trailers(&mut self, trailers: HashMap<String, String>) {
+        self.trailers = trailers;
+    }
+
+    /// 路由匹配出的路径参数，例如 `/api/users/:id` 中的 `id`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|value| value.as_str())
+    }
+
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+
+    pub fn set_param(&mut self, name: &str, value: &str) {
+        self.params.insert(name.to_string(), value.to_string());
+    }
 }
 
 impl From<String> for HttpRequest {
@@ -102,7 +256,10 @@ impl From<String> for HttpRequest {
             resource: parsed_resource,
             version: parsed_version,
             headers,
-            body,
+            body: body.into_bytes(),
+            params: HashMap::new(),
+            trailers: HashMap::new(),
+            mount_prefix: String::new(),
         }
     }
 }
@@ -141,6 +298,8 @@ mod tests {
         assert_eq!(HttpMethod::from("GET"), HttpMethod::GET);
         let method: HttpMethod = "POST".into();
         assert_eq!(method, HttpMethod::POST);
+        assert_eq!(HttpMethod::from("DELETE"), HttpMethod::DELETE);
+        assert_eq!(HttpMethod::PUT.as_str(), "PUT");
         let method: HttpMethod = "OPTIONS".into();
         assert_eq!(method, HttpMethod::UNINITIALIZED);
     }
@@ -151,6 +310,7 @@ mod tests {
         assert_eq!(version, HttpVersion::HTTP10);
 
         assert_eq!(HttpVersion::from("HTTP/1.1"), HttpVersion::HTTP11);
+        assert_eq!(HttpVersion::from("HTTP/2"), HttpVersion::HTTP20);
         let version: HttpVersion = "HTTP/2.0".into();
         assert_eq!(version, HttpVersion::UNINITIALIZED);
     }
@@ -166,6 +326,29 @@ mod tests {
             Some(&"localhost:8080".to_string())
         );
         assert_eq!(request.headers.len(), 2);
-        assert_eq!(request.body, "Hello, world!\nEnd Line.");
+        assert_eq!(request.body(), "Hello, world!\nEnd Line.");
+    }
+
+    #[test]
+    fn test_http_request_accessors() {
+        let mut request: HttpRequest =
+            "GET /api/users/1?page=2 HTTP/1.1\r\ncontent-type: text/plain\r\n\r\n"
+                .to_string()
+                .into();
+        assert_eq!(request.path(), "/api/users/1");
+        assert_eq!(request.query(), Some("page=2"));
+        assert_eq!(request.header("Content-Type"), Some("text/plain"));
+        assert_eq!(request.param("id"), None);
+        request.set_param("id", "1");
+        assert_eq!(request.param("id"), Some("1"));
+
+        assert_eq!(request.original_path(), "/api/users/1");
+        request.set_resource_path("/1?page=2".to_string());
+        request.push_mount_prefix("/api");
+        request.push_mount_prefix("/users");
+        assert_eq!(request.mount_prefix(), "/api/users");
+        assert_eq!(request.original_path(), "/api/users/1");
+        request.set_resource_path("/".to_string());
+        assert_eq!(request.original_path(), "/api/users");
     }
 }
@@ -1,14 +1,109 @@
 use crate::httprequest::HttpVersion;
-use std::{collections::HashMap, fmt::Debug, io::Write};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    io::{self, Read, Write},
+    time::Duration,
+};
 
-#[derive(Debug, PartialEq, Clone)]
+/// 发送流式响应体时每次读取的最大字节数，也是每个 chunk 的最大长度
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+pub type Trailers = Box<dyn FnOnce() -> HashMap<String, String> + Send>;
+
+/// 流式响应体，发送响应时才逐块读取，不需要预先知道长度
+pub struct BodyStream {
+    reader: Box<dyn Read + Send>,
+    /// 响应体发送完后才计算的 trailer，例如校验和
+    trailers: Option<Trailers>,
+}
+
+impl BodyStream {
+    /// 拆分为响应体读取器和 trailer，用于不通过 [`HttpResponse::send_response`] 发送的协议
+    pub fn into_parts(self) -> (Box<dyn Read + Send>, Option<Trailers>) {
+        (self.reader, self.trailers)
+    }
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("trailers", &self.trailers.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// 协议升级后交给回调的连接，读写的是升级后的协议数据
+pub trait Upgraded: Read + Write + Send {
+    /// 设置读超时，`None` 表示一直阻塞等待
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// 关闭连接，之后的读写都会失败
+    fn close(&mut self);
+}
+
+/// 发送 `101 Switching Protocols` 之后接管连接的回调
+pub struct OnUpgrade(Box<dyn FnOnce(Box<dyn Upgraded>) + Send>);
+
+impl OnUpgrade {
+    /// 把连接交给回调，回调返回后连接被关闭
+    pub fn run(self, connection: Box<dyn Upgraded>) {
+        (self.0)(connection)
+    }
+}
+
+impl Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnUpgrade").finish_non_exhaustive()
+    }
+}
+
+/// 把逐块产生数据的迭代器适配成 [`Read`]
+struct ChunkIter<I> {
+    chunks: I,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl<I, B> Read for ChunkIter<I>
+where
+    I: Iterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // 跳过空的数据块，空 chunk 在 chunked 编码中表示结束
+        while self.position == self.current.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current.clear();
+                    self.current.extend_from_slice(chunk.as_ref());
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len() - self.position);
+        buf[..n].copy_from_slice(&self.current[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[derive(Debug)]
 pub struct HttpResponse<'a> {
     version: &'a HttpVersion,
     status_code: &'a str,
     status_text: &'a str,
-    headers: Option<HashMap<&'a str, &'a str>>,
+    headers: Option<HashMap<String, String>>,
     body: Option<String>,
     binary_body: Option<Vec<u8>>,
+    stream: Option<BodyStream>,
+    /// 流式响应体是否使用 chunked 编码，否则通过关闭连接表示响应结束
+    chunked: bool,
+    /// 协议升级响应发送后接管连接的回调
+    upgrade: Option<OnUpgrade>,
+    /// 只写出响应头，用于 HEAD 请求
+    omit_body: bool,
 }
 
 impl<'a> Default for HttpResponse<'a> {
@@ -20,6 +115,10 @@ impl<'a> Default for HttpResponse<'a> {
             headers: None,
             body: None,
             binary_body: None,
+            stream: None,
+            chunked: true,
+            upgrade: None,
+            omit_body: false,
         }
     }
 }
@@ -35,23 +134,17 @@ impl<'a> HttpResponse<'a> {
             response.status_code = status_code;
         }
 
-        response.headers = match &headers {
-            Some(_) => headers,
+        response.headers = match headers {
+            Some(headers) => Some(owned_headers(headers)),
             None => {
                 let mut h = HashMap::new();
-                h.insert("Content-Type", "text/html");
+                h.insert("Content-Type".to_string(), "text/html".to_string());
                 Some(h)
             }
         };
 
         response.body = body;
-
-        match status_code {
-            "200" => response.status_text = "OK",
-            "404" => response.status_text = "Not Found",
-            "500" => response.status_text = "Internal Server Error",
-            _ => response.status_text = "Not Found",
-        }
+        response.status_text = status_text(status_code);
         response
     }
 
@@ -65,24 +158,170 @@ impl<'a> HttpResponse<'a> {
             response.status_code = status_code;
         }
 
-        response.headers = headers;
+        response.headers = headers.map(owned_headers);
         response.binary_body = binary_body;
+        response.status_text = status_text(status_code);
+        response
+    }
 
-        match status_code {
-            "200" => response.status_text = "OK",
-            "404" => response.status_text = "Not Found",
-            "500" => response.status_text = "Internal Server Error",
-            _ => response.status_text = "Not Found",
-        }
+    /// 响应体从 `reader` 中逐块读取，HTTP/1.1 下使用 chunked 编码发送
+    ///
+    /// 适合边生成边发送的大响应（打包文件、日志、报表等），不需要预先知道长度。
+    pub fn streaming(
+        status_code: &'a str,
+        headers: Option<HashMap<&'a str, &'a str>>,
+        reader: impl Read + Send + 'static,
+    ) -> HttpResponse<'a> {
+        let mut response = HttpResponse::new_binary(status_code, headers, None);
+        response.stream = Some(BodyStream {
+            reader: Box::new(reader),
+            trailers: None,
+        });
         response
     }
 
+    /// 响应体由迭代器逐块产生，每次迭代得到的数据会尽快发送给客户端
+    pub fn from_chunks<I, B>(
+        status_code: &'a str,
+        headers: Option<HashMap<&'a str, &'a str>>,
+        chunks: I,
+    ) -> HttpResponse<'a>
+    where
+        I: IntoIterator<Item = B>,
+        I::IntoIter: Send + 'static,
+        B: AsRef<[u8]>,
+    {
+        let reader = ChunkIter {
+            chunks: chunks.into_iter(),
+            current: Vec::new(),
+            position: 0,
+        };
+        HttpResponse::streaming(status_code, headers, reader)
+    }
+
+    /// `101 Switching Protocols` 响应，服务器发送后把连接交给 `on_upgrade`，不再按 HTTP 处理
+    ///
+    /// 响应头中需要带上 `Upgrade`，`Connection` 由服务器设置。
+    pub fn switching_protocols(
+        headers: Option<HashMap<&'a str, &'a str>>,
+        on_upgrade: impl FnOnce(Box<dyn Upgraded>) + Send + 'static,
+    ) -> HttpResponse<'a> {
+        HttpResponse::upgrade("101", headers, on_upgrade)
+    }
+
+    /// 只发送响应头，之后把连接交给 `on_upgrade`，由它直接写出响应体
+    ///
+    /// 响应体没有长度也不使用 chunked 编码，以关闭连接表示结束，适合 Server-Sent Events 这类长连接。
+    pub fn upgrade(
+        status_code: &'a str,
+        headers: Option<HashMap<&'a str, &'a str>>,
+        on_upgrade: impl FnOnce(Box<dyn Upgraded>) + Send + 'static,
+    ) -> HttpResponse<'a> {
+        // 空的流式响应体：发送时只写出响应头，不带 Content-Length
+        let mut response = HttpResponse::streaming(status_code, headers, io::empty());
+        response.set_chunked(false);
+        response.upgrade = Some(OnUpgrade(Box::new(on_upgrade)));
+        response
+    }
+
+    /// 是否是带有升级回调的协议升级响应
+    pub fn is_upgrade(&self) -> bool {
+        self.upgrade.is_some()
+    }
+
+    /// 取出协议升级回调
+    pub fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        self.upgrade.take()
+    }
+
+    /// 是否是流式响应体
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// 流式响应体发送完后附加的 trailer，只在使用 chunked 编码时发送
+    pub fn set_trailers(
+        &mut self,
+        trailers: impl FnOnce() -> HashMap<String, String> + Send + 'static,
+    ) {
+        if let Some(stream) = self.stream.as_mut() {
+            stream.trailers = Some(Box::new(trailers));
+        }
+    }
+
+    /// 流式响应体是否使用 chunked 编码，默认为 `true`
+    ///
+    /// HTTP/1.0 客户端不支持 chunked 编码，此时直接发送原始数据，发送完后必须关闭连接。
+    pub fn set_chunked(&mut self, chunked: bool) {
+        self.chunked = chunked;
+    }
+
+    /// 发送时只写出响应头，`Content-Length` 仍然是响应体的长度；服务器对 HEAD 请求设置
+    pub fn set_omit_body(&mut self, omit_body: bool) {
+        self.omit_body = omit_body;
+    }
+
+    /// 设置响应头，已存在的同名响应头（忽略大小写）会被替换
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        let headers = self.headers.get_or_insert_with(HashMap::new);
+        headers.retain(|k, _| !k.eq_ignore_ascii_case(key));
+        headers.insert(key.to_string(), value.to_string());
+    }
+
+    /// 删除响应头，忽略大小写
+    pub fn remove_header(&mut self, key: &str) {
+        if let Some(headers) = self.headers.as_mut() {
+            headers.retain(|k, _| !k.eq_ignore_ascii_case(key));
+        }
+    }
+
+    /// 所有响应头的名称和值
+    pub fn header_fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// 取出流式响应体，之后响应体为空
+    pub fn take_stream(&mut self) -> Option<BodyStream> {
+        self.stream.take()
+    }
+
+    /// 按名称查找响应头，忽略大小写
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.as_ref().and_then(|map| {
+            map.iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        })
+    }
+
     pub fn send_response(mut self, stream: &mut impl Write) -> Result<(), std::io::Error> {
-        if let Some(binary_body) = self.binary_body.take() {
+        if self.status_code.starts_with('1') {
+            // 1xx 响应没有响应体，也不能带 Content-Length
+            let head = format!(
+                "{} {} {}\r\n{}\r\n",
+                self.version(),
+                self.status_code(),
+                self.status_text(),
+                self.headers()
+            );
+            stream.write_all(head.as_bytes())?;
+            stream.flush()?;
+        } else if let Some(body) = self.stream.take() {
+            self.send_stream(body, stream)?;
+        } else if let Some(binary_body) = self.binary_body.take() {
             // 发送二进制响应
             let response_string = self.to_binary_response_string(&binary_body);
             stream.write_all(response_string.as_bytes())?;
-            stream.write_all(&binary_body)?;
+            if !self.omit_body {
+                stream.write_all(&binary_body)?;
+            }
+            stream.flush()?;
+        } else if self.omit_body {
+            let head = self.to_binary_response_string(self.body().as_bytes());
+            stream.write_all(head.as_bytes())?;
             stream.flush()?;
         } else {
             // 发送文本响应
@@ -93,6 +332,74 @@ impl<'a> HttpResponse<'a> {
         Ok(())
     }
 
+    /// 边读取边发送流式响应体，每个数据块都会立即 flush
+    fn send_stream(&self, body: BodyStream, stream: &mut impl Write) -> io::Result<()> {
+        let mut head = format!(
+            "{} {} {}\r\n",
+            self.version(),
+            self.status_code(),
+            self.status_text()
+        );
+        if let Some(map) = &self.headers {
+            for (key, value) in map.iter() {
+                // 长度由传输方式决定，忽略处理器设置的值
+                if key.eq_ignore_ascii_case("content-length")
+                    || key.eq_ignore_ascii_case("transfer-encoding")
+                {
+                    continue;
+                }
+                head.push_str(&format!("{key}: {value}\r\n"));
+            }
+        }
+        if self.chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.flush()?;
+        if self.omit_body {
+            return Ok(());
+        }
+
+        let BodyStream {
+            mut reader,
+            trailers,
+        } = body;
+        let mut buffer = vec![0u8; STREAM_CHUNK_SIZE];
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK_SIZE + 16);
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if self.chunked {
+                // 大小行、数据和结尾的 \r\n 一次写出
+                chunk.clear();
+                chunk.extend_from_slice(format!("{n:x}\r\n").as_bytes());
+                chunk.extend_from_slice(&buffer[..n]);
+                chunk.extend_from_slice(b"\r\n");
+                stream.write_all(&chunk)?;
+            } else {
+                stream.write_all(&buffer[..n])?;
+            }
+            stream.flush()?;
+        }
+
+        if self.chunked {
+            let mut end = String::from("0\r\n");
+            if let Some(trailers) = trailers {
+                for (key, value) in trailers() {
+                    end.push_str(&format!("{key}: {value}\r\n"));
+                }
+            }
+            end.push_str("\r\n");
+            stream.write_all(end.as_bytes())?;
+        }
+        stream.flush()
+    }
+
     fn to_binary_response_string(&self, binary_body: &[u8]) -> String {
         let mut headers = String::new();
         if let Some(map) = &self.headers {
@@ -142,11 +449,68 @@ impl<'a> HttpResponse<'a> {
     pub fn binary_body(&self) -> Option<&[u8]> {
         self.binary_body.as_deref()
     }
+
+    /// 响应体的字节内容，不区分文本和二进制
+    pub fn body_bytes(&self) -> &[u8] {
+        match &self.binary_body {
+            Some(binary_body) => binary_body,
+            None => self.body().as_bytes(),
+        }
+    }
+
+    pub fn set_body(&mut self, body: Option<String>) {
+        self.body = body;
+        self.binary_body = None;
+        self.stream = None;
+    }
+
+    pub fn set_binary_body(&mut self, binary_body: Option<Vec<u8>>) {
+        self.binary_body = binary_body;
+        self.body = None;
+        self.stream = None;
+    }
+}
+
+fn owned_headers(headers: HashMap<&str, &str>) -> HashMap<String, String> {
+    headers
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// 状态码对应的原因短语
+pub fn status_text(status_code: &str) -> &'static str {
+    match status_code {
+        "100" => "Continue",
+        "101" => "Switching Protocols",
+        "200" => "OK",
+        "201" => "Created",
+        "204" => "No Content",
+        "301" => "Moved Permanently",
+        "302" => "Found",
+        "304" => "Not Modified",
+        "307" => "Temporary Redirect",
+        "308" => "Permanent Redirect",
+        "400" => "Bad Request",
+        "401" => "Unauthorized",
+        "403" => "Forbidden",
+        "404" => "Not Found",
+        "405" => "Method Not Allowed",
+        "408" => "Request Timeout",
+        "413" => "Content Too Large",
+        "414" => "URI Too Long",
+        "417" => "Expectation Failed",
+        "426" => "Upgrade Required",
+        "431" => "Request Header Fields Too Large",
+        "500" => "Internal Server Error",
+        "501" => "Not Implemented",
+        "503" => "Service Unavailable",
+        _ => "Not Found",
+    }
 }
 
 impl<'a> From<HttpResponse<'a>> for String {
     fn from(response: HttpResponse<'a>) -> String {
-        let response = response.clone();
         format!(
             "{} {} {}\r\n{}Content-Length: {}\r\n\r\n{}",
             &response.version(),
@@ -167,7 +531,7 @@ mod tests {
     #[test]
     fn test_http_response_into_string() {
         let mut headers = HashMap::new();
-        headers.insert("Content-Type", "text/html");
+        headers.insert("Content-Type".to_string(), "text/html".to_string());
         let response = HttpResponse {
             version: &HttpVersion::HTTP11,
             status_code: "200",
@@ -175,6 +539,10 @@ mod tests {
             headers: Some(headers),
             body: Some("Hello, world!".to_string()),
             binary_body: None,
+            stream: None,
+            chunked: true,
+            upgrade: None,
+            omit_body: false,
         };
         let response_string: String = response.into();
         assert_eq!(
@@ -222,4 +590,86 @@ mod tests {
             "HTTP/1.1 500 Internal Server Error\r\nContent-Type: text/html\r\nContent-Length: 21\r\n\r\nInternal Server Error"
         );
     }
+
+    #[test]
+    fn test_set_header_replaces_case_insensitively() {
+        let mut response = HttpResponse::new("405", None, None);
+        response.set_header("content-type", "text/plain");
+        response.set_header("Allow", "GET, POST");
+        assert_eq!(response.status_text(), "Method Not Allowed");
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.header("allow"), Some("GET, POST"));
+    }
+
+    fn sent(response: HttpResponse) -> String {
+        let mut bytes = Vec::new();
+        response.send_response(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_streaming_response_uses_chunked_encoding() {
+        let mut response = HttpResponse::from_chunks("200", None, vec!["hello", "", " world"]);
+        response.set_header("Content-Length", "99");
+        response.set_trailers(|| HashMap::from([("Checksum".to_string(), "abc".to_string())]));
+        assert!(response.is_streaming());
+        assert_eq!(
+            sent(response),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n6\r\n world\r\n0\r\nChecksum: abc\r\n\r\n"
+        );
+
+        // HTTP/1.0 客户端直接发送原始数据，不发送 trailer
+        let mut response = HttpResponse::streaming("200", None, &b"raw body"[..]);
+        response.set_chunked(false);
+        response.set_trailers(HashMap::new);
+        assert_eq!(sent(response), "HTTP/1.1 200 OK\r\n\r\nraw body");
+    }
+
+    #[test]
+    fn test_omit_body_keeps_content_length() {
+        let mut response =
+            HttpResponse::new("200", Some(HashMap::new()), Some("hello".to_string()));
+        response.set_omit_body(true);
+        assert_eq!(
+            sent(response),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"
+        );
+
+        let mut response = HttpResponse::new_binary("200", None, Some(vec![0u8; 3]));
+        response.set_omit_body(true);
+        assert_eq!(
+            sent(response),
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n"
+        );
+
+        let mut response = HttpResponse::from_chunks("200", None, ["hello"]);
+        response.set_omit_body(true);
+        assert_eq!(
+            sent(response),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_switching_protocols_has_no_content_length() {
+        let mut response = HttpResponse::switching_protocols(
+            Some(HashMap::from([("Upgrade", "websocket")])),
+            |_| {},
+        );
+        assert!(response.is_upgrade());
+        assert!(response.take_upgrade().is_some());
+        assert_eq!(
+            sent(response),
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n"
+        );
+
+        let headers = HashMap::from([("Content-Type", "text/event-stream")]);
+        let response = HttpResponse::upgrade("200", Some(headers), |_| {});
+        assert!(response.is_upgrade());
+        assert_eq!(
+            sent(response),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n"
+        );
+    }
 }
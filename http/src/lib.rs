@@ -1,2 +1,4 @@
+pub mod chunked;
 pub mod httprequest;
 pub mod httpresponse;
+pub mod parser;
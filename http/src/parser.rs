@@ -0,0 +1,563 @@
+use crate::chunked::ChunkedDecoder;
+use crate::httprequest::HttpRequest;
+use std::fmt;
+use std::io;
+
+/// 请求各部分的大小上限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    max_request_line: usize,
+    max_headers: usize,
+    max_header_bytes: usize,
+    max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_header_bytes: 64 * 1024,
+            max_body: 8 * 1024 * 1024,
+        }
+    }
+
+    /// 请求行的最大字节数，超过返回 `414`
+    pub fn request_line(mut self, max_request_line: usize) -> Self {
+        self.max_request_line = max_request_line;
+        self
+    }
+
+    /// 请求头的最大数量，超过返回 `431`
+    pub fn headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// 所有请求头（不含请求行）加起来的最大字节数，超过返回 `431`
+    pub fn header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    /// 请求体（chunked 编码时为解码后）的最大字节数，超过返回 `413`
+    pub fn body(mut self, max_body: usize) -> Self {
+        self.max_body = max_body;
+        self
+    }
+
+    pub fn max_request_line(&self) -> usize {
+        self.max_request_line
+    }
+
+    pub fn max_headers(&self) -> usize {
+        self.max_headers
+    }
+
+    pub fn max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+
+    pub fn max_body(&self) -> usize {
+        self.max_body
+    }
+
+    /// 一个合法请求最多占用的字节数，用于限制连接的读缓冲区
+    pub fn max_request_size(&self) -> usize {
+        // chunked 编码的分块头和结尾也要占用一些空间，这里留出余量
+        self.max_request_line
+            .saturating_add(self.max_header_bytes)
+            .saturating_add(self.max_body.saturating_mul(2))
+            .saturating_add(4)
+    }
+}
+
+/// 请求不符合要求，需要直接返回错误响应并关闭连接
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// 请求体的长度无法确定或 chunked 编码格式错误
+    BadRequest,
+    /// 请求行过长
+    UriTooLong,
+    /// 请求头数量过多或总长度过长
+    HeadersTooLarge,
+    /// 请求体过大
+    PayloadTooLarge,
+}
+
+impl ParseError {
+    /// 对应的响应状态码
+    pub fn status_code(&self) -> &'static str {
+        match self {
+            ParseError::BadRequest => "400",
+            ParseError::UriTooLong => "414",
+            ParseError::HeadersTooLarge => "431",
+            ParseError::PayloadTooLarge => "413",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::BadRequest => write!(f, "malformed request framing"),
+            ParseError::UriTooLong => write!(f, "request line too long"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::PayloadTooLarge => write!(f, "request body too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// 作为读取错误返回，调用方可以通过 `get_ref` 取回原因
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// 请求体的长度由哪种方式确定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// 没有请求体
+    Empty,
+    /// `Content-Length` 指定的长度
+    Length(usize),
+    /// `Transfer-Encoding: chunked`
+    Chunked,
+}
+
+/// 按 RFC 9112 第 6.3 节根据请求头确定请求体的长度
+///
+/// 同时出现 `Content-Length` 和 `Transfer-Encoding`、`chunked` 不是最后一个传输编码、
+/// 多个 `Content-Length` 不一致或不是数字时都返回 [`ParseError::BadRequest`]，
+/// 避免前置代理和服务器对请求边界的理解不一致（request smuggling）。
+pub fn framing<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    limits: &Limits,
+) -> Result<Framing, ParseError> {
+    let mut content_length: Option<&str> = None;
+    let mut codings = Vec::new();
+    for (key, value) in headers {
+        let key = key.trim();
+        if key.eq_ignore_ascii_case("content-length") {
+            // 同一个值可能重复出现，例如 `Content-Length: 5, 5`
+            for value in value.split(',').map(str::trim) {
+                if content_length.is_some_and(|length| length != value) {
+                    return Err(ParseError::BadRequest);
+                }
+                content_length = Some(value);
+            }
+        } else if key.eq_ignore_ascii_case("transfer-encoding") {
+            codings.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|coding| !coding.is_empty()),
+            );
+        }
+    }
+
+    if !codings.is_empty() {
+        // 只支持 chunked 一种传输编码
+        if content_length.is_some()
+            || codings.len() > 1
+            || !codings[0].eq_ignore_ascii_case("chunked")
+        {
+            return Err(ParseError::BadRequest);
+        }
+        return Ok(Framing::Chunked);
+    }
+    let Some(length) = content_length else {
+        return Ok(Framing::Empty);
+    };
+    if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::BadRequest);
+    }
+    // 超出 usize 的长度一定超过上限
+    match length.parse::<usize>() {
+        Ok(length) if length <= limits.max_body() => Ok(match length {
+            0 => Framing::Empty,
+            length => Framing::Length(length),
+        }),
+        _ => Err(ParseError::PayloadTooLarge),
+    }
+}
+
+/// 从字节缓冲区中解析请求的结果
+#[derive(Debug, PartialEq)]
+pub enum Parse {
+    /// 缓冲区里还不是一个完整的请求，需要继续读取
+    Incomplete,
+    /// 解析出一个完整的请求，以及它在缓冲区中占用的字节数
+    Complete(Box<HttpRequest>, usize),
+    /// 请求超出限制，不需要再继续读取
+    Error(ParseError),
+}
+
+/// 从缓冲区开头解析一个完整的请求，不消耗缓冲区
+///
+/// 每次都从头解析，适合一次性拿到完整数据的场景；
+/// 非阻塞连接上逐步读到数据时使用 [`RequestParser`]，避免重复扫描已经处理过的字节。
+pub fn parse_request(buffer: &[u8], limits: &Limits) -> Parse {
+    RequestParser::new(*limits).parse(buffer)
+}
+
+/// 增量解析同一连接上的请求
+///
+/// 用于非阻塞连接：每次读到新数据后用连接的完整读缓冲区调用 [`parse`](Self::parse)，
+/// 解析器记住请求头结束的位置、请求体的长度方式和 chunked 请求体的解码进度，
+/// 只处理上次之后新增的字节。返回 `Complete` 时解析器回到初始状态，
+/// 由调用方移除已消耗的字节，剩余的字节属于同一连接上的下一个（pipelining）请求。
+#[derive(Debug)]
+pub struct RequestParser {
+    limits: Limits,
+    /// 已经查找过请求行和请求头结束位置的字节数
+    scanned: usize,
+    line_end: Option<usize>,
+    head: Option<Head>,
+}
+
+#[derive(Debug)]
+struct Head {
+    request: HttpRequest,
+    body_start: usize,
+    body: Body,
+}
+
+#[derive(Debug)]
+enum Body {
+    Empty,
+    Length(usize),
+    /// 解码器和已经交给它的数据在缓冲区中的结束位置
+    Chunked(ChunkedDecoder, usize),
+}
+
+impl RequestParser {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            scanned: 0,
+            line_end: None,
+            head: None,
+        }
+    }
+
+    /// 请求头已经完整时返回只包含请求头的请求，用于在收到请求体之前处理 `Expect: 100-continue`
+    pub fn head(&self) -> Option<&HttpRequest> {
+        self.head.as_ref().map(|head| &head.request)
+    }
+
+    /// 请求头（包括结尾的空行）的字节数，请求头还没有读完时为 `None`
+    pub fn header_len(&self) -> Option<usize> {
+        self.head.as_ref().map(|head| head.body_start)
+    }
+
+    /// `buffer` 从当前请求的第一个字节开始，两次调用之间只能在末尾追加数据
+    pub fn parse(&mut self, buffer: &[u8]) -> Parse {
+        if self.head.is_none() {
+            match self.parse_head(buffer) {
+                Ok(Some(head)) => self.head = Some(head),
+                Ok(None) => return Parse::Incomplete,
+                Err(e) => return Parse::Error(e),
+            }
+        }
+        let Some(head) = &mut self.head else {
+            return Parse::Incomplete;
+        };
+        let consumed = match &mut head.body {
+            Body::Empty => head.body_start,
+            Body::Length(length) => {
+                let end = head.body_start + *length;
+                if buffer.len() < end {
+                    return Parse::Incomplete;
+                }
+                end
+            }
+            Body::Chunked(decoder, fed) => {
+                match decoder.feed(&buffer[*fed..]) {
+                    Ok(n) => *fed += n,
+                    Err(e) => return Parse::Error(e),
+                }
+                if !decoder.is_done() {
+                    return Parse::Incomplete;
+                }
+                *fed
+            }
+        };
+
+        let Some(Head {
+            mut request,
+            body_start,
+            body,
+        }) = self.head.take()
+        else {
+            return Parse::Incomplete;
+        };
+        self.scanned = 0;
+        self.line_end = None;
+        // 请求体保留原始字节，不经过文本转换
+        match body {
+            Body::Empty => {}
+            Body::Length(_) => request.set_body(buffer[body_start..consumed].to_vec()),
+            Body::Chunked(decoder, _) => {
+                let (body, trailers) = decoder.into_parts();
+                request.set_body(body);
+                request.set_trailers(trailers);
+            }
+        }
+        Parse::Complete(Box::new(request), consumed)
+    }
+
+    /// 请求头完整时解析请求头并确定请求体的长度
+    fn parse_head(&mut self, buffer: &[u8]) -> Result<Option<Head>, ParseError> {
+        let limits = &self.limits;
+        // 分隔符可能跨越两次读取，从上次查找结束位置之前几个字节开始
+        if self.line_end.is_none() {
+            let from = self.scanned.saturating_sub(1);
+            self.line_end = find(&buffer[from..], b"\r\n").map(|index| from + index);
+        }
+        // 请求行还没有读完时也要检查长度
+        if self.line_end.unwrap_or(buffer.len()) > limits.max_request_line {
+            return Err(ParseError::UriTooLong);
+        }
+        let from = self.scanned.saturating_sub(3);
+        let header_end = find(&buffer[from..], b"\r\n\r\n").map(|index| from + index);
+        self.scanned = buffer.len();
+        let Some(line_end) = self.line_end else {
+            return Ok(None);
+        };
+        // 请求头部分从请求行之后开始，到空行之前结束
+        let header_bytes = header_end
+            .unwrap_or(buffer.len())
+            .saturating_sub(line_end + 2);
+        if header_bytes > limits.max_header_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        let Some(header_end) = header_end else {
+            return Ok(None);
+        };
+        let head = String::from_utf8_lossy(&buffer[..header_end]);
+        let body_start = header_end + 4;
+
+        let headers: Vec<_> = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .collect();
+        if headers.len() > limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        let body = match framing(headers, limits)? {
+            Framing::Empty => Body::Empty,
+            Framing::Length(length) => Body::Length(length),
+            Framing::Chunked => Body::Chunked(ChunkedDecoder::new(limits), body_start),
+        };
+
+        let mut head = head.lines().collect::<Vec<_>>().join("\r\n");
+        head.push_str("\r\n\r\n");
+        Ok(Some(Head {
+            request: HttpRequest::from(head),
+            body_start,
+            body,
+        }))
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_waits_for_complete_body() {
+        let limits = Limits::new();
+        let raw = b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel";
+        assert_eq!(parse_request(&raw[..20], &limits), Parse::Incomplete);
+        assert_eq!(parse_request(raw, &limits), Parse::Incomplete);
+
+        let raw = b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        let Parse::Complete(request, consumed) = parse_request(raw, &limits) else {
+            panic!("request should be complete");
+        };
+        assert_eq!(request.path(), "/echo");
+        assert_eq!(request.body(), "hello");
+        // 剩余部分是下一个请求
+        let Parse::Complete(next, rest) = parse_request(&raw[consumed..], &limits) else {
+            panic!("pipelined request should be complete");
+        };
+        assert_eq!(next.path(), "/");
+        assert_eq!(consumed + rest, raw.len());
+    }
+
+    #[test]
+    fn test_parse_chunked_request() {
+        let limits = Limits::new();
+        let raw = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_request(&raw[..raw.len() - 2], &limits),
+            Parse::Incomplete
+        );
+        let Parse::Complete(request, consumed) = parse_request(raw, &limits) else {
+            panic!("request should be complete");
+        };
+        assert_eq!(request.body(), "hello world");
+        assert_eq!(consumed, raw.len());
+
+        let raw = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n0\r\nDigest: abc\r\n\r\n";
+        let Parse::Complete(request, _) = parse_request(raw, &limits) else {
+            panic!("request should be complete");
+        };
+        assert_eq!(request.trailer("digest"), Some("abc"));
+        assert_eq!(
+            parse_request(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n",
+                &limits
+            ),
+            Parse::Error(ParseError::BadRequest)
+        );
+    }
+
+    #[test]
+    fn test_request_parser_resumes_across_reads() {
+        let limits = Limits::new();
+        let raw = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\nDigest: abc\r\n\r\nGET /next HTTP/1.1\r\n\r\n";
+        let mut parser = RequestParser::new(limits);
+        let mut completed = None;
+        // 数据一个字节一个字节地到达，每次都用完整的缓冲区调用
+        for end in 1..=raw.len() {
+            match parser.parse(&raw[..end]) {
+                Parse::Incomplete => {}
+                Parse::Complete(request, consumed) => {
+                    completed = Some((request, consumed, end));
+                    break;
+                }
+                Parse::Error(e) => panic!("unexpected error {e}"),
+            }
+        }
+        let (request, consumed, end) = completed.expect("request should be complete");
+        assert_eq!(request.body(), "hello world");
+        assert_eq!(request.trailer("Digest"), Some("abc"));
+        assert_eq!(consumed, end);
+        assert!(parser.head().is_none());
+        assert_eq!(parser.header_len(), None);
+
+        // 完成后解析器回到初始状态，可以继续解析下一个请求
+        let Parse::Complete(next, _) = parser.parse(&raw[consumed..]) else {
+            panic!("pipelined request should be complete");
+        };
+        assert_eq!(next.path(), "/next");
+
+        let mut parser = RequestParser::new(limits);
+        assert_eq!(
+            parser.parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n"),
+            Parse::Incomplete
+        );
+        assert_eq!(
+            parser.head().and_then(|head| head.header("Expect")),
+            Some("100-continue")
+        );
+        assert_eq!(parser.header_len(), Some(60));
+    }
+
+    #[test]
+    fn test_parse_request_keeps_binary_body() {
+        let limits = Limits::new();
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\n\xff\x00\xfe\x80";
+        let Parse::Complete(request, _) = parse_request(raw, &limits) else {
+            panic!("request should be complete");
+        };
+        assert_eq!(request.body_bytes(), b"\xff\x00\xfe\x80");
+
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n\xff\x00\r\n2\r\n\xfe\x80\r\n0\r\n\r\n";
+        let Parse::Complete(request, _) = parse_request(raw, &limits) else {
+            panic!("request should be complete");
+        };
+        assert_eq!(request.body_bytes(), b"\xff\x00\xfe\x80");
+    }
+
+    #[test]
+    fn test_framing_rejects_ambiguous_lengths() {
+        let limits = Limits::new().body(100);
+        let framing = |headers: &[(&str, &str)]| framing(headers.iter().copied(), &limits);
+        assert_eq!(framing(&[]), Ok(Framing::Empty));
+        assert_eq!(framing(&[("Content-Length", "5")]), Ok(Framing::Length(5)));
+        assert_eq!(
+            framing(&[("Content-Length", "5"), ("content-length", "5, 5")]),
+            Ok(Framing::Length(5))
+        );
+        assert_eq!(
+            framing(&[("Transfer-Encoding", "chunked")]),
+            Ok(Framing::Chunked)
+        );
+
+        let bad = [
+            &[("Content-Length", "5"), ("Transfer-Encoding", "chunked")][..],
+            &[("Content-Length", "5"), ("Content-Length", "6")],
+            &[("Content-Length", "-1")],
+            &[("Content-Length", "+5")],
+            &[("Content-Length", "")],
+            &[("Transfer-Encoding", "gzip")],
+            &[("Transfer-Encoding", "chunked, gzip")],
+            &[
+                ("Transfer-Encoding", "chunked"),
+                ("Transfer-Encoding", "chunked"),
+            ],
+        ];
+        for headers in bad {
+            assert_eq!(framing(headers), Err(ParseError::BadRequest), "{headers:?}");
+        }
+        assert_eq!(
+            framing(&[("Content-Length", "101")]),
+            Err(ParseError::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_parse_request_enforces_limits() {
+        let limits = Limits::new()
+            .request_line(32)
+            .headers(2)
+            .header_bytes(64)
+            .body(16);
+        let error = |raw: &[u8]| match parse_request(raw, &limits) {
+            Parse::Error(e) => Some(e.status_code()),
+            _ => None,
+        };
+
+        // 请求行还没读完就已经超长
+        let long_line = format!("GET /{} HTTP/1.1", "a".repeat(40));
+        assert_eq!(error(long_line.as_bytes()), Some("414"));
+        assert_eq!(
+            error(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"),
+            Some("431")
+        );
+        let big_header = format!("GET / HTTP/1.1\r\nCookie: {}", "c".repeat(80));
+        assert_eq!(error(big_header.as_bytes()), Some("431"));
+        // 只看 Content-Length 就拒绝，不等待请求体
+        assert_eq!(
+            error(b"POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n"),
+            Some("413")
+        );
+        assert_eq!(
+            error(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffff\r\n"),
+            Some("413")
+        );
+        assert_eq!(
+            error(b"POST / HTTP/1.1\r\nContent-Length: 16\r\n\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use http::httpresponse::HttpResponse;
+use httpserver::httpserver::HttpServer;
+use httpserver::route::Router;
+use httpserver::websocket::{Message, WebSocketHandler};
+use log::{LevelFilter, error, info};
+
+/// 连接 `/echo` 并显示收到的消息的测试页面
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<body>
+<input id="text" value="hello"> <button onclick="socket.send(text.value)">send</button>
+<pre id="log"></pre>
+<script>
+const socket = new WebSocket(`ws://${location.host}/echo`);
+socket.onmessage = (event) => log.textContent += event.data + "\n";
+socket.onclose = (event) => log.textContent += `closed ${event.code}\n`;
+</script>
+</body>
+</html>
+"#;
+
+/// WebSocket 回显服务器：`cargo run -p httpserver --example websocket_echo`，
+/// 然后在浏览器中打开 http://127.0.0.1:7879/
+fn main() {
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Info)
+        .init();
+
+    let echo = WebSocketHandler::new(|mut socket| {
+        info!("WebSocket connected: {}", socket.request().resource_path());
+        while let Ok(message) = socket.recv() {
+            if let Message::Text(_) | Message::Binary(_) = message
+                && socket.send(message).is_err()
+            {
+                break;
+            }
+        }
+        info!("WebSocket disconnected");
+    });
+    let router = Router::new().get("/echo", echo).get("/", |_| {
+        let headers = HashMap::from([("Content-Type", "text/html; charset=utf-8")]);
+        HttpResponse::new("200", Some(headers), Some(PAGE.to_string()))
+    });
+
+    let server = HttpServer::new("127.0.0.1", 7879, ".")
+        .router(router)
+        .handle_signals(true);
+    if let Err(e) = server.run() {
+        error!("{e}");
+    }
+}
@@ -0,0 +1,553 @@
+use http::httprequest::HttpRequest;
+use log::error;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 访问日志每一行的格式
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Common Log Format：`%h %l %u %t "%r" %>s %b`
+    Common,
+    /// Combined Log Format，在 Common 之后加上 Referer 和 User-Agent
+    #[default]
+    Combined,
+    /// 每行一个 JSON 对象，包含所有字段和处理耗时
+    Json,
+    /// Apache 风格的格式字符串，支持 `%h %l %u %t %r %m %U %q %H %s %>s %b %B %D %T %{Name}i %%`
+    Custom(String),
+}
+
+/// 访问日志，每个响应发送完后写一行
+///
+/// 写入是同步的，每行写完后立即 flush，输出到文件时多个进程追加写也不会交错。
+pub struct AccessLog {
+    format: LogFormat,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    /// 输出到标准输出
+    pub fn stdout() -> Self {
+        Self::writer(io::stdout())
+    }
+
+    /// 追加写入文件，文件不存在时创建
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::writer(file))
+    }
+
+    /// 输出到任意 writer
+    pub fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            format: LogFormat::default(),
+            output: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// 日志格式，默认为 [`LogFormat::Combined`]
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub(crate) fn log(&self, entry: &AccessEntry) {
+        let mut line = match &self.format {
+            LogFormat::Common => entry.render(COMMON),
+            LogFormat::Combined => entry.render(COMBINED),
+            LogFormat::Json => entry.json(),
+            LogFormat::Custom(format) => entry.render(format),
+        };
+        line.push('\n');
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = output
+            .write_all(line.as_bytes())
+            .and_then(|_| output.flush())
+        {
+            error!("Failed to write access log: {e}");
+        }
+    }
+}
+
+const COMMON: &str = "%h %l %u %t \"%r\" %>s %b";
+const COMBINED: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+/// 一个请求的访问记录，请求读完时创建，响应发送完后补上状态码和字节数
+pub(crate) struct AccessEntry {
+    remote_addr: Option<SocketAddr>,
+    time: SystemTime,
+    start: Instant,
+    method: String,
+    target: String,
+    protocol: String,
+    headers: HashMap<String, String>,
+    status: String,
+    bytes: u64,
+}
+
+impl AccessEntry {
+    pub(crate) fn new(request: &HttpRequest, remote_addr: Option<SocketAddr>) -> Self {
+        Self {
+            remote_addr,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            method: request.method().as_str().to_string(),
+            target: request.resource_path().to_string(),
+            protocol: String::from(request.version()),
+            headers: request.headers().clone(),
+            status: String::new(),
+            bytes: 0,
+        }
+    }
+
+    /// 请求行没有读完就被拒绝（超时、超出限制）的请求，请求中的字段都记为 `-`
+    pub(crate) fn unparsed(remote_addr: Option<SocketAddr>) -> Self {
+        Self {
+            remote_addr,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            method: "-".to_string(),
+            target: "-".to_string(),
+            protocol: "-".to_string(),
+            headers: HashMap::new(),
+            status: String::new(),
+            bytes: 0,
+        }
+    }
+
+    /// 记录响应的状态码和响应体字节数
+    pub(crate) fn finish(&mut self, status: &str, bytes: u64) {
+        self.status = status.to_string();
+        self.bytes = bytes;
+    }
+
+    fn duration(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn remote_host(&self) -> String {
+        self.remote_addr
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    /// 按格式字符串输出，无法识别的指令原样保留
+    fn render(&self, format: &str) -> String {
+        let mut line = String::new();
+        let mut rest = format;
+        while let Some(index) = rest.find('%') {
+            line.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+            // `%>s` 和 `%s` 相同，都是最终的状态码
+            rest = rest.strip_prefix('>').unwrap_or(rest);
+            if let Some(header) = rest.strip_prefix('{')
+                && let Some((name, after)) = header.split_once('}')
+                && let Some(after) = after.strip_prefix('i')
+            {
+                line.push_str(&quoted(self.header(name).unwrap_or("-")));
+                rest = after;
+                continue;
+            }
+            let Some(directive) = rest.chars().next() else {
+                line.push('%');
+                break;
+            };
+            rest = &rest[directive.len_utf8()..];
+            match directive {
+                'h' => line.push_str(&self.remote_host()),
+                'l' | 'u' => line.push('-'),
+                't' => line.push_str(&format!("[{}]", clf_time(self.time))),
+                // 没有解析出请求行时整个 `%r` 只输出一个 `-`
+                'r' if self.method == "-" => line.push('-'),
+                'r' => {
+                    let request_line = format!("{} {} {}", self.method, self.target, self.protocol);
+                    line.push_str(&quoted(&request_line));
+                }
+                'm' => line.push_str(&self.method),
+                'U' => line.push_str(&quoted(self.target.split('?').next().unwrap_or("/"))),
+                'q' => {
+                    if let Some((_, query)) = self.target.split_once('?') {
+                        line.push('?');
+                        line.push_str(&quoted(query));
+                    }
+                }
+                'H' => line.push_str(&self.protocol),
+                's' => line.push_str(&self.status),
+                // CLF 中没有响应体时用 `-` 表示
+                'b' if self.bytes == 0 => line.push('-'),
+                'b' | 'B' => line.push_str(&self.bytes.to_string()),
+                'D' => line.push_str(&self.duration().as_micros().to_string()),
+                'T' => line.push_str(&self.duration().as_secs().to_string()),
+                '%' => line.push('%'),
+                other => {
+                    line.push('%');
+                    line.push(other);
+                }
+            }
+        }
+        line.push_str(rest);
+        line
+    }
+
+    fn json(&self) -> String {
+        let fields = [
+            ("time", json_string(&iso_time(self.time))),
+            ("remote_addr", json_string(&self.remote_host())),
+            ("method", json_string(&self.method)),
+            ("path", json_string(&self.target)),
+            ("protocol", json_string(&self.protocol)),
+            (
+                "status",
+                self.status.parse::<u16>().unwrap_or(0).to_string(),
+            ),
+            ("bytes", self.bytes.to_string()),
+            (
+                "referer",
+                json_string(self.header("Referer").unwrap_or_default()),
+            ),
+            (
+                "user_agent",
+                json_string(self.header("User-Agent").unwrap_or_default()),
+            ),
+            (
+                "duration_ms",
+                format!("{:.3}", self.duration().as_secs_f64() * 1000.0),
+            ),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("\"{key}\":{value}"))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// 日志字段中的引号、反斜杠和控制字符转义，避免伪造日志行
+fn quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// UTC 时间的年、月、日、时、分、秒
+pub(crate) fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = (seconds / 86400) as i64;
+    let rest = seconds % 86400;
+    // 公历日期换算，见 http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+/// `10/Oct/2000:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, hour, minute, second) = utc(time);
+    let month = MONTHS[month as usize - 1];
+    format!("{day:02}/{month}/{year}:{hour:02}:{minute:02}:{second:02} +0000")
+}
+
+/// `2000-10-10T13:55:36Z`
+fn iso_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!("{year}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/// 统计写出的响应体字节数，响应头（第一个空行之前的内容）不计入
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    /// 响应头是否已经写完
+    in_body: bool,
+    /// 上一次写入末尾的几个字节，空行可能跨越两次写入
+    tail: Vec<u8>,
+    body_bytes: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            in_body: false,
+            tail: Vec::new(),
+            body_bytes: 0,
+        }
+    }
+
+    pub(crate) fn body_bytes(&self) -> u64 {
+        self.body_bytes
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn count(&mut self, written: &[u8]) {
+        if self.in_body {
+            self.body_bytes += written.len() as u64;
+            return;
+        }
+        let mut window = std::mem::take(&mut self.tail);
+        window.extend_from_slice(written);
+        match window.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+            Some(index) => {
+                self.in_body = true;
+                self.body_bytes = (window.len() - (index + 4)) as u64;
+            }
+            None => {
+                let keep = window.len().min(3);
+                self.tail = window[window.len() - keep..].to_vec();
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::httpserver::{Engine, HttpServer};
+    use crate::route::Router;
+    use crate::timeout::Timeouts;
+    use http::httpresponse::HttpResponse;
+    use http::parser::Limits;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    /// 多个线程共用的内存输出
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        fn lines(&self) -> Vec<String> {
+            let bytes = self.0.lock().unwrap();
+            String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sample(request: &str) -> AccessEntry {
+        let request: HttpRequest = request.to_string().into();
+        let mut entry = AccessEntry::new(&request, Some("10.0.0.1:5000".parse().unwrap()));
+        entry.time = UNIX_EPOCH + Duration::from_secs(971_185_536);
+        entry.finish("200", 2326);
+        entry
+    }
+
+    #[test]
+    fn test_formats() {
+        let entry = sample(
+            "GET /a.png?x=1 HTTP/1.1\r\nReferer: http://example.com/\r\nUser-Agent: curl/8 \"quoted\"\r\n\r\n",
+        );
+        assert_eq!(
+            entry.render(COMMON),
+            "10.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \"GET /a.png?x=1 HTTP/1.1\" 200 2326"
+        );
+        assert_eq!(
+            entry.render(COMBINED),
+            "10.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \"GET /a.png?x=1 HTTP/1.1\" 200 2326 \
+             \"http://example.com/\" \"curl/8 \\\"quoted\\\"\""
+        );
+        assert_eq!(
+            entry.render("%m %U%q %H %>s %B %{X-Missing}i 100%% %z"),
+            "GET /a.png?x=1 HTTP/1.1 200 2326 - 100% %z"
+        );
+        let json = entry.json();
+        assert!(json.starts_with(
+            "{\"time\":\"2000-10-10T13:45:36Z\",\"remote_addr\":\"10.0.0.1\",\"method\":\"GET\",\
+             \"path\":\"/a.png?x=1\",\"protocol\":\"HTTP/1.1\",\"status\":200,\"bytes\":2326,\
+             \"referer\":\"http://example.com/\",\"user_agent\":\"curl/8 \\\"quoted\\\"\",\"duration_ms\":"
+        ));
+
+        // 没有响应体时 `%b` 输出 `-`，请求中的换行不能伪造新的日志行
+        let mut entry = sample("GET /%0A HTTP/1.1\r\nUser-Agent: a\u{1b}b\r\n\r\n");
+        entry.finish("304", 0);
+        assert_eq!(entry.render("%>s %b %B %{User-Agent}i"), "304 - 0 a\\x1bb");
+        assert_eq!(
+            iso_time(UNIX_EPOCH + Duration::from_secs(951_868_799)),
+            "2000-02-29T23:59:59Z"
+        );
+    }
+
+    #[test]
+    fn test_counting_writer_skips_head() {
+        let mut writer = CountingWriter::new(Vec::new());
+        writer
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r")
+            .unwrap();
+        assert_eq!(writer.body_bytes(), 0);
+        writer.write_all(b"\nhel").unwrap();
+        writer.write_all(b"lo").unwrap();
+        assert_eq!(writer.body_bytes(), 5);
+    }
+
+    #[test]
+    fn test_access_log_on_both_engines() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let buffer = Buffer::default();
+            let router = Router::new()
+                .get("/hello", |_| {
+                    HttpResponse::new("200", None, Some("hello".to_string()))
+                })
+                .get("/report", |_| {
+                    HttpResponse::from_chunks("200", None, ["a", "b"])
+                });
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine)
+                .access_log(AccessLog::writer(buffer.clone()).format(LogFormat::Custom(
+                    "%h \"%r\" %>s %b \"%{User-Agent}i\" %D".to_string(),
+                )));
+            let handle = server.spawn().unwrap();
+            for request in [
+                "GET /hello HTTP/1.1\r\nUser-Agent: test\r\nConnection: close\r\n\r\n",
+                "GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+                "GET /report HTTP/1.1\r\nConnection: close\r\n\r\n",
+            ] {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+            }
+            handle.shutdown();
+
+            let lines = buffer.lines();
+            assert_eq!(lines.len(), 3, "{engine:?}: {lines:?}");
+            assert!(
+                lines[0].starts_with("127.0.0.1 \"GET /hello HTTP/1.1\" 200 5 \"test\" "),
+                "{engine:?}: {}",
+                lines[0]
+            );
+            assert!(lines[1].starts_with("127.0.0.1 \"GET /missing HTTP/1.1\" 404 "));
+            assert!(lines[1].contains(" \"-\" "));
+            // chunked 响应体按实际写出的字节数计算，包括分块的长度行
+            assert!(lines[2].starts_with("127.0.0.1 \"GET /report HTTP/1.1\" 200 17 "));
+            let micros = lines[0].rsplit(' ').next().unwrap();
+            assert!(micros.parse::<u64>().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_rejections_are_logged_on_both_engines() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let buffer = Buffer::default();
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(Router::new())
+                .engine(engine)
+                .limits(Limits::new().request_line(32).body(16))
+                .timeouts(Timeouts::new().header_read(Duration::from_millis(200)))
+                .access_log(
+                    AccessLog::writer(buffer.clone())
+                        .format(LogFormat::Custom("%h \"%r\" %>s".to_string())),
+                );
+            let handle = server.spawn().unwrap();
+            let long_path = "a".repeat(64);
+            for request in [
+                format!("GET /{long_path} HTTP/1.1\r\n\r\n"),
+                "POST /upload HTTP/1.1\r\nContent-Length: 5\r\nExpect: bogus\r\n\r\n".to_string(),
+                "POST /upload HTTP/1.1\r\nContent-Length: 1000\r\n\r\n".to_string(),
+                // 请求头没有发完，等待超时
+                "GET /slow HT".to_string(),
+            ] {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+            }
+            handle.shutdown();
+
+            // 没有解析出来的请求行记为 `-`
+            let lines = buffer.lines();
+            assert_eq!(
+                lines,
+                [
+                    "127.0.0.1 \"-\" 414",
+                    "127.0.0.1 \"POST /upload HTTP/1.1\" 417",
+                    "127.0.0.1 \"-\" 413",
+                    "127.0.0.1 \"-\" 408",
+                ],
+                "{engine:?}"
+            );
+        }
+    }
+}
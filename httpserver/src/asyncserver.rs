@@ -0,0 +1,750 @@
+use crate::accesslog::{AccessEntry, AccessLog, CountingWriter};
+use crate::handler::StaticResourceHandler;
+use crate::http2::{self, PREFACE, Rewind};
+use crate::httpserver::ServerError;
+use crate::route::{
+    CONTINUE, Expectation, Route, Router, check_expectation, is_closed, rejection_response,
+};
+use crate::timeout::{RequestClock, Timeouts, request_timeout_response};
+use crate::tls::{Tls, TlsConfig};
+use crate::upgrade::{self, Bridge};
+use http::httprequest::{HttpMethod, HttpRequest, HttpVersion};
+use http::httpresponse::HttpResponse;
+use http::parser::{Limits, Parse, ParseError, RequestParser};
+use log::{error, info, warn};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+
+/// 异步服务器处理请求的方式
+pub(crate) enum Service {
+    /// 和同步服务器相同的路由器，处理器在 tokio 的阻塞线程池中执行
+    Router(Arc<Router>),
+    /// 使用异步文件 I/O 的静态资源服务
+    Static(StaticResourceHandler),
+}
+
+impl Service {
+    /// 见 [`check_expectation`]
+    pub(crate) fn check_expectation(&self, head: &HttpRequest) -> Expectation {
+        match self {
+            Service::Router(router) => check_expectation(router.as_ref(), head),
+            Service::Static(handler) => check_expectation(handler, head),
+        }
+    }
+
+    pub(crate) async fn handle(self: &Arc<Self>, request: HttpRequest) -> HttpResponse<'static> {
+        match self.as_ref() {
+            Service::Static(handler) => {
+                let method = request.method().as_str();
+                let path = request.resource_path().to_string();
+                let start = Instant::now();
+                let response = handler.handle_request_async(request).await;
+                info!(
+                    "{method} {path} -> {} ({:.3}ms)",
+                    response.status_code(),
+                    start.elapsed().as_secs_f64() * 1000.0
+                );
+                response
+            }
+            Service::Router(router) => {
+                let router = Arc::clone(router);
+                let task = tokio::task::spawn_blocking(move || {
+                    Route::handle_catching_panic(router.as_ref(), request)
+                });
+                match task.await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!("Handler task failed: {e}");
+                        HttpResponse::new("500", None, Some("Internal Server Error".to_string()))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 基于 tokio 的异步 HTTP 服务器，需要启用 `async` feature
+///
+/// 请求解析和响应序列化与同步服务器相同；默认用异步文件 I/O 提供 `work_dir` 下的静态资源，
+/// 通过 [`AsyncHttpServer::router`] 可以使用和同步服务器相同的 [`Router`] 与处理器。
+/// 默认支持 HTTP/2：HTTPS 连接通过 ALPN 协商 `h2`，明文连接支持 prior knowledge 方式的 h2c。
+pub struct AsyncHttpServer<'a> {
+    host: &'a str,
+    port: u16,
+    work_dir: &'a str,
+    router: Option<Arc<Router>>,
+    drain_timeout: Duration,
+    timeouts: Timeouts,
+    limits: Limits,
+    tls: Option<TlsConfig>,
+    http2: bool,
+    access_log: Option<Arc<AccessLog>>,
+}
+
+/// 每个连接共用的配置
+#[derive(Clone)]
+struct ConnectionSettings {
+    timeouts: Timeouts,
+    limits: Limits,
+    tls: Option<TlsAcceptor>,
+    http2: bool,
+    access_log: Option<Arc<AccessLog>>,
+}
+
+/// 一个连接上的访问日志，没有设置访问日志时不做任何事
+#[derive(Clone)]
+pub(crate) struct ConnectionLog {
+    access_log: Option<Arc<AccessLog>>,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl ConnectionLog {
+    /// 请求的访问记录，请求头没有解析出来时请求中的字段记为 `-`
+    pub(crate) fn entry(&self, request: Option<&HttpRequest>) -> Option<AccessEntry> {
+        self.access_log.as_ref()?;
+        Some(match request {
+            Some(request) => AccessEntry::new(request, self.remote_addr),
+            None => AccessEntry::unparsed(self.remote_addr),
+        })
+    }
+
+    /// 响应发送完后补上状态码和响应体字节数，写一行访问日志
+    pub(crate) fn finish(&self, entry: Option<AccessEntry>, status: &str, body_bytes: u64) {
+        if let (Some(access_log), Some(mut entry)) = (&self.access_log, entry) {
+            entry.finish(status, body_bytes);
+            access_log.log(&entry);
+        }
+    }
+}
+
+impl<'a> AsyncHttpServer<'a> {
+    pub fn new(host: &'a str, port: u16, work_dir: &'a str) -> Self {
+        Self {
+            host,
+            port,
+            work_dir,
+            router: None,
+            drain_timeout: Duration::from_secs(30),
+            timeouts: Timeouts::new(),
+            limits: Limits::new(),
+            tls: None,
+            http2: true,
+            access_log: None,
+        }
+    }
+
+    /// 每个响应发送完后写一行访问日志，HTTP/1.x 和 HTTP/2 的请求都会记录
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// 使用 HTTPS 提供服务，开启 HTTP/2 时 ALPN 会优先协商 `h2`
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// 是否支持 HTTP/2，默认开启；关闭后只使用 HTTP/1.1
+    pub fn http2(mut self, http2: bool) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    /// 请求行、请求头和请求体的大小上限，超过时分别返回 `414`、`431` 和 `413`
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// 连接的空闲、读请求和写响应超时
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// 使用路由器处理所有请求，替换默认的静态资源服务
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
+    /// 停止时等待正在处理的请求完成的最长时间，超时后强制关闭剩余连接
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// 在当前任务中运行服务器，不会主动停止
+    pub async fn run(&self) -> Result<(), ServerError> {
+        let (settings, _) = self.settings()?;
+        let (listener, service) = self.start().await?;
+        // 持有发送端，服务器一直运行
+        let (_stop, stop_receiver) = watch::channel(false);
+        serve(
+            listener,
+            service,
+            stop_receiver,
+            settings,
+            self.drain_timeout,
+        )
+        .await;
+        Ok(())
+    }
+
+    /// 在新的 tokio 任务中运行服务器，返回的句柄可以查询监听地址并关闭服务器
+    pub async fn spawn(&self) -> Result<AsyncServerHandle, ServerError> {
+        let (settings, tls) = self.settings()?;
+        let (listener, service) = self.start().await?;
+        let local_addr = listener.local_addr()?;
+        let (stop, stop_receiver) = watch::channel(false);
+        let task = tokio::spawn(serve(
+            listener,
+            service,
+            stop_receiver,
+            settings,
+            self.drain_timeout,
+        ));
+        Ok(AsyncServerHandle {
+            local_addr,
+            stop,
+            tls,
+            task,
+        })
+    }
+
+    /// 读取证书，生成每个连接共用的配置
+    fn settings(&self) -> Result<(ConnectionSettings, Option<Arc<Tls>>), ServerError> {
+        let tls = match &self.tls {
+            Some(config) => Some(Arc::new(config.build()?)),
+            None => None,
+        };
+        let acceptor = tls.as_ref().map(|tls| {
+            let mut server_config = tls.server_config().as_ref().clone();
+            if !self.http2 {
+                server_config.alpn_protocols.retain(|p| p != b"h2");
+            } else if !server_config.alpn_protocols.iter().any(|p| p == b"h2") {
+                server_config.alpn_protocols.insert(0, b"h2".to_vec());
+            }
+            TlsAcceptor::from(Arc::new(server_config))
+        });
+        let settings = ConnectionSettings {
+            timeouts: self.timeouts,
+            limits: self.limits,
+            tls: acceptor,
+            http2: self.http2,
+            access_log: self.access_log.clone(),
+        };
+        Ok((settings, tls))
+    }
+
+    async fn start(&self) -> Result<(TcpListener, Arc<Service>), ServerError> {
+        if !Path::new(self.work_dir).is_dir() {
+            return Err(ServerError::WorkDir(self.work_dir.to_string()));
+        }
+        let addr = format!("{}:{}", self.host, self.port);
+        let listener = TcpListener::bind(&addr)
+            .await
+            .map_err(|source| ServerError::Bind { addr, source })?;
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        info!(
+            "Async server is running on {scheme}://{} in {}",
+            listener.local_addr()?,
+            self.work_dir
+        );
+
+        let service = match &self.router {
+            Some(router) => Service::Router(Arc::clone(router)),
+            None => Service::Static(StaticResourceHandler::new(self.work_dir)),
+        };
+        Ok((listener, Arc::new(service)))
+    }
+}
+
+/// 后台运行的异步服务器句柄，drop 时服务器也会停止
+pub struct AsyncServerHandle {
+    local_addr: SocketAddr,
+    stop: watch::Sender<bool>,
+    tls: Option<Arc<Tls>>,
+    task: JoinHandle<()>,
+}
+
+impl AsyncServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// 重新读取 HTTPS 证书，之后的新连接使用新证书；读取失败时继续使用原来的证书
+    pub fn reload_tls(&self) -> Result<(), ServerError> {
+        match &self.tls {
+            Some(tls) => tls.reload(),
+            None => Err(ServerError::Tls("TLS is not enabled".to_string())),
+        }
+    }
+
+    /// 停止接收新连接，等待正在处理的请求完成
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(true);
+        if let Err(e) = self.task.await {
+            error!("Async server task failed: {e}");
+        }
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    service: Arc<Service>,
+    mut stop: watch::Receiver<bool>,
+    settings: ConnectionSettings,
+    drain_timeout: Duration,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    let connection = handle_connection(stream, Arc::clone(&service), stop.clone(), settings.clone());
+                    connections.spawn(connection);
+                }
+                Err(e) => error!("Failed to accept connection: {e}"),
+            },
+            // 回收已经结束的连接任务
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = stop.changed() => break,
+        }
+    }
+    // 不再接受新连接
+    drop(listener);
+
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if tokio::time::timeout(drain_timeout, drain).await.is_err() {
+        warn!(
+            "Drain timeout exceeded, aborting {} connections",
+            connections.len()
+        );
+        connections.shutdown().await;
+    }
+    info!("Async server shut down");
+}
+
+/// 完成 TLS 握手并按协商的协议处理连接
+async fn handle_connection(
+    stream: TcpStream,
+    service: Arc<Service>,
+    mut stop: watch::Receiver<bool>,
+    settings: ConnectionSettings,
+) {
+    let ConnectionSettings {
+        timeouts,
+        limits,
+        tls,
+        http2,
+        access_log,
+    } = settings;
+    let log = ConnectionLog {
+        access_log,
+        remote_addr: stream.peer_addr().ok(),
+    };
+    // HTTP/2 的 WINDOW_UPDATE 等小帧不能被 Nagle 算法延迟
+    if let Err(e) = stream.set_nodelay(true) {
+        warn!("Error setting TCP_NODELAY: {e}");
+    }
+    let Some(acceptor) = tls else {
+        let mut stream = stream;
+        // 明文连接以 HTTP/2 连接前言开头时切换到 h2c
+        if let Some(preface) = serve_connection(
+            &mut stream,
+            &service,
+            &mut stop,
+            timeouts,
+            limits,
+            http2,
+            &log,
+        )
+        .await
+        {
+            http2::serve(
+                Rewind::new(preface, stream),
+                service,
+                stop,
+                timeouts,
+                limits,
+                log,
+            )
+            .await;
+        }
+        return;
+    };
+
+    let mut stream =
+        match tokio::time::timeout(timeouts.header_read_timeout(), acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => {
+                warn!("TLS handshake failed: {e}");
+                return;
+            }
+            Err(_) => return,
+        };
+    if stream.get_ref().1.alpn_protocol() == Some(b"h2") {
+        http2::serve(stream, service, stop, timeouts, limits, log).await;
+        return;
+    }
+    serve_connection(
+        &mut stream,
+        &service,
+        &mut stop,
+        timeouts,
+        limits,
+        false,
+        &log,
+    )
+    .await;
+    // 发送 close_notify，让客户端区分正常结束和连接被截断
+    let _ = stream.shutdown().await;
+}
+
+/// 处理一个连接上的所有 HTTP/1.x 请求，直到连接关闭、不再 keep-alive 或服务器停止
+///
+/// `h2c` 为 true 且连接以 HTTP/2 连接前言开头时，返回已经读到的数据，由调用方切换到 HTTP/2。
+async fn serve_connection<S>(
+    stream: &mut S,
+    service: &Arc<Service>,
+    stop: &mut watch::Receiver<bool>,
+    timeouts: Timeouts,
+    limits: Limits,
+    h2c: bool,
+    log: &ConnectionLog,
+) -> Option<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8 * 1024];
+    let mut clock = RequestClock::new(timeouts);
+    // 只解析新读到的数据，慢速上传不会让每次读取都从头扫描缓冲区
+    let mut parser = RequestParser::new(limits);
+    let mut first = true;
+    loop {
+        clock.reset();
+        let mut expect_checked = false;
+        let request = loop {
+            if h2c && first && buffer.starts_with(PREFACE) {
+                return Some(buffer);
+            }
+            let parsed = if h2c && first && PREFACE.starts_with(&buffer) {
+                // 连接前言的前半部分也是完整的 HTTP/1.x 请求头，收完之前不能解析
+                Parse::Incomplete
+            } else {
+                match parser.parse(&buffer) {
+                    // 缓冲区已满仍然不是完整的请求，只可能是 chunked 请求体的分块过多
+                    Parse::Incomplete if buffer.len() >= limits.max_request_size() => {
+                        Parse::Error(ParseError::PayloadTooLarge)
+                    }
+                    parsed => parsed,
+                }
+            };
+            match parsed {
+                Parse::Complete(request, consumed) => {
+                    buffer.drain(..consumed);
+                    first = false;
+                    break *request;
+                }
+                Parse::Error(error) => {
+                    warn!("Rejected request: {error}");
+                    let entry = log.entry(parser.head());
+                    let response = rejection_response(error);
+                    write_response(stream, response, timeouts, log, entry).await;
+                    return None;
+                }
+                Parse::Incomplete => {}
+            }
+            // 请求头已经完整但请求体还没有收到时，处理 `Expect: 100-continue`
+            if !expect_checked && let Some(head) = parser.head() {
+                expect_checked = true;
+                match service.check_expectation(head) {
+                    Expectation::None => {}
+                    Expectation::Continue => {
+                        if stream.write_all(CONTINUE).await.is_err() {
+                            return None;
+                        }
+                    }
+                    Expectation::Reject(response) => {
+                        let entry = log.entry(Some(head));
+                        write_response(stream, response, timeouts, log, entry).await;
+                        return None;
+                    }
+                }
+            }
+            // 请求头的长度由解析器给出，不需要再扫描缓冲区
+            clock.observe(buffer.len(), parser.header_len());
+            let deadline = tokio::time::Instant::from_std(clock.deadline());
+            let read = tokio::select! {
+                read = tokio::time::timeout_at(deadline, stream.read(&mut chunk)) => read,
+                // 停止时直接关闭正在等待请求的连接
+                _ = stop.changed() => return None,
+            };
+            match read {
+                Err(_) if clock.in_request() => {
+                    warn!("Request timed out");
+                    let entry = log.entry(parser.head());
+                    let response = request_timeout_response();
+                    write_response(stream, response, timeouts, log, entry).await;
+                    return None;
+                }
+                Ok(Ok(0)) | Err(_) => return None,
+                Ok(Ok(n)) => {
+                    buffer.extend_from_slice(&chunk[..n]);
+                }
+                Ok(Err(e)) => {
+                    if !is_closed(&e) {
+                        error!("Error reading request: {e}");
+                    }
+                    return None;
+                }
+            }
+        };
+
+        let keep_alive = Route::keep_alive(&request);
+        let http11 = *request.version() == HttpVersion::HTTP11;
+        let head = *request.method() == HttpMethod::HEAD;
+        let entry = log.entry(Some(&request));
+        let mut response = service.handle(request).await;
+        // HEAD 请求的响应只有响应头，长度和 GET 请求相同
+        response.set_omit_body(head);
+        if let Some(on_upgrade) = response.take_upgrade() {
+            // 服务器停止时不再建立长期占用连接的新会话
+            let stopping = *stop.borrow();
+            if stopping {
+                response = HttpResponse::new("503", None, Some("Service Unavailable".to_string()));
+                response.set_header("Connection", "close");
+            } else {
+                // 升级后连接不再处理 HTTP 请求，其他状态码的响应体以关闭连接表示结束
+                let connection = if response.status_code() == "101" {
+                    "Upgrade"
+                } else {
+                    "close"
+                };
+                response.set_header("Connection", connection);
+            }
+            if write_response(stream, response, timeouts, log, entry).await && !stopping {
+                // 缓冲区中剩余的数据已经属于升级后的协议
+                let bridge = Bridge::spawn(on_upgrade, buffer);
+                upgrade::serve(stream, bridge, stop, timeouts).await;
+            }
+            return None;
+        }
+        if response.is_streaming() {
+            stream_response(stream, response, http11, timeouts, log, entry).await;
+            return None;
+        }
+        // 处理期间服务器可能开始停止，此时通知客户端关闭连接
+        let keep_alive = keep_alive && !*stop.borrow();
+        response.set_header(
+            "Connection",
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+
+        if !write_response(stream, response, timeouts, log, entry).await || !keep_alive {
+            return None;
+        }
+    }
+}
+
+/// 流式响应体需要阻塞读取，在阻塞线程池中序列化后交给当前任务写出，写完后关闭连接
+async fn stream_response<S>(
+    stream: &mut S,
+    mut response: HttpResponse<'static>,
+    http11: bool,
+    timeouts: Timeouts,
+    log: &ConnectionLog,
+    entry: Option<AccessEntry>,
+) where
+    S: AsyncWrite + Unpin,
+{
+    // HTTP/1.0 不支持 chunked 编码，通过关闭连接表示响应结束
+    response.set_chunked(http11);
+    response.set_header("Connection", "close");
+    let status = response.status_code().to_string();
+    let (sender, mut receiver) = mpsc::channel(4);
+    let task = tokio::task::spawn_blocking(move || {
+        let mut writer = CountingWriter::new(ChannelWriter(sender));
+        let sent = response.send_response(&mut writer);
+        (sent, writer.body_bytes())
+    });
+    while let Some(bytes) = receiver.recv().await {
+        match tokio::time::timeout(timeouts.write_timeout(), stream.write_all(&bytes)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!("Error streaming response: {e}");
+                break;
+            }
+            Err(_) => {
+                warn!("Timed out streaming response");
+                break;
+            }
+        }
+    }
+    // 写出失败时关闭接收端，让阻塞线程中的发送尽快结束
+    drop(receiver);
+    let body_bytes = match task.await {
+        Ok((Ok(()), body_bytes)) => body_bytes,
+        Ok((Err(e), body_bytes)) => {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                warn!("Error streaming response: {e}");
+            }
+            body_bytes
+        }
+        Err(e) => {
+            error!("Streaming task failed: {e}");
+            0
+        }
+    };
+    log.finish(entry, &status, body_bytes);
+}
+
+/// 把阻塞线程中写出的数据交给异步任务发送，接收端关闭后返回 `BrokenPipe`
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 写出响应并写一行访问日志，失败或超时返回 false
+async fn write_response<S>(
+    stream: &mut S,
+    response: HttpResponse<'static>,
+    timeouts: Timeouts,
+    log: &ConnectionLog,
+    entry: Option<AccessEntry>,
+) -> bool
+where
+    S: AsyncWrite + Unpin,
+{
+    let status = response.status_code().to_string();
+    let mut writer = CountingWriter::new(Vec::new());
+    // 写入 Vec 不会失败
+    let _ = response.send_response(&mut writer);
+    let body_bytes = writer.body_bytes();
+    let bytes = writer.into_inner();
+    let written = tokio::time::timeout(timeouts.write_timeout(), stream.write_all(&bytes)).await;
+    log.finish(entry, &status, body_bytes);
+    match written {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            error!("Error sending response: {e}");
+            false
+        }
+        Err(_) => {
+            warn!("Timed out sending response");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    async fn get(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_async_server_serves_static_files() {
+        let root = env::temp_dir().join(format!("web-server-async-{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.txt"), "async hello").unwrap();
+        let work_dir = root.to_string_lossy().to_string();
+
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, &work_dir)
+            .spawn()
+            .await
+            .unwrap();
+        let addr = handle.local_addr();
+
+        let response = get(addr, "GET /index.txt HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(response.ends_with("async hello"));
+
+        let response = get(addr, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.contains("href=\"/docs\""));
+        let response = get(addr, "GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        handle.shutdown().await;
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_server_runs_sync_handlers_with_keep_alive() {
+        let router = Router::new()
+            .get("/users/:id", |request: HttpRequest| {
+                let id = request.param("id").unwrap_or("").to_string();
+                HttpResponse::new("200", None, Some(id))
+            })
+            .get("/boom", |_| panic!("handler failed"));
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .spawn()
+            .await
+            .unwrap();
+        let addr = handle.local_addr();
+
+        let response = get(
+            addr,
+            "GET /users/1 HTTP/1.1\r\n\r\nGET /users/2 HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(response.contains("Connection: keep-alive"));
+        assert!(response.ends_with("2"));
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+
+        let response = get(addr, "GET /boom HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 500"));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_server_streams_chunked_responses() {
+        let router = Router::new().get("/report", |_| {
+            HttpResponse::from_chunks("200", None, ["a", "bc"])
+        });
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .spawn()
+            .await
+            .unwrap();
+        let addr = handle.local_addr();
+
+        let response = get(addr, "GET /report HTTP/1.1\r\n\r\n").await;
+        assert!(response.contains("Transfer-Encoding: chunked"));
+        assert!(response.ends_with("1\r\na\r\n2\r\nbc\r\n0\r\n\r\n"));
+        let response = get(addr, "GET /report HTTP/1.0\r\n\r\n").await;
+        assert!(response.ends_with("\r\n\r\nabc"));
+        handle.shutdown().await;
+    }
+}
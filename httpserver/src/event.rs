@@ -0,0 +1,733 @@
+use crate::accesslog::{AccessEntry, AccessLog, CountingWriter};
+use crate::handler::Handler;
+use crate::httpserver::overloaded_response;
+use crate::route::{CONTINUE, Expectation, Route, check_expectation, rejection_response};
+use crate::shutdown::ServerState;
+use crate::timeout::{RequestClock, Timeouts, request_timeout_response};
+use crate::tls::{Stream, UpgradedStream};
+use http::httprequest::HttpRequest;
+use http::httpresponse::HttpResponse;
+use http::parser::{Limits, Parse, ParseError, RequestParser};
+use log::{debug, error, warn};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+use threadpool::threadpool::{ExecuteError, ThreadPool};
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+/// 没有事件时也定期醒来检查停止标志和超时连接
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, PartialEq)]
+enum Phase {
+    /// 等待一个完整的请求
+    Reading,
+    /// 请求已交给工作线程处理
+    Processing,
+    /// 正在把响应写回客户端
+    Writing,
+}
+
+struct Connection {
+    stream: TcpStream,
+    phase: Phase,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    written: usize,
+    keep_alive: bool,
+    /// 对方已经关闭了写端，不会再有新请求
+    eof: bool,
+    /// 是否同时关注可写事件
+    writable: bool,
+    /// 当前请求的读取进度
+    clock: RequestClock,
+    /// 当前请求的解析进度，每次只处理新读到的数据
+    parser: RequestParser,
+    /// 最近一次写出数据的时间
+    last_active: Instant,
+    /// 当前请求的 `Expect` 请求头是否已经处理过
+    expect_checked: bool,
+    /// 当前请求的访问记录，响应写完后输出
+    entry: Option<AccessEntry>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, timeouts: Timeouts, limits: Limits) -> Self {
+        Self {
+            stream,
+            phase: Phase::Reading,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            written: 0,
+            keep_alive: false,
+            eof: false,
+            writable: false,
+            clock: RequestClock::new(timeouts),
+            parser: RequestParser::new(limits),
+            last_active: Instant::now(),
+            expect_checked: false,
+            entry: None,
+        }
+    }
+}
+
+/// 工作线程处理完请求后交回事件循环的响应
+struct Completion {
+    token: Token,
+    payload: Payload,
+    keep_alive: bool,
+    /// 状态码和响应体字节数，写完后记入连接上的访问记录
+    status: String,
+    body_bytes: u64,
+}
+
+enum Payload {
+    /// 已经序列化好的完整响应
+    Buffered(Vec<u8>),
+    /// 流式响应体，需要在工作线程中阻塞发送
+    Stream(HttpResponse<'static>),
+}
+
+impl Completion {
+    fn new(token: Token, mut response: HttpResponse<'static>, keep_alive: bool) -> Self {
+        let status = response.status_code().to_string();
+        if response.is_upgrade() {
+            // 升级后的连接交给工作线程，由处理器接管
+            return Self {
+                token,
+                payload: Payload::Stream(response),
+                keep_alive: false,
+                status,
+                body_bytes: 0,
+            };
+        }
+        if response.is_streaming() {
+            // 流式响应发送完后关闭连接
+            response.set_header("Connection", "close");
+            return Self {
+                token,
+                payload: Payload::Stream(response),
+                keep_alive: false,
+                status,
+                body_bytes: 0,
+            };
+        }
+        let mut writer = CountingWriter::new(Vec::new());
+        // 写入 Vec 不会失败
+        let _ = response.send_response(&mut writer);
+        Self {
+            token,
+            body_bytes: writer.body_bytes(),
+            payload: Payload::Buffered(writer.into_inner()),
+            keep_alive,
+            status,
+        }
+    }
+}
+
+/// 基于 epoll（mio）的连接引擎
+///
+/// 所有连接都是非阻塞的，由一个线程统一读写；只有读到完整的请求后才交给线程池处理，
+/// 处理结果通过 channel 和 [`Waker`] 交回事件循环写出。
+/// 空闲的 keep-alive 连接和慢速客户端不会占用工作线程。
+pub(crate) struct EventLoop {
+    poll: Poll,
+    timeouts: Timeouts,
+    limits: Limits,
+    listener: Option<TcpListener>,
+    waker: Arc<Waker>,
+    handler: Arc<dyn Handler>,
+    state: Arc<ServerState>,
+    access_log: Option<Arc<AccessLog>>,
+    connections: HashMap<Token, Connection>,
+    next_token: usize,
+    sender: Sender<Completion>,
+    completions: Receiver<Completion>,
+}
+
+impl EventLoop {
+    pub(crate) fn new(
+        listener: std::net::TcpListener,
+        handler: Arc<dyn Handler>,
+        state: Arc<ServerState>,
+        timeouts: Timeouts,
+        limits: Limits,
+        access_log: Option<Arc<AccessLog>>,
+    ) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        let mut listener = TcpListener::from_std(listener);
+        let poll = Poll::new()?;
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (sender, completions) = mpsc::channel();
+        Ok(Self {
+            poll,
+            timeouts,
+            limits,
+            listener: Some(listener),
+            waker,
+            handler,
+            state,
+            access_log,
+            connections: HashMap::new(),
+            next_token: WAKER.0 + 1,
+            sender,
+            completions,
+        })
+    }
+
+    /// 运行事件循环直到服务器停止且连接排空，返回被强制关闭的连接数
+    pub(crate) fn run(mut self, pool: &ThreadPool, drain_timeout: Duration) -> usize {
+        let mut events = Events::with_capacity(1024);
+        let mut drain_deadline = None;
+        let mut last_sweep = Instant::now();
+
+        loop {
+            if self.state.is_stopping() && drain_deadline.is_none() {
+                self.stop_accepting();
+                drain_deadline = Some(Instant::now() + drain_timeout);
+            }
+            if let Some(deadline) = drain_deadline {
+                if self.connections.is_empty() {
+                    return 0;
+                }
+                if Instant::now() >= deadline {
+                    let aborted = self.connections.len();
+                    warn!("Drain timeout exceeded, aborting {aborted} connections");
+                    self.close_all();
+                    return aborted;
+                }
+            }
+
+            if let Err(e) = self.poll.poll(&mut events, Some(POLL_INTERVAL)) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Event loop poll failed: {e}");
+                let aborted = self.connections.len();
+                self.close_all();
+                return aborted;
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.accept(),
+                    WAKER => {}
+                    token => {
+                        if event.is_readable() || event.is_read_closed() {
+                            self.read(token, pool);
+                        }
+                        if event.is_writable() {
+                            self.write(token, pool);
+                        }
+                    }
+                }
+            }
+
+            while let Ok(completion) = self.completions.try_recv() {
+                self.complete(completion, pool);
+            }
+
+            if last_sweep.elapsed() >= Duration::from_secs(1) {
+                self.expire(pool);
+                last_sweep = Instant::now();
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        // 停止时唤醒用的连接也会触发这里，交给下一轮循环处理
+        if self.state.is_stopping() {
+            return;
+        }
+        loop {
+            let mut stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    error!("Failed to accept connection: {e}");
+                    break;
+                }
+            };
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            if let Err(e) = self
+                .poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE)
+            {
+                error!("Failed to register connection: {e}");
+                continue;
+            }
+            self.state.connection_opened();
+            self.connections
+                .insert(token, Connection::new(stream, self.timeouts, self.limits));
+        }
+    }
+
+    /// 读取所有可读的数据，然后尝试解析出完整的请求
+    fn read(&mut self, token: Token, pool: &ThreadPool) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let mut chunk = [0u8; 8 * 1024];
+        let max_buffer = self.limits.max_request_size();
+        loop {
+            // 缓冲区已满时暂停读取，写完当前响应后再继续
+            if connection.read_buf.len() >= max_buffer {
+                break;
+            }
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => {
+                    connection.eof = true;
+                    break;
+                }
+                Ok(n) => connection.read_buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("Error reading from connection: {e}");
+                    self.close(token);
+                    return;
+                }
+            }
+        }
+        self.process(token, pool);
+    }
+
+    /// 连接处于等待请求的状态时，把缓冲区中的完整请求交给线程池
+    fn process(&mut self, token: Token, pool: &ThreadPool) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        if connection.phase != Phase::Reading {
+            return;
+        }
+        let parsed = connection.parser.parse(&connection.read_buf);
+        // 请求头的长度由解析器给出，不需要再扫描缓冲区
+        let header_len = connection.parser.header_len();
+        connection
+            .clock
+            .observe(connection.read_buf.len(), header_len);
+        match parsed {
+            Parse::Complete(request, consumed) => {
+                connection.read_buf.drain(..consumed);
+                connection.phase = Phase::Processing;
+                connection.expect_checked = false;
+                self.dispatch(token, *request, pool);
+            }
+            Parse::Incomplete if connection.eof => self.close(token),
+            // 缓冲区已满仍然不是完整的请求，只可能是 chunked 请求体的分块过多
+            Parse::Incomplete if connection.read_buf.len() >= self.limits.max_request_size() => {
+                self.reject(token, ParseError::PayloadTooLarge, pool);
+            }
+            Parse::Incomplete if !connection.expect_checked => self.expect(token, pool),
+            Parse::Incomplete => {}
+            Parse::Error(error) => self.reject(token, error, pool),
+        }
+    }
+
+    /// 请求头已经完整但请求体还没有收到时，处理 `Expect: 100-continue`
+    fn expect(&mut self, token: Token, pool: &ThreadPool) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let Some(head) = connection.parser.head() else {
+            return;
+        };
+        connection.expect_checked = true;
+        match check_expectation(self.handler.as_ref(), head) {
+            Expectation::None => {}
+            Expectation::Continue => {
+                // 上一个响应已经全部写出，临时响应一般可以直接写入发送缓冲区；
+                // 只写出一部分时无法再发送最终响应，只能关闭连接
+                if !matches!(connection.stream.write(CONTINUE), Ok(n) if n == CONTINUE.len()) {
+                    debug!("Failed to send 100 Continue on connection {}", token.0);
+                    self.close(token);
+                }
+            }
+            Expectation::Reject(response) => {
+                self.begin_entry(token, None);
+                let completion = Completion::new(token, response, false);
+                self.complete(completion, pool);
+            }
+        }
+    }
+
+    /// 请求超出大小限制，返回对应的错误响应后关闭连接
+    fn reject(&mut self, token: Token, error: ParseError, pool: &ThreadPool) {
+        warn!("Rejected request on connection {}: {error}", token.0);
+        self.begin_entry(token, None);
+        let completion = Completion::new(token, rejection_response(error), false);
+        self.complete(completion, pool);
+    }
+
+    /// 设置了访问日志时为连接上的当前请求创建访问记录
+    ///
+    /// 没有传入 `request` 时使用已经解析的请求头，请求头也没有读完时请求中的字段记为 `-`。
+    fn begin_entry(&mut self, token: Token, request: Option<&HttpRequest>) {
+        if self.access_log.is_none() {
+            return;
+        }
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let remote_addr = connection.stream.peer_addr().ok();
+        connection.entry = match request.or_else(|| connection.parser.head()) {
+            Some(request) => Some(AccessEntry::new(request, remote_addr)),
+            None => Some(AccessEntry::unparsed(remote_addr)),
+        };
+    }
+
+    fn dispatch(&mut self, token: Token, request: HttpRequest, pool: &ThreadPool) {
+        let handler = Arc::clone(&self.handler);
+        let state = Arc::clone(&self.state);
+        let sender = self.sender.clone();
+        let waker = Arc::clone(&self.waker);
+        self.begin_entry(token, Some(&request));
+        let result = pool.try_execute(move || {
+            let (response, keep_alive) = Route::respond(handler.as_ref(), request, &state);
+            // 事件循环已经退出时丢弃响应即可
+            if sender
+                .send(Completion::new(token, response, keep_alive))
+                .is_ok()
+                && let Err(e) = waker.wake()
+            {
+                error!("Failed to wake event loop: {e}");
+            }
+        });
+        match result {
+            Ok(()) => {}
+            Err(ExecuteError::Full) => {
+                warn!(
+                    "Thread pool is saturated, rejecting request: {}",
+                    pool.stats()
+                );
+                let completion = Completion::new(token, overloaded_response(), false);
+                self.complete(completion, pool);
+            }
+            Err(e) => {
+                error!("Failed to dispatch request: {e}");
+                self.close(token);
+            }
+        }
+    }
+
+    fn complete(&mut self, completion: Completion, pool: &ThreadPool) {
+        // 连接可能在处理期间已经被关闭
+        let Some(connection) = self.connections.get_mut(&completion.token) else {
+            return;
+        };
+        let response = match completion.payload {
+            Payload::Buffered(response) => response,
+            Payload::Stream(response) => {
+                self.hand_off(completion.token, response, pool);
+                return;
+            }
+        };
+        if let Some(entry) = connection.entry.as_mut() {
+            entry.finish(&completion.status, completion.body_bytes);
+        }
+        connection.write_buf = response;
+        connection.written = 0;
+        connection.keep_alive = completion.keep_alive;
+        connection.phase = Phase::Writing;
+        connection.last_active = Instant::now();
+        self.write(completion.token, pool);
+    }
+
+    /// 流式响应体的长度未知，不能缓存在事件循环中，
+    /// 把连接交给工作线程阻塞写出，写完后关闭连接
+    ///
+    /// 协议升级响应同样交给工作线程发送，之后由升级回调在该线程上接管连接。
+    fn hand_off(&mut self, token: Token, mut response: HttpResponse<'static>, pool: &ThreadPool) {
+        let Some(mut connection) = self.connections.remove(&token) else {
+            return;
+        };
+        let _ = self.poll.registry().deregister(&mut connection.stream);
+        let stream = std::net::TcpStream::from(connection.stream);
+        let leftover = connection.read_buf;
+        let mut entry = connection.entry;
+        let state = Arc::clone(&self.state);
+        let access_log = self.access_log.clone();
+        let write_timeout = self.timeouts.write_timeout();
+        let result = pool.try_execute(move || {
+            let on_upgrade = response.take_upgrade();
+            let status = response.status_code().to_string();
+            let mut writer = CountingWriter::new(&stream);
+            let sent = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_write_timeout(Some(write_timeout)))
+                .and_then(|_| response.send_response(&mut writer));
+            if let (Some(access_log), Some(entry)) = (access_log, entry.as_mut()) {
+                entry.finish(&status, writer.body_bytes());
+                access_log.log(entry);
+            }
+            match &sent {
+                Ok(()) => state.record_request(),
+                Err(e) => debug!("Error streaming response: {e}"),
+            }
+            // 登记到跟踪器中，服务器停止时和阻塞模式的空闲连接一样被关闭
+            if let (Some(on_upgrade), Ok(())) = (on_upgrade, sent)
+                && let Some(_guard) = state.track(&stream)
+            {
+                on_upgrade.run(Box::new(UpgradedStream::new(
+                    Stream::Plain(stream),
+                    leftover,
+                )));
+            }
+            state.connection_closed();
+        });
+        if let Err(e) = result {
+            warn!("Failed to stream response: {e}");
+            self.state.connection_closed();
+        }
+    }
+
+    /// 尽量写出响应，写不完时等待可写事件
+    fn write(&mut self, token: Token, pool: &ThreadPool) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        if connection.phase != Phase::Writing {
+            return;
+        }
+        while connection.written < connection.write_buf.len() {
+            match connection
+                .stream
+                .write(&connection.write_buf[connection.written..])
+            {
+                Ok(0) => {
+                    self.close(token);
+                    return;
+                }
+                Ok(n) => {
+                    connection.written += n;
+                    connection.last_active = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if !connection.writable {
+                        connection.writable = true;
+                        self.reregister(token);
+                    }
+                    return;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    debug!("Error writing to connection: {e}");
+                    self.close(token);
+                    return;
+                }
+            }
+        }
+
+        self.state.record_request();
+        if let (Some(access_log), Some(entry)) = (&self.access_log, connection.entry.take()) {
+            access_log.log(&entry);
+        }
+        connection.write_buf = Vec::new();
+        connection.written = 0;
+        if !connection.keep_alive {
+            self.close(token);
+            return;
+        }
+        connection.phase = Phase::Reading;
+        connection.clock.reset();
+        connection.clock.observe(connection.read_buf.len(), None);
+        if connection.writable {
+            connection.writable = false;
+            self.reregister(token);
+        }
+        // 客户端可能已经发来了下一个请求（pipelining），缓冲区满时还有数据没有读取
+        self.read(token, pool);
+    }
+
+    fn reregister(&mut self, token: Token) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let interest = if connection.writable {
+            Interest::READABLE | Interest::WRITABLE
+        } else {
+            Interest::READABLE
+        };
+        if let Err(e) = self
+            .poll
+            .registry()
+            .reregister(&mut connection.stream, token, interest)
+        {
+            error!("Failed to update connection interest: {e}");
+            self.close(token);
+        }
+    }
+
+    fn close(&mut self, token: Token) {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            let _ = self.poll.registry().deregister(&mut connection.stream);
+            self.state.connection_closed();
+        }
+    }
+
+    /// 处理超时的连接：空闲和写超时的连接直接关闭，请求没读完的返回 `408`
+    fn expire(&mut self, pool: &ThreadPool) {
+        let now = Instant::now();
+        let write_timeout = self.timeouts.write_timeout();
+        let mut expired = Vec::new();
+        let mut timed_out = Vec::new();
+        for (token, connection) in &self.connections {
+            match connection.phase {
+                Phase::Reading if now >= connection.clock.deadline() => {
+                    if connection.clock.in_request() {
+                        timed_out.push(*token);
+                    } else {
+                        expired.push(*token);
+                    }
+                }
+                Phase::Writing if now - connection.last_active > write_timeout => {
+                    expired.push(*token);
+                }
+                _ => {}
+            }
+        }
+        for token in expired {
+            self.close(token);
+        }
+        for token in timed_out {
+            warn!("Request on connection {} timed out", token.0);
+            self.begin_entry(token, None);
+            let completion = Completion::new(token, request_timeout_response(), false);
+            self.complete(completion, pool);
+        }
+    }
+
+    /// 停止接受新连接，关闭所有没有正在处理请求的连接
+    fn stop_accepting(&mut self) {
+        if let Some(mut listener) = self.listener.take() {
+            let _ = self.poll.registry().deregister(&mut listener);
+        }
+        let idle: Vec<Token> = self
+            .connections
+            .iter()
+            .filter(|(_, c)| c.phase == Phase::Reading)
+            .map(|(token, _)| *token)
+            .collect();
+        for token in idle {
+            self.close(token);
+        }
+        // 已经升级的连接由工作线程持有，登记在跟踪器中
+        self.state.close_idle();
+    }
+
+    fn close_all(&mut self) {
+        let tokens: Vec<Token> = self.connections.keys().copied().collect();
+        for token in tokens {
+            self.close(token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::httpserver::{Engine, HttpServer};
+    use crate::route::Router;
+    use crate::timeout::Timeouts;
+    use http::httpresponse::HttpResponse;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    fn pong(_: http::httprequest::HttpRequest) -> HttpResponse<'static> {
+        HttpResponse::new("200", None, Some("pong".to_string()))
+    }
+
+    #[test]
+    fn test_idle_connections_do_not_pin_workers() {
+        let router = Router::new().get("/ping", pong);
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .engine(Engine::Event)
+            .workers(1)
+            .max_workers(1);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+
+        // 大量空闲连接和只发了一半请求的慢速客户端
+        let mut idle: Vec<TcpStream> = (0..200)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        for stream in idle.iter_mut().take(50) {
+            stream.write_all(b"GET /ping HTTP/1.1\r\nHost: ").unwrap();
+        }
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("pong"));
+    }
+
+    #[test]
+    fn test_slow_request_headers_get_408() {
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(Router::new().get("/ping", pong))
+            .engine(Engine::Event)
+            .timeouts(Timeouts::new().header_read(Duration::from_millis(300)));
+        let handle = server.spawn().unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+        stream.write_all(b"GET /ping HTTP/1.1\r\nHost: ").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+
+    #[test]
+    fn test_pipelined_requests_and_graceful_shutdown() {
+        let router = Router::new().get("/ping", pong).get("/slow", |_| {
+            thread::sleep(Duration::from_millis(300));
+            HttpResponse::new("200", None, Some("done".to_string()))
+        });
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .engine(Engine::Event);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+
+        // 一次发送两个请求，按顺序收到两个响应
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\n\r\nGET /ping HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response.matches("pong").count(), 2);
+        assert!(response.contains("Connection: keep-alive"));
+        assert!(response.contains("Connection: close"));
+
+        let mut idle = TcpStream::connect(addr).unwrap();
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let mut response = String::new();
+        slow.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("done"));
+        let mut buffer = [0u8; 16];
+        assert_eq!(idle.read(&mut buffer).unwrap_or(0), 0);
+    }
+}
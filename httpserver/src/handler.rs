@@ -1,15 +1,27 @@
 use std::collections::HashMap;
-use std::path::Path;
-use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
 
 use http::httprequest::HttpRequest;
 use http::httpresponse::HttpResponse;
-use log::{info, warn};
+use log::{debug, warn};
 
-pub trait Handler {
-    fn handle_request(request: HttpRequest) -> HttpResponse<'static>;
+pub trait Handler: Send + Sync {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static>;
 
-    fn load_build_in_file(file_path: &str) -> Option<String> {
+    /// 请求带有 `Expect: 100-continue` 时，在读取请求体之前调用，`request` 中只有请求头
+    ///
+    /// 返回响应时直接发送该响应并关闭连接，客户端不会再发送请求体；
+    /// 返回 `None` 时先发送 `100 Continue` 再读取请求体。
+    /// 可能在事件循环线程中调用，应当快速返回。
+    fn check_continue(&self, _request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        None
+    }
+
+    fn load_build_in_file(file_path: &str) -> Option<String>
+    where
+        Self: Sized,
+    {
         // 获取工作空间根目录（httpserver的上级目录）
         let manifest_dir = env!("CARGO_MANIFEST_DIR");
         let workspace_root = Path::new(manifest_dir).parent().unwrap();
@@ -23,109 +35,208 @@ pub trait Handler {
     }
 }
 
-pub struct StaticResourceHandler {}
+impl<F> Handler for F
+where
+    F: Fn(HttpRequest) -> HttpResponse<'static> + Send + Sync,
+{
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        self(request)
+    }
+}
 
-impl Handler for StaticResourceHandler {
-    fn handle_request(request: HttpRequest) -> HttpResponse<'static> {
-        let path = request.resource_path();
-        let work_dir = std::env::current_dir().unwrap();
+/// 静态资源处理器，所有路径都相对于 `root` 解析
+pub struct StaticResourceHandler {
+    root: PathBuf,
+}
 
-        // ./FontAwesome/fonts/fontawesome-webfont.woff?v=4.7.0 把参数去掉
-        let path = path.split('?').next().unwrap_or(path);
-        let current_path = format!("{}/{path}", work_dir.display());
-        if !Path::new(&current_path).exists() {
-            warn!("{current_path} not found");
-            return NotFoundHandler::handle_request(request);
-        }
+impl StaticResourceHandler {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        // 尽量在创建时得到绝对路径，之后工作目录变化也不受影响
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
 
+impl StaticResourceHandler {
+    /// 检查解析出的绝对路径，文件不存在或不在根目录下时返回 `None`
+    fn check_path(&self, current_path: &Path, canonical: io::Result<PathBuf>) -> Option<PathBuf> {
         // 获取绝对路径
-        let file_path = Path::new(&current_path).canonicalize().unwrap();
-        info!("{current_path} -> {}", file_path.display());
-        // 当前路径必须在程序运行目录下
-        let current_dir = work_dir.canonicalize().unwrap();
-        if !file_path.starts_with(&current_dir) {
+        let file_path = match canonical {
+            Ok(file_path) => file_path,
+            Err(_) => {
+                warn!("{} not found", current_path.display());
+                return None;
+            }
+        };
+        debug!("{} -> {}", current_path.display(), file_path.display());
+        // 当前路径必须在根目录下
+        if !file_path.starts_with(&self.root) {
             warn!(
                 "{} is not in {}",
-                current_path,
-                current_dir.to_str().unwrap()
+                current_path.display(),
+                self.root.display()
             );
-            return NotFoundHandler::handle_request(request);
+            return None;
         }
+        Some(file_path)
+    }
+}
+
+impl Handler for StaticResourceHandler {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        // ./FontAwesome/fonts/fontawesome-webfont.woff?v=4.7.0 把参数去掉
+        let path = request.path();
+        let current_path = self.root.join(path.trim_start_matches('/'));
+        let Some(file_path) = self.check_path(&current_path, current_path.canonicalize()) else {
+            return NotFoundHandler::response();
+        };
 
         if file_path.is_dir() {
-            return deal_dir_resource(file_path.to_str().unwrap(), path);
+            return deal_dir_resource(&file_path.to_string_lossy(), &request.original_path());
+        }
+
+        deal_file_resource(&file_path.to_string_lossy())
+    }
+
+    /// 只检查路径是否存在，不读取文件或目录
+    fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        let current_path = self.root.join(request.path().trim_start_matches('/'));
+        match self.check_path(&current_path, current_path.canonicalize()) {
+            Some(_) => None,
+            None => Some(NotFoundHandler::response()),
         }
+    }
+}
+
+#[cfg(feature = "async")]
+impl StaticResourceHandler {
+    /// 和 [`Handler::handle_request`] 行为相同，文件系统操作使用 tokio 的异步 I/O
+    pub async fn handle_request_async(&self, request: HttpRequest) -> HttpResponse<'static> {
+        use tokio::fs as async_fs;
 
-        deal_file_resource(file_path.to_str().unwrap())
+        let path = request.path();
+        let current_path = self.root.join(path.trim_start_matches('/'));
+        let canonical = async_fs::canonicalize(&current_path).await;
+        let Some(file_path) = self.check_path(&current_path, canonical) else {
+            return NotFoundHandler::response();
+        };
+        let file_name = file_path.to_string_lossy();
+
+        let is_dir = async_fs::metadata(&file_path)
+            .await
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false);
+        if !is_dir {
+            return match async_fs::read(&file_path).await {
+                Ok(content) => file_response(&file_name, content),
+                Err(e) => {
+                    warn!("{file_name} read error: {e}");
+                    HttpResponse::new("404", None, None)
+                }
+            };
+        }
+
+        let mut read_dir = match async_fs::read_dir(&file_path).await {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                warn!("{file_name} read error: {e}");
+                return HttpResponse::new("404", None, None);
+            }
+        };
+        let mut entries = Vec::new();
+        while let Ok(Some(dir_entry)) = read_dir.next_entry().await {
+            let is_dir = async_fs::metadata(dir_entry.path())
+                .await
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+            entries.push((dir_entry.file_name().to_string_lossy().to_string(), is_dir));
+        }
+        dir_listing(&request.original_path(), entries)
     }
 }
 
 pub struct NotFoundHandler {}
 
-impl Handler for NotFoundHandler {
-    fn handle_request(_: HttpRequest) -> HttpResponse<'static> {
+impl NotFoundHandler {
+    fn response() -> HttpResponse<'static> {
         HttpResponse::new("404", None, Self::load_build_in_file("404.html"))
     }
 }
 
-fn deal_file_resource(file_path: &str) -> HttpResponse<'static> {
-    let mut header = HashMap::new();
+impl Handler for NotFoundHandler {
+    fn handle_request(&self, _: HttpRequest) -> HttpResponse<'static> {
+        Self::response()
+    }
+
+    /// 不存在的资源不需要客户端上传请求体
+    fn check_continue(&self, _request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        Some(Self::response())
+    }
+}
 
-    // 判断文件类型并设置相应的Content-Type
+/// 根据扩展名判断 Content-Type
+fn content_type(file_path: &str) -> &'static str {
     if file_path.ends_with(".html") {
-        header.insert("Content-Type", "text/html; charset=utf-8");
+        "text/html; charset=utf-8"
     } else if file_path.ends_with(".css") {
-        header.insert("Content-Type", "text/css; charset=utf-8");
+        "text/css; charset=utf-8"
     } else if file_path.ends_with(".js") {
-        header.insert("Content-Type", "text/javascript; charset=utf-8");
+        "text/javascript; charset=utf-8"
     } else if file_path.ends_with(".json") {
-        header.insert("Content-Type", "application/json; charset=utf-8");
+        "application/json; charset=utf-8"
     } else if file_path.ends_with(".xml") {
-        header.insert("Content-Type", "application/xml; charset=utf-8");
+        "application/xml; charset=utf-8"
     } else if file_path.ends_with(".txt") {
-        header.insert("Content-Type", "text/plain; charset=utf-8");
+        "text/plain; charset=utf-8"
     } else if file_path.ends_with(".csv") {
-        header.insert("Content-Type", "text/csv; charset=utf-8");
+        "text/csv; charset=utf-8"
     } else if file_path.ends_with(".md") {
-        header.insert("Content-Type", "text/markdown; charset=utf-8");
+        "text/markdown; charset=utf-8"
     } else if file_path.ends_with(".png") {
-        header.insert("Content-Type", "image/png");
+        "image/png"
     } else if file_path.ends_with(".jpg") || file_path.ends_with(".jpeg") {
-        header.insert("Content-Type", "image/jpeg");
+        "image/jpeg"
     } else if file_path.ends_with(".gif") {
-        header.insert("Content-Type", "image/gif");
+        "image/gif"
     } else if file_path.ends_with(".ico") {
-        header.insert("Content-Type", "image/x-icon");
+        "image/x-icon"
     } else if file_path.ends_with(".svg") {
-        header.insert("Content-Type", "image/svg+xml");
+        "image/svg+xml"
     } else if file_path.ends_with(".woff") {
-        header.insert("Content-Type", "font/woff");
+        "font/woff"
     } else if file_path.ends_with(".woff2") {
-        header.insert("Content-Type", "font/woff2");
+        "font/woff2"
     } else if file_path.ends_with(".ttf") {
-        header.insert("Content-Type", "font/ttf");
+        "font/ttf"
     } else if file_path.ends_with(".eot") {
-        header.insert("Content-Type", "font/eot");
+        "font/eot"
     } else if file_path.ends_with(".otf") {
-        header.insert("Content-Type", "font/otf");
+        "font/otf"
     } else if file_path.ends_with(".wasm") {
-        header.insert("Content-Type", "application/wasm");
+        "application/wasm"
     } else if file_path.ends_with(".pdf") {
-        header.insert("Content-Type", "application/pdf");
+        "application/pdf"
     } else if file_path.ends_with(".zip") {
-        header.insert("Content-Type", "application/zip");
+        "application/zip"
     } else if file_path.ends_with(".tar") {
-        header.insert("Content-Type", "application/x-tar");
+        "application/x-tar"
     } else if file_path.ends_with(".gz") {
-        header.insert("Content-Type", "application/gzip");
+        "application/gzip"
     } else if file_path.ends_with(".bz2") {
-        header.insert("Content-Type", "application/x-bzip2");
+        "application/x-bzip2"
     } else {
-        header.insert("Content-Type", "application/octet-stream");
+        "application/octet-stream"
     }
+}
 
-    // 判断是否为二进制文件
-    let is_binary = file_path.ends_with(".png")
+/// 是否按二进制方式返回文件内容
+fn is_binary(file_path: &str) -> bool {
+    file_path.ends_with(".png")
         || file_path.ends_with(".jpg")
         || file_path.ends_with(".jpeg")
         || file_path.ends_with(".gif")
@@ -141,58 +252,79 @@ fn deal_file_resource(file_path: &str) -> HttpResponse<'static> {
         || file_path.ends_with(".zip")
         || file_path.ends_with(".tar")
         || file_path.ends_with(".gz")
-        || file_path.ends_with(".bz2");
+        || file_path.ends_with(".bz2")
+}
 
-    if is_binary {
-        // 读取二进制文件
-        match fs::read(file_path) {
-            Ok(binary_content) => {
-                HttpResponse::new_binary("200", Some(header), Some(binary_content))
-            }
-            Err(e) => {
-                warn!("{file_path} read error: {e}");
-                HttpResponse::new("404", None, None)
-            }
+fn deal_file_resource(file_path: &str) -> HttpResponse<'static> {
+    match fs::read(file_path) {
+        Ok(content) => file_response(file_path, content),
+        Err(e) => {
+            warn!("{file_path} read error: {e}");
+            HttpResponse::new("404", None, None)
         }
-    } else {
-        // 读取文本文件
-        match fs::read_to_string(file_path) {
-            Ok(text_content) => HttpResponse::new("200", Some(header), Some(text_content)),
-            Err(e) => {
-                warn!("{file_path} read error: {e}");
-                HttpResponse::new("404", None, None)
-            }
+    }
+}
+
+/// 用读取到的文件内容构造响应，同步和异步的静态资源处理共用
+fn file_response(file_path: &str, content: Vec<u8>) -> HttpResponse<'static> {
+    let mut header = HashMap::new();
+    header.insert("Content-Type", content_type(file_path));
+
+    if is_binary(file_path) {
+        return HttpResponse::new_binary("200", Some(header), Some(content));
+    }
+    // 文本文件
+    match String::from_utf8(content) {
+        Ok(text_content) => HttpResponse::new("200", Some(header), Some(text_content)),
+        Err(e) => {
+            warn!("{file_path} read error: {e}");
+            HttpResponse::new("404", None, None)
         }
     }
 }
 
 fn deal_dir_resource(_resource: &str, _current_path: &str) -> HttpResponse<'static> {
-    let read_dir = fs::read_dir(_resource).unwrap();
+    let read_dir = match fs::read_dir(_resource) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            warn!("{_resource} read error: {e}");
+            return HttpResponse::new("404", None, None);
+        }
+    };
 
-    let mut resources: Vec<String> = Vec::new();
+    let entries = read_dir
+        .flatten()
+        .map(|dir_entry| {
+            (
+                dir_entry.file_name().to_string_lossy().to_string(),
+                dir_entry.path().is_dir(),
+            )
+        })
+        .collect();
+    dir_listing(_current_path, entries)
+}
 
+/// 生成目录浏览页面，`entries` 为 (名称, 是否为目录)
+fn dir_listing(_current_path: &str, entries: Vec<(String, bool)>) -> HttpResponse<'static> {
+    let mut resources: Vec<String> = Vec::new();
     // 添加返回上级目录的链接（如果不是根目录）
     let mut navigation = String::new();
     if _current_path != "/" {
         let parent_path = if let Some(parent) = Path::new(_current_path).parent() {
-            if parent.to_str().unwrap().is_empty() {
+            let parent = parent.to_string_lossy();
+            if parent.is_empty() {
                 "/".to_string()
             } else {
-                parent.to_str().unwrap().to_string()
+                parent.to_string()
             }
         } else {
             "/".to_string()
         };
+        let parent_path = escape_html(&parent_path);
         navigation = format!("<div class='nav'><a href=\"{parent_path}\">← 返回上级目录</a></div>");
     }
 
-    for dir in read_dir {
-        let dir_entry = dir.unwrap();
-        let path = dir_entry.path();
-
-        // 获取文件名或目录名
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-
+    for (file_name, is_dir) in entries {
         // 构建相对路径
         let relative_path = if _current_path == "/" {
             format!("/{file_name}")
@@ -201,13 +333,17 @@ fn deal_dir_resource(_resource: &str, _current_path: &str) -> HttpResponse<'stat
         };
 
         // 判断是文件还是目录，添加不同的图标
-        let icon = if path.is_dir() { "📁" } else { "📄" };
+        let icon = if is_dir { "📁" } else { "📄" };
 
-        let link_str =
-            format!("<div class='item'><a href=\"{relative_path}\">{icon} {file_name}</a></div>");
+        let link_str = format!(
+            "<div class='item'><a href=\"{}\">{icon} {}</a></div>",
+            escape_html(&relative_path),
+            escape_html(&file_name)
+        );
         resources.push(link_str);
     }
 
+    let title = escape_html(_current_path);
     let html_content = format!(
         r#"<!DOCTYPE html>
 <html>
@@ -237,8 +373,8 @@ fn deal_dir_resource(_resource: &str, _current_path: &str) -> HttpResponse<'stat
     </div>
 </body>
 </html>"#,
-        _current_path,
-        _current_path,
+        title,
+        title,
         navigation,
         if resources.is_empty() {
             "<p>目录为空</p>".to_string()
@@ -251,3 +387,94 @@ fn deal_dir_resource(_resource: &str, _current_path: &str) -> HttpResponse<'stat
     header.insert("Content-Type", "text/html; charset=utf-8");
     HttpResponse::new("200", Some(header), Some(html_content))
 }
+
+/// 转义 HTML 中的特殊字符，文件名可能包含 `<`、`&`、`"` 等字符
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("web-server-{name}-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("index.txt"), name).unwrap();
+        root
+    }
+
+    fn get(handler: &StaticResourceHandler, path: &str) -> HttpResponse<'static> {
+        handler.handle_request(format!("GET {path} HTTP/1.1\r\n\r\n").into())
+    }
+
+    #[test]
+    fn test_static_handler_serves_from_its_own_root() {
+        let first = StaticResourceHandler::new(temp_root("first"));
+        let second = StaticResourceHandler::new(temp_root("second"));
+
+        assert_eq!(get(&first, "/index.txt?v=1").body(), "first");
+        assert_eq!(get(&second, "/index.txt").body(), "second");
+        assert_eq!(get(&first, "/sub").status_code(), "200");
+        assert_eq!(get(&first, "/missing.txt").status_code(), "404");
+    }
+
+    #[test]
+    fn test_static_handler_rejects_paths_outside_root() {
+        let root = temp_root("outside");
+        let handler = StaticResourceHandler::new(root.join("sub"));
+        assert_eq!(get(&handler, "/../index.txt").status_code(), "404");
+    }
+
+    #[test]
+    fn test_check_continue_only_rejects_missing_paths() {
+        let handler = StaticResourceHandler::new(temp_root("continue"));
+        let head = |path: &str| -> HttpRequest {
+            format!("PUT {path} HTTP/1.1\r\nExpect: 100-continue\r\n\r\n").into()
+        };
+        assert!(handler.check_continue(&head("/index.txt")).is_none());
+        assert!(handler.check_continue(&head("/sub")).is_none());
+        let rejected = handler.check_continue(&head("/missing.txt"));
+        assert_eq!(
+            rejected.map(|response| response.status_code().to_string()),
+            Some("404".to_string())
+        );
+        assert!(handler.check_continue(&head("/../index.txt")).is_some());
+    }
+
+    #[test]
+    fn test_dir_listing_links_keep_mount_prefix_and_escape_names() {
+        let root = temp_root("listing");
+        fs::write(root.join("sub").join("a<b>&\"c\".txt"), "").unwrap();
+        let router = crate::route::Router::new().mount(
+            "/files",
+            crate::route::Router::new().mount("/static", StaticResourceHandler::new(&root)),
+        );
+
+        let listing =
+            router.handle_request("GET /files/static/sub HTTP/1.1\r\n\r\n".to_string().into());
+        let body = listing.body();
+        assert!(body.contains("<a href=\"/files/static\">"), "{body}");
+        assert!(
+            body.contains("href=\"/files/static/sub/a&lt;b&gt;&amp;&quot;c&quot;.txt\">📄 a&lt;b&gt;&amp;&quot;c&quot;.txt</a>"),
+            "{body}"
+        );
+
+        let listing =
+            router.handle_request("GET /files/static HTTP/1.1\r\n\r\n".to_string().into());
+        let body = listing.body();
+        assert!(body.contains("<a href=\"/files\">"), "{body}");
+        assert!(body.contains("href=\"/files/static/sub\">"), "{body}");
+    }
+}
@@ -0,0 +1,849 @@
+use crate::asyncserver::{ConnectionLog, Service};
+use crate::route::Expectation;
+use crate::timeout::Timeouts;
+use crate::upgrade::Bridge;
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use http::httprequest::{HttpMethod, HttpRequest, HttpVersion};
+use http::httpresponse::{HttpResponse, OnUpgrade, status_text};
+use http::parser::Limits;
+use http_crate::header::{HeaderMap, HeaderName, HeaderValue};
+use http_crate::{Method, Request, Response, StatusCode};
+use log::{error, warn};
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+
+/// HTTP/2 连接前言，prior knowledge 方式的 h2c 连接以它开头
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// 每个连接上同时处理的最大请求数
+const MAX_CONCURRENT_STREAMS: u32 = 128;
+
+/// 发送流式响应体时每次读取的最大字节数
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// HTTP/2 中不允许出现的逐跳响应头
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 处理一个 HTTP/2 连接上的所有请求，每个流在单独的任务中处理
+///
+/// 多路复用、流量控制和 HPACK 由 h2 完成，请求转换为 [`HttpRequest`] 后交给和
+/// HTTP/1.1 相同的处理器。服务器停止或连接空闲超时后发送 GOAWAY，等待正在处理的流完成。
+pub(crate) async fn serve<S>(
+    io: S,
+    service: Arc<Service>,
+    mut stop: watch::Receiver<bool>,
+    timeouts: Timeouts,
+    limits: Limits,
+    log: ConnectionLog,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let handshake = h2::server::Builder::new()
+        .max_concurrent_streams(MAX_CONCURRENT_STREAMS)
+        .max_header_list_size(limits.max_header_bytes().try_into().unwrap_or(u32::MAX))
+        .handshake::<_, Bytes>(io);
+    let mut connection = match tokio::time::timeout(timeouts.header_read_timeout(), handshake).await
+    {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => {
+            warn!("HTTP/2 handshake failed: {e}");
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let mut streams = JoinSet::new();
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            accepted = connection.accept() => match accepted {
+                Some(Ok((request, respond))) => {
+                    let service = Arc::clone(&service);
+                    streams.spawn(serve_stream(request, respond, service, limits, log.clone()));
+                }
+                Some(Err(e)) => {
+                    if !e.is_go_away() && !e.is_io() {
+                        warn!("HTTP/2 connection error: {e}");
+                    }
+                    break;
+                }
+                None => break,
+            },
+            // 回收已经结束的流
+            Some(_) = streams.join_next(), if !streams.is_empty() => {}
+            _ = tokio::time::sleep(timeouts.idle_timeout()), if streams.is_empty() && !closing => {
+                closing = true;
+                connection.graceful_shutdown();
+            }
+            _ = stop.changed(), if !closing => {
+                closing = true;
+                connection.graceful_shutdown();
+            }
+        }
+    }
+}
+
+/// 读取一个流上的请求，交给处理器后发送响应
+async fn serve_stream(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    service: Arc<Service>,
+    limits: Limits,
+    log: ConnectionLog,
+) {
+    let (head, body) = request.into_parts();
+    let head_only = head.method == Method::HEAD;
+    let mut request = request_head(&head);
+    let entry = log.entry(Some(&request));
+    // 和 HTTP/1.1 一样，先检查路由、认证等再读取请求体
+    let rejection = match service.check_expectation(&request) {
+        Expectation::None => None,
+        Expectation::Continue => {
+            let mut interim = Response::new(());
+            *interim.status_mut() = StatusCode::CONTINUE;
+            let _ = respond.send_informational(interim);
+            None
+        }
+        Expectation::Reject(response) => Some(response),
+    };
+    let mut response = match rejection {
+        // 不读取请求体，响应发送完后 h2 用 RST_STREAM(NO_ERROR) 通知客户端停止上传
+        Some(response) => response,
+        None => match read_body(&mut request, body, &limits).await {
+            Ok(()) => service.handle(request).await,
+            Err(response) => response,
+        },
+    };
+    if response.is_upgrade() && response.status_code() == "101" {
+        // HTTP/2 中没有协议切换，不支持 RFC 8441 方式的 WebSocket
+        warn!("Protocol switching is not supported over HTTP/2");
+        response = error_response("501");
+    }
+    let on_upgrade = if head_only {
+        None
+    } else {
+        response.take_upgrade()
+    };
+    let status = response.status_code().to_string();
+    let mut body_bytes = 0;
+    let sent = match on_upgrade {
+        Some(on_upgrade) => {
+            send_upgraded(&mut respond, response, on_upgrade, &mut body_bytes).await
+        }
+        None => send_response(&mut respond, response, head_only, &mut body_bytes).await,
+    };
+    log.finish(entry, &status, body_bytes);
+    if let Err(e) = sent
+        && !e.is_reset()
+        && !e.is_io()
+    {
+        warn!("Error sending HTTP/2 response: {e}");
+    }
+}
+
+/// 由请求头构造还没有请求体的 [`HttpRequest`]，`:authority` 转换为 `host`
+fn request_head(head: &http_crate::request::Parts) -> HttpRequest {
+    let target = head
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let mut headers = fields(&head.headers);
+    if let Some(authority) = head.uri.authority()
+        && !headers.contains_key("host")
+    {
+        headers.insert("host".to_string(), authority.to_string());
+    }
+    HttpRequest::new(
+        HttpMethod::from(head.method.as_str()),
+        target,
+        HttpVersion::HTTP20,
+        headers,
+        Vec::new(),
+    )
+}
+
+/// 读取完整的请求体和 trailer，请求体保持原始字节；超出限制时返回错误响应
+async fn read_body(
+    request: &mut HttpRequest,
+    mut body: RecvStream,
+    limits: &Limits,
+) -> Result<(), HttpResponse<'static>> {
+    if request.resource_path().len() > limits.max_request_line() {
+        return Err(error_response("414"));
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| error_response("400"))?;
+        // 数据已经取出，归还流量控制窗口让客户端继续发送
+        let _ = body.flow_control().release_capacity(chunk.len());
+        if data.len() + chunk.len() > limits.max_body() {
+            return Err(error_response("413"));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    let trailers = match body.trailers().await {
+        Ok(trailers) => trailers
+            .map(|trailers| fields(&trailers))
+            .unwrap_or_default(),
+        Err(_) => return Err(error_response("400")),
+    };
+
+    // HTTP/2 的请求可以不带 content-length，按实际收到的字节数设置
+    if !data.is_empty() {
+        request.set_header("content-length", &data.len().to_string());
+    }
+    request.set_body(data);
+    request.set_trailers(trailers);
+    Ok(())
+}
+
+/// 把 HTTP/2 的头部合并成每个名称一个值，`cookie` 按 RFC 9113 用 `; ` 连接
+fn fields(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        let separator = if name == "cookie" { "; " } else { ", " };
+        fields
+            .entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(separator);
+                existing.push_str(&value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    fields
+}
+
+fn error_response(status_code: &'static str) -> HttpResponse<'static> {
+    HttpResponse::new(
+        status_code,
+        None,
+        Some(status_text(status_code).to_string()),
+    )
+}
+
+/// 发送响应头和响应体，流式响应体在阻塞线程池中读取
+async fn send_response(
+    respond: &mut SendResponse<Bytes>,
+    mut response: HttpResponse<'static>,
+    head_only: bool,
+    body_bytes: &mut u64,
+) -> Result<(), h2::Error> {
+    let stream = response.take_stream();
+    let body = Bytes::copy_from_slice(response.body_bytes());
+    let mut head = match response_head(&response) {
+        Ok(head) => head,
+        Err(e) => {
+            error!("Invalid response for HTTP/2: {e}");
+            let mut head = Response::new(());
+            *head.status_mut() = http_crate::StatusCode::INTERNAL_SERVER_ERROR;
+            respond.send_response(head, true)?;
+            return Ok(());
+        }
+    };
+    if stream.is_none() {
+        head.headers_mut()
+            .insert("content-length", HeaderValue::from(body.len()));
+    }
+    let end_of_stream = head_only || (stream.is_none() && body.is_empty());
+    let mut send = respond.send_response(head, end_of_stream)?;
+    if end_of_stream {
+        return Ok(());
+    }
+
+    let Some(stream) = stream else {
+        let length = body.len() as u64;
+        send_data(&mut send, body).await?;
+        *body_bytes = length;
+        return send.send_data(Bytes::new(), true);
+    };
+    let (reader, trailers) = stream.into_parts();
+    let mut chunks = read_chunks(reader);
+    while let Some(chunk) = chunks.recv().await {
+        match chunk {
+            Ok(chunk) => {
+                let length = chunk.len() as u64;
+                send_data(&mut send, chunk).await?;
+                *body_bytes += length;
+            }
+            Err(e) => {
+                warn!("Error streaming response: {e}");
+                send.send_reset(h2::Reason::INTERNAL_ERROR);
+                return Ok(());
+            }
+        }
+    }
+    // 读完响应体后才计算 trailer
+    let trailers = match trailers {
+        Some(trailers) => tokio::task::spawn_blocking(trailers)
+            .await
+            .unwrap_or_default(),
+        None => HashMap::new(),
+    };
+    if trailers.is_empty() {
+        return send.send_data(Bytes::new(), true);
+    }
+    let mut map = HeaderMap::new();
+    for (key, value) in &trailers {
+        match (
+            HeaderName::try_from(key.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            (Ok(name), Ok(value)) => {
+                map.append(name, value);
+            }
+            _ => warn!("Skipping invalid trailer {key}"),
+        }
+    }
+    send.send_trailers(map)
+}
+
+/// 发送响应头后把流交给阻塞线程池中的升级回调，回调写出的数据作为响应体，
+/// 回调结束时结束流；对方重置流后回调读到结束
+async fn send_upgraded(
+    respond: &mut SendResponse<Bytes>,
+    response: HttpResponse<'static>,
+    on_upgrade: OnUpgrade,
+    body_bytes: &mut u64,
+) -> Result<(), h2::Error> {
+    let head = match response_head(&response) {
+        Ok(head) => head,
+        Err(e) => {
+            error!("Invalid response for HTTP/2: {e}");
+            let mut head = Response::new(());
+            *head.status_mut() = http_crate::StatusCode::INTERNAL_SERVER_ERROR;
+            respond.send_response(head, true)?;
+            return Ok(());
+        }
+    };
+    let mut send = respond.send_response(head, false)?;
+    // 请求已经读完，`incoming` 不会再有数据，持有到流结束
+    let Bridge {
+        incoming: _incoming,
+        mut outgoing,
+    } = Bridge::spawn(on_upgrade, Vec::new());
+    loop {
+        tokio::select! {
+            data = outgoing.recv() => match data {
+                Some(data) => {
+                    let length = data.len() as u64;
+                    send_data(&mut send, Bytes::from(data)).await?;
+                    *body_bytes += length;
+                }
+                None => return send.send_data(Bytes::new(), true),
+            },
+            reset = poll_fn(|cx| send.poll_reset(cx)) => {
+                return reset.map(|_| ());
+            }
+        }
+    }
+}
+
+/// 转换状态码和响应头，去掉 HTTP/2 中不允许的逐跳响应头
+fn response_head(response: &HttpResponse) -> Result<Response<()>, http_crate::Error> {
+    let mut builder = Response::builder().status(response.status_code());
+    for (key, value) in response.header_fields() {
+        let lower = key.to_ascii_lowercase();
+        if HOP_BY_HOP.contains(&lower.as_str()) || lower == "content-length" {
+            continue;
+        }
+        builder = builder.header(lower, value);
+    }
+    builder.body(())
+}
+
+/// 按对方的流量控制窗口分段发送数据
+async fn send_data(send: &mut SendStream<Bytes>, mut data: Bytes) -> Result<(), h2::Error> {
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let capacity = match poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(capacity) => capacity?,
+            // 流已经被对方关闭
+            None => return Err(h2::Reason::CANCEL.into()),
+        };
+        if capacity > 0 {
+            let chunk = data.split_to(capacity.min(data.len()));
+            send.send_data(chunk, false)?;
+        }
+    }
+    Ok(())
+}
+
+/// 在阻塞线程池中读取流式响应体，接收端关闭后停止读取
+fn read_chunks(mut reader: Box<dyn Read + Send>) -> mpsc::Receiver<io::Result<Bytes>> {
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut buffer = vec![0u8; STREAM_CHUNK_SIZE];
+        loop {
+            let chunk = match reader.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(n) => Ok(Bytes::copy_from_slice(&buffer[..n])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if sender.blocking_send(chunk).is_err() || failed {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// 先读出已经缓冲的数据再读连接，用于在检测到 h2c 连接前言后交给 h2
+pub(crate) struct Rewind<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub(crate) fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self {
+            prefix,
+            position: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if this.position < this.prefix.len() {
+            let n = buf.remaining().min(this.prefix.len() - this.position);
+            buf.put_slice(&this.prefix[this.position..this.position + n]);
+            this.position += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accesslog::{AccessLog, LogFormat};
+    use crate::asyncserver::AsyncHttpServer;
+    use crate::route::Router;
+    use crate::tls::TlsConfig;
+    use h2::client::SendRequest;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use std::{env, fs};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// 通过 h2c prior knowledge 建立 HTTP/2 连接
+    async fn connect(addr: SocketAddr) -> SendRequest<Bytes> {
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        tcp.set_nodelay(true).unwrap();
+        let (client, connection) = h2::client::handshake(tcp).await.unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    /// 发送请求并读取完整的响应体和 trailer
+    async fn send(
+        client: &SendRequest<Bytes>,
+        request: Request<()>,
+        body: &[u8],
+    ) -> (Response<()>, Vec<u8>, Option<HeaderMap>) {
+        let mut client = client.clone().ready().await.unwrap();
+        let (response, mut stream) = client.send_request(request, body.is_empty()).unwrap();
+        if !body.is_empty() {
+            stream
+                .send_data(Bytes::copy_from_slice(body), true)
+                .unwrap();
+        }
+        let (head, mut body) = response.await.unwrap().into_parts();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            body.flow_control().release_capacity(chunk.len()).unwrap();
+            data.extend_from_slice(&chunk);
+        }
+        let trailers = body.trailers().await.unwrap();
+        (Response::from_parts(head, ()), data, trailers)
+    }
+
+    fn get(path: &str) -> Request<()> {
+        Request::get(format!("http://localhost{path}"))
+            .body(())
+            .unwrap()
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/slow", |_| {
+                std::thread::sleep(Duration::from_millis(300));
+                HttpResponse::new("200", None, Some("slow".to_string()))
+            })
+            .get("/fast", |request: HttpRequest| {
+                let version = String::from(request.version());
+                let mut response = HttpResponse::new("200", None, Some("fast".to_string()));
+                response.set_header("X-Version", &version);
+                response.set_header("Connection", "keep-alive");
+                response
+            })
+            .get("/big", |_| {
+                HttpResponse::new_binary("200", None, Some(vec![7u8; 1024 * 1024]))
+            })
+            .post("/echo", |request: HttpRequest| {
+                let body = format!(
+                    "{} {} {}",
+                    request.body().len(),
+                    request.header("X-Custom").unwrap_or(""),
+                    request.header("Cookie").unwrap_or("")
+                );
+                HttpResponse::new("200", None, Some(body))
+            })
+            .post("/raw", |request: HttpRequest| {
+                let length = request.header("Content-Length").unwrap_or("").to_string();
+                let mut response =
+                    HttpResponse::new_binary("200", None, Some(request.body_bytes().to_vec()));
+                response.set_header("X-Request-Length", &length);
+                response
+            })
+            .get("/stream", |_| {
+                let mut response = HttpResponse::from_chunks("200", None, ["a", "bc"]);
+                response.set_trailers(|| {
+                    HashMap::from([("x-checksum".to_string(), "abc".to_string())])
+                });
+                response
+            })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_h2c_multiplexing_flow_control_and_trailers() {
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router())
+            .limits(Limits::new().body(512 * 1024))
+            .spawn()
+            .await
+            .unwrap();
+        let client = connect(handle.local_addr()).await;
+
+        // 同一个连接上慢请求不会阻塞后面的快请求
+        let start = Instant::now();
+        let slow = async {
+            let result = send(&client, get("/slow"), b"").await;
+            (result, start.elapsed())
+        };
+        let fast = async {
+            let result = send(&client, get("/fast"), b"").await;
+            (result, start.elapsed())
+        };
+        let (((slow, slow_body, _), slow_elapsed), ((fast, fast_body, _), fast_elapsed)) =
+            tokio::join!(slow, fast);
+        assert_eq!(slow.status(), 200);
+        assert_eq!(slow_body, b"slow");
+        assert_eq!(fast_body, b"fast");
+        assert!(fast_elapsed < slow_elapsed);
+        assert_eq!(fast.headers()["x-version"], "HTTP/2");
+        assert_eq!(fast.headers()["content-length"], "4");
+        // 逐跳响应头不能出现在 HTTP/2 中
+        assert!(!fast.headers().contains_key("connection"));
+
+        // 超过初始流量控制窗口的响应体和请求体
+        let (big, big_body, _) = send(&client, get("/big"), b"").await;
+        assert_eq!(big.status(), 200);
+        assert_eq!(big_body.len(), 1024 * 1024);
+        let upload = vec![b'x'; 200 * 1024];
+        for _ in 0..2 {
+            // 第二次请求的头部通过 HPACK 动态表编码
+            let request = Request::post("http://localhost/echo")
+                .header("x-custom", "value")
+                .header("cookie", "a=1")
+                .header("cookie", "b=2")
+                .body(())
+                .unwrap();
+            let (response, body, _) = send(&client, request, &upload).await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(body, b"204800 value a=1; b=2");
+        }
+        // 非 UTF-8 的请求体原样交给处理函数，Content-Length 与实际字节数一致
+        let binary: Vec<u8> = (0..=255u8).rev().collect();
+        let request = Request::post("http://localhost/raw").body(()).unwrap();
+        let (response, body, _) = send(&client, request, &binary).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body, binary);
+        assert_eq!(response.headers()["x-request-length"], "256");
+        let too_large = vec![b'x'; 600 * 1024];
+        let request = Request::post("http://localhost/echo").body(()).unwrap();
+        let (response, _, _) = send(&client, request, &too_large).await;
+        assert_eq!(response.status(), 413);
+
+        let (response, body, trailers) = send(&client, get("/stream"), b"").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body, b"abc");
+        assert_eq!(trailers.unwrap()["x-checksum"], "abc");
+
+        let (response, _, _) = send(&client, get("/missing"), b"").await;
+        assert_eq!(response.status(), 404);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_access_log_covers_http1_and_h2() {
+        let path = env::temp_dir().join(format!("web-server-h2-access-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let access_log = AccessLog::file(&path)
+            .unwrap()
+            .format(LogFormat::Custom("\"%r\" %>s %b".to_string()));
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router())
+            .limits(Limits::new().request_line(32))
+            .access_log(access_log)
+            .spawn()
+            .await
+            .unwrap();
+
+        let client = connect(handle.local_addr()).await;
+        let (response, _, _) = send(&client, get("/fast"), b"").await;
+        assert_eq!(response.status(), 200);
+        let (response, body, _) = send(&client, get("/stream"), b"").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body, b"abc");
+        drop(client);
+
+        for request in [
+            format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64)),
+            "GET /fast HTTP/1.1\r\nConnection: close\r\n\r\n".to_string(),
+        ] {
+            let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+        }
+        handle.shutdown().await;
+
+        let mut lines: Vec<String> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        // HTTP/2 的流并发处理，日志的先后顺序不固定
+        lines[..2].sort();
+        assert_eq!(
+            lines,
+            [
+                "\"GET /fast HTTP/2\" 200 4",
+                "\"GET /stream HTTP/2\" 200 3",
+                "\"-\" 414 12",
+                "\"GET /fast HTTP/1.1\" 200 4",
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_expect_checks_run_before_reading_body() {
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router())
+            .spawn()
+            .await
+            .unwrap();
+        let client = connect(handle.local_addr()).await;
+        let post = |path: &str, expect: &str| {
+            Request::post(format!("http://localhost{path}"))
+                .header("expect", expect)
+                .body(())
+                .unwrap()
+        };
+
+        // 检查没有通过时不等待请求体，直接返回最终响应并重置流
+        for (path, expect, status) in [
+            ("/missing", "100-continue", 404),
+            ("/echo", "something-else", 417),
+        ] {
+            let mut sender = client.clone().ready().await.unwrap();
+            let (response, _stream) = sender.send_request(post(path, expect), false).unwrap();
+            let response = tokio::time::timeout(Duration::from_secs(2), response)
+                .await
+                .expect("response should not wait for the body")
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        // 检查通过时读取请求体，连接仍然可用
+        let (response, body, _) = send(&client, post("/echo", "100-continue"), b"hello").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body, b"5  ");
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_upgrade_responses_on_http1_and_h2() {
+        use crate::sse::{Event, EventStream};
+
+        let router = Router::new()
+            .get("/events", |request: HttpRequest| {
+                let (sender, response) = EventStream::new(&request).into_response();
+                for id in 1..=2 {
+                    let _ = sender.send(Event::new(format!("step {id}")).id(&id.to_string()));
+                }
+                // 发送端 drop 后服务器结束响应
+                response
+            })
+            .get("/switch", |_| {
+                let headers = HashMap::from([("Upgrade", "test")]);
+                HttpResponse::switching_protocols(Some(headers), |_| {})
+            });
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .spawn()
+            .await
+            .unwrap();
+        let expected = "id: 1\ndata: step 1\n\nid: 2\ndata: step 2\n\n";
+
+        // HTTP/1.1 上的 SSE 以关闭连接结束
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with(expected));
+
+        // HTTP/2 上回调写出的数据作为响应体
+        let client = connect(handle.local_addr()).await;
+        let (response, body, _) = send(&client, get("/events"), b"").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/event-stream; charset=utf-8"
+        );
+        assert_eq!(body, expected.as_bytes());
+
+        // HTTP/2 中没有协议切换
+        let (response, _, _) = send(&client, get("/switch"), b"").await;
+        assert_eq!(response.status(), 501);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_h2_over_tls_is_negotiated_with_alpn() {
+        let dir = env::temp_dir().join(format!("web-server-h2-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+        fs::write(dir.join("cert.key"), signing_key.serialize_pem()).unwrap();
+        let tls = TlsConfig::new(dir.join("cert.pem"), dir.join("cert.key"));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let connect_tls = |addr: SocketAddr, alpn: &[&[u8]]| {
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let mut config = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth();
+            config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+            let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+            async move {
+                let tcp = TcpStream::connect(addr).await.unwrap();
+                let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+                connector.connect(name, tcp).await.unwrap()
+            }
+        };
+
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router())
+            .tls(tls.clone())
+            .spawn()
+            .await
+            .unwrap();
+        let addr = handle.local_addr();
+        let stream = connect_tls(addr, &[b"h2", b"http/1.1"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let (response, body, _) = send(&client, get("/fast"), b"").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body, b"fast");
+        assert_eq!(response.headers()["x-version"], "HTTP/2");
+
+        // 只支持 HTTP/1.1 的客户端继续使用 HTTP/1.1
+        let mut stream = connect_tls(addr, &[b"http/1.1"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        stream
+            .write_all(b"GET /fast HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("fast"));
+        handle.shutdown().await;
+
+        // 关闭 HTTP/2 后不再协商 h2，即使 ALPN 中明确列出
+        let handle = AsyncHttpServer::new("127.0.0.1", 0, ".")
+            .router(router())
+            .tls(tls.alpn(&["h2", "http/1.1"]))
+            .http2(false)
+            .spawn()
+            .await
+            .unwrap();
+        let stream = connect_tls(handle.local_addr(), &[b"h2", b"http/1.1"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        handle.shutdown().await;
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,12 +1,92 @@
-use crate::route::Route;
-use log::{error, info};
+use crate::accesslog::AccessLog;
+use crate::event::EventLoop;
+use crate::handler::{Handler, StaticResourceHandler};
+use crate::logfile;
+use crate::metrics::MetricsHandler;
+use crate::middleware::RequestLogger;
+use crate::route::{Route, Router};
+use crate::shutdown::{self, ServerState};
+use crate::timeout::Timeouts;
+use crate::tls::{self, Hsts, HttpsRedirect, Stream, Tls, TlsConfig};
+use http::httpresponse::HttpResponse;
+use http::parser::Limits;
+use log::{error, info, warn};
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use std::{net::TcpListener, path::Path};
-use threadpool::threadpool::ThreadPool;
+use threadpool::threadpool::{Builder as PoolBuilder, ExecuteError, ThreadPool};
+
+#[derive(Debug)]
+pub enum ServerError {
+    /// 绑定监听地址失败
+    Bind {
+        addr: String,
+        source: io::Error,
+    },
+    /// 工作目录不存在或不是目录
+    WorkDir(String),
+    /// 证书或私钥无法读取，或者 TLS 配置不可用
+    Tls(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Bind { addr, source } => write!(f, "Failed to bind to {addr}: {source}"),
+            ServerError::WorkDir(dir) => write!(f, "Work directory {dir} does not exist"),
+            ServerError::Tls(message) => write!(f, "TLS error: {message}"),
+            ServerError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::Bind { source, .. } => Some(source),
+            ServerError::WorkDir(_) | ServerError::Tls(_) => None,
+            ServerError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> Self {
+        ServerError::Io(e)
+    }
+}
+
+/// 处理连接的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// 每个连接占用一个工作线程，阻塞读写
+    #[default]
+    Threaded,
+    /// 所有连接由一个 epoll 事件循环非阻塞读写，只把完整的请求交给工作线程
+    Event,
+}
 
 pub struct HttpServer<'a> {
     host: &'a str,
     port: u16,
     work_dir: &'a str,
+    router: Arc<Router>,
+    drain_timeout: Duration,
+    handle_signals: bool,
+    pool: PoolBuilder,
+    metrics_path: Option<String>,
+    engine: Engine,
+    timeouts: Timeouts,
+    limits: Limits,
+    tls: Option<TlsConfig>,
+    hsts: Option<Hsts>,
+    redirect_port: Option<u16>,
+    access_log: Option<Arc<AccessLog>>,
 }
 
 impl<'a> HttpServer<'a> {
@@ -15,39 +95,1150 @@ impl<'a> HttpServer<'a> {
             host,
             port,
             work_dir,
+            router: Arc::new(
+                Router::new()
+                    .middleware(RequestLogger)
+                    .fallback(StaticResourceHandler::new(work_dir)),
+            ),
+            drain_timeout: Duration::from_secs(30),
+            handle_signals: false,
+            pool: ThreadPool::builder()
+                .min_workers(4)
+                .max_workers(16)
+                .queue_capacity(256)
+                .name_prefix("web-worker"),
+            metrics_path: None,
+            engine: Engine::Threaded,
+            timeouts: Timeouts::new(),
+            limits: Limits::new(),
+            tls: None,
+            hsts: None,
+            redirect_port: None,
+            access_log: None,
         }
     }
 
-    pub fn run(&self) {
-        let listener = match TcpListener::bind(format!("{}:{}", self.host, self.port)) {
-            Ok(listener) => listener,
-            Err(e) => {
-                error!("Failed to bind to port {}: {}", self.port, e);
-                std::process::exit(1);
+    /// 使用 HTTPS 提供服务，目前只有 [`Engine::Threaded`] 支持
+    ///
+    /// 开启 [`handle_signals`](Self::handle_signals) 时收到 `SIGHUP` 会重新读取证书。
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// HTTPS 响应中加上 `Strict-Transport-Security`，明文 HTTP 响应不会加
+    pub fn hsts(mut self, hsts: Hsts) -> Self {
+        self.hsts = Some(hsts);
+        self
+    }
+
+    /// 在 `port` 上额外监听明文 HTTP，所有请求重定向到对应的 `https://` 地址，需要同时配置 TLS
+    ///
+    /// `port` 为 `0` 时由系统分配端口，通过 [`ServerHandle::redirect_addr`] 获取。
+    pub fn redirect_http(mut self, port: u16) -> Self {
+        self.redirect_port = Some(port);
+        self
+    }
+
+    /// 请求行、请求头和请求体的大小上限，超过时分别返回 `414`、`431` 和 `413`
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// 连接的空闲、读请求和写响应超时
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// 选择连接引擎，默认为 [`Engine::Threaded`]
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// 常驻工作线程数，最大线程数小于它时一起调整
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.pool = self.pool.min_workers(workers);
+        self
+    }
+
+    /// 连接排队时最多扩展到的工作线程数
+    pub fn max_workers(mut self, max_workers: usize) -> Self {
+        self.pool = self.pool.max_workers(max_workers);
+        self
+    }
+
+    /// 等待工作线程处理的连接数上限，超过后新连接直接返回 `503`
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.pool = self.pool.queue_capacity(queue_capacity);
+        self
+    }
+
+    /// 完全自定义线程池配置，例如空闲回收时间和线程栈大小
+    pub fn thread_pool(mut self, pool: PoolBuilder) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// 使用自定义路由器替换默认的静态资源路由
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Arc::new(router);
+        self
+    }
+
+    /// 停止时等待正在处理的请求完成的最长时间，超时后强制关闭剩余连接
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// 收到 SIGINT/SIGTERM 时优雅停止服务器，收到 SIGUSR1 时重新打开日志文件
+    pub fn handle_signals(mut self, handle_signals: bool) -> Self {
+        self.handle_signals = handle_signals;
+        self
+    }
+
+    /// 每个响应发送完后写一行访问日志，包括客户端地址、状态码、响应体字节数和耗时
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// 在指定路径上提供线程池和连接统计数据，格式为 Prometheus 文本格式
+    pub fn metrics_path(mut self, path: &str) -> Self {
+        self.metrics_path = Some(path.to_string());
+        self
+    }
+
+    /// 在当前线程运行服务器，直到服务器停止才返回
+    pub fn run(&self) -> Result<(), ServerError> {
+        self.start()?.serve();
+        Ok(())
+    }
+
+    /// 在后台线程运行服务器，返回的句柄可以查询实际监听地址并关闭服务器
+    ///
+    /// 端口设置为 `0` 时由系统分配端口，通过 [`ServerHandle::local_addr`] 获取。
+    pub fn spawn(&self) -> Result<ServerHandle, ServerError> {
+        let server = self.start()?;
+        let local_addr = server.local_addr;
+        let state = Arc::clone(&server.state);
+        let tls = server.tls.clone();
+        let redirect_addr = server.redirect.as_ref().map(|redirect| redirect.local_addr);
+        let thread = thread::Builder::new()
+            .name("web-accept".to_string())
+            .spawn(move || server.serve())?;
+
+        Ok(ServerHandle {
+            local_addr,
+            state,
+            tls,
+            redirect_addr,
+            thread: Some(thread),
+        })
+    }
+
+    /// 绑定端口并准备好运行服务器需要的所有状态
+    fn start(&self) -> Result<Server, ServerError> {
+        let tls = match &self.tls {
+            Some(_) if self.engine != Engine::Threaded => {
+                return Err(ServerError::Tls(
+                    "TLS is only supported by the threaded engine".to_string(),
+                ));
             }
+            // 阻塞模式只支持 HTTP/1.x，不能让客户端协商 h2
+            Some(config) => Some(Arc::new(config.clone().without_alpn("h2").build()?)),
+            None if self.redirect_port.is_some() => {
+                return Err(ServerError::Tls(
+                    "Redirecting HTTP to HTTPS requires TLS".to_string(),
+                ));
+            }
+            None => None,
+        };
+        let listener = self.bind()?;
+        let local_addr = listener.local_addr()?;
+        let state = Arc::new(ServerState::new(local_addr));
+        if self.handle_signals {
+            shutdown::watch_signals(Arc::clone(&state))?;
+            logfile::watch_reopen(Arc::clone(&state))?;
+            if let Some(tls) = &tls {
+                tls::watch_reload(Arc::clone(tls), Arc::clone(&state))?;
+            }
+        }
+        let pool = self.pool.clone().build();
+
+        let handler: Arc<dyn Handler> = match &self.metrics_path {
+            Some(path) => Arc::new(MetricsHandler::new(
+                path,
+                pool.monitor(),
+                Arc::clone(&state),
+                self.router.clone(),
+            )),
+            None => self.router.clone(),
+        };
+
+        let settings = ConnectionSettings {
+            timeouts: self.timeouts,
+            limits: self.limits,
+            tls: None,
+            hsts: None,
+            access_log: self.access_log.clone(),
         };
+        let redirect = match self.redirect_port {
+            Some(port) => Some(self.bind_redirect(port, local_addr.port(), settings.clone())?),
+            None => None,
+        };
+        let acceptor = match self.engine {
+            Engine::Threaded => Acceptor::Threaded(
+                listener,
+                ConnectionSettings {
+                    tls: tls.clone(),
+                    hsts: tls.as_ref().and(self.hsts.as_ref()).map(Hsts::header_value),
+                    ..settings
+                },
+            ),
+            Engine::Event => Acceptor::Event(EventLoop::new(
+                listener,
+                Arc::clone(&handler),
+                Arc::clone(&state),
+                self.timeouts,
+                self.limits,
+                self.access_log.clone(),
+            )?),
+        };
+
+        Ok(Server {
+            acceptor,
+            local_addr,
+            handler,
+            state,
+            tls,
+            redirect,
+            pool,
+            drain_timeout: self.drain_timeout,
+        })
+    }
+
+    /// 绑定把明文 HTTP 重定向到 HTTPS 端口 `https_port` 的监听端口
+    fn bind_redirect(
+        &self,
+        port: u16,
+        https_port: u16,
+        settings: ConnectionSettings,
+    ) -> Result<Redirect, ServerError> {
+        let addr = format!("{}:{}", self.host, port);
+        let listener =
+            TcpListener::bind(&addr).map_err(|source| ServerError::Bind { addr, source })?;
+        let local_addr = listener.local_addr()?;
+        info!("Redirecting http://{local_addr} to HTTPS");
+        Ok(Redirect {
+            listener,
+            local_addr,
+            state: Arc::new(ServerState::new(local_addr)),
+            handler: Arc::new(HttpsRedirect::new(https_port, self.host)),
+            settings,
+        })
+    }
 
-        self.set_work_dir();
+    fn bind(&self) -> Result<TcpListener, ServerError> {
+        self.check_work_dir()?;
 
-        let pool = ThreadPool::new(4);
+        let addr = format!("{}:{}", self.host, self.port);
+        let listener =
+            TcpListener::bind(&addr).map_err(|source| ServerError::Bind { addr, source })?;
 
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
         info!(
-            "Server is running on http://{}:{} in {}",
-            self.host, self.port, self.work_dir
+            "Server is running on {scheme}://{} in {}",
+            listener.local_addr()?,
+            self.work_dir
         );
-        for stream in listener.incoming() {
-            pool.execute(move || {
-                let connection = stream.unwrap();
-                Route::route(connection);
+        Ok(listener)
+    }
+
+    fn check_work_dir(&self) -> Result<(), ServerError> {
+        if !Path::new(self.work_dir).is_dir() {
+            return Err(ServerError::WorkDir(self.work_dir.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// 后台运行的服务器句柄，drop 时会关闭服务器
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    state: Arc<ServerState>,
+    tls: Option<Arc<Tls>>,
+    redirect_addr: Option<SocketAddr>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// 把明文 HTTP 重定向到 HTTPS 的监听地址，没有开启时为 `None`
+    pub fn redirect_addr(&self) -> Option<SocketAddr> {
+        self.redirect_addr
+    }
+
+    /// 重新读取 HTTPS 证书，之后的新连接使用新证书；读取失败时继续使用原来的证书
+    pub fn reload_tls(&self) -> Result<(), ServerError> {
+        match &self.tls {
+            Some(tls) => tls.reload(),
+            None => Err(ServerError::Tls("TLS is not enabled".to_string())),
+        }
+    }
+
+    /// 停止接收新连接，等待正在处理的请求完成后回收工作线程
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.state.stop();
+        if thread.join().is_err() {
+            error!("Accept thread panicked");
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 阻塞模式下每个连接共用的配置
+#[derive(Clone)]
+struct ConnectionSettings {
+    timeouts: Timeouts,
+    limits: Limits,
+    tls: Option<Arc<Tls>>,
+    /// HTTPS 响应中 `Strict-Transport-Security` 的值
+    hsts: Option<String>,
+    access_log: Option<Arc<AccessLog>>,
+}
+
+/// 把明文 HTTP 请求重定向到 HTTPS 的监听端口，和主监听端口共用线程池
+struct Redirect {
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    state: Arc<ServerState>,
+    handler: Arc<dyn Handler>,
+    settings: ConnectionSettings,
+}
+
+enum Acceptor {
+    Threaded(TcpListener, ConnectionSettings),
+    Event(EventLoop),
+}
+
+struct Server {
+    acceptor: Acceptor,
+    local_addr: SocketAddr,
+    handler: Arc<dyn Handler>,
+    state: Arc<ServerState>,
+    tls: Option<Arc<Tls>>,
+    redirect: Option<Redirect>,
+    pool: ThreadPool,
+    drain_timeout: Duration,
+}
+
+impl Server {
+    fn serve(self) {
+        let Server {
+            acceptor,
+            handler,
+            state,
+            redirect,
+            pool,
+            drain_timeout,
+            ..
+        } = self;
+
+        let aborted = thread::scope(|scope| {
+            let redirect = redirect.and_then(|redirect| {
+                let redirect_state = Arc::clone(&redirect.state);
+                let pool = &pool;
+                let spawned = thread::Builder::new()
+                    .name("web-redirect".to_string())
+                    .spawn_scoped(scope, move || {
+                        serve_threaded(
+                            redirect.listener,
+                            redirect.settings,
+                            &redirect.handler,
+                            &redirect.state,
+                            pool,
+                            drain_timeout,
+                        )
+                    });
+                match spawned {
+                    Ok(thread) => Some((redirect_state, thread)),
+                    Err(e) => {
+                        error!("Failed to start redirect listener: {e}");
+                        None
+                    }
+                }
             });
+
+            let mut aborted = match acceptor {
+                Acceptor::Threaded(listener, settings) => {
+                    serve_threaded(listener, settings, &handler, &state, &pool, drain_timeout)
+                }
+                Acceptor::Event(event_loop) => event_loop.run(&pool, drain_timeout),
+            };
+            // 主监听端口停止后再停止重定向端口
+            if let Some((redirect_state, thread)) = redirect {
+                redirect_state.stop();
+                match thread.join() {
+                    Ok(redirect_aborted) => aborted += redirect_aborted,
+                    Err(_) => error!("Redirect thread panicked"),
+                }
+            }
+            aborted
+        });
+
+        // pool drop 时会等待所有工作线程结束
+        let monitor = pool.monitor();
+        drop(pool);
+        state.log_summary(aborted);
+        info!("Thread pool: {}", monitor.stats());
+    }
+}
+
+/// 每个连接交给一个工作线程处理，返回排空超时后被强制关闭的连接数
+fn serve_threaded(
+    listener: TcpListener,
+    settings: ConnectionSettings,
+    handler: &Arc<dyn Handler>,
+    state: &Arc<ServerState>,
+    pool: &ThreadPool,
+    drain_timeout: Duration,
+) -> usize {
+    for stream in listener.incoming() {
+        if state.is_stopping() {
+            break;
+        }
+        let connection = match stream {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        // 任务被拒绝时闭包已被消耗，提前复制一份用于返回 503
+        let overflow = connection.try_clone();
+        let handler = Arc::clone(handler);
+        let worker_state = Arc::clone(state);
+        let settings = settings.clone();
+        let https = settings.tls.is_some();
+        let result = pool.try_execute(move || {
+            // TLS 握手放在工作线程上，避免慢速客户端阻塞 accept 循环
+            let connection = match &settings.tls {
+                Some(tls) => match tls.accept(connection) {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        error!("Failed to start TLS session: {e}");
+                        return;
+                    }
+                },
+                None => Stream::Plain(connection),
+            };
+            Route::route(
+                connection,
+                handler.as_ref(),
+                &worker_state,
+                &settings.timeouts,
+                &settings.limits,
+                settings.hsts.as_deref(),
+                settings.access_log.as_deref(),
+            );
+        });
+        match (result, overflow) {
+            (Ok(()), _) => {}
+            (Err(ExecuteError::Full), Ok(stream)) => {
+                warn!(
+                    "Thread pool is saturated, rejecting connection: {}",
+                    pool.stats()
+                );
+                // HTTPS 连接还没有握手，无法发送 503，只能直接关闭
+                if !https {
+                    reject_overloaded(stream);
+                }
+            }
+            (Err(e), _) => error!("Failed to dispatch connection: {e}"),
+        }
+    }
+    // 不再接受新连接
+    drop(listener);
+
+    // 空闲的 keep-alive 连接直接关闭，正在处理的请求在超时前处理完
+    state.close_idle();
+    let mut aborted = 0;
+    if !state.wait_drained(drain_timeout) {
+        aborted = state.close_all();
+        warn!("Drain timeout exceeded, aborting {aborted} connections");
+    }
+    aborted
+}
+
+/// 线程池已满时返回的响应
+pub(crate) fn overloaded_response() -> HttpResponse<'static> {
+    let mut response = HttpResponse::new("503", None, Some("Service Unavailable".to_string()));
+    response.set_header("Retry-After", "1");
+    response.set_header("Connection", "close");
+    response
+}
+
+/// 线程池已满时在 accept 线程上直接返回 `503`，不读取请求
+fn reject_overloaded(mut stream: TcpStream) {
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    if let Err(e) = overloaded_response().send_response(&mut stream) {
+        warn!("Error sending 503 response: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::{BasicAuth, Chain};
+    use crate::tls::TlsVersion;
+    use http::httprequest::HttpRequest;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::{env, fs};
+
+    #[test]
+    fn test_spawn_serves_on_ephemeral_port_and_shuts_down() {
+        let router = Router::new().get("/ping", |_| {
+            http::httpresponse::HttpResponse::new("200", None, Some("pong".to_string()))
+        });
+        let server = HttpServer::new("127.0.0.1", 0, ".").router(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+        assert_ne!(addr.port(), 0);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("pong"));
+
+        handle.shutdown();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_run_reports_errors_instead_of_exiting() {
+        let server = HttpServer::new("127.0.0.1", 0, "/definitely/not/here");
+        assert!(matches!(server.run(), Err(ServerError::WorkDir(_))));
+
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let server = HttpServer::new("127.0.0.1", port, ".");
+        assert!(matches!(server.spawn(), Err(ServerError::Bind { .. })));
+    }
+
+    #[test]
+    fn test_keep_alive_and_graceful_shutdown_finishes_in_flight_requests() {
+        let router = Router::new()
+            .get("/ping", |_| {
+                http::httpresponse::HttpResponse::new("200", None, Some("pong".to_string()))
+            })
+            .get("/slow", |_| {
+                thread::sleep(Duration::from_millis(300));
+                http::httpresponse::HttpResponse::new("200", None, Some("done".to_string()))
+            });
+        let server = HttpServer::new("127.0.0.1", 0, ".").router(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+
+        // 同一个连接上连续发送两个请求
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut buffer = [0u8; 1024];
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let n = stream.read(&mut buffer).unwrap();
+            let response = String::from_utf8_lossy(&buffer[..n]);
+            assert!(response.contains("Connection: keep-alive"));
+            assert!(response.ends_with("pong"));
+        }
+
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        // 正在处理的请求完成并以 Connection: close 结束，空闲连接被关闭
+        let mut response = String::new();
+        slow.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("done"));
+        assert_eq!(stream.read(&mut buffer).unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn test_saturated_pool_answers_503() {
+        let router = Router::new().get("/slow", |_| {
+            thread::sleep(Duration::from_millis(300));
+            HttpResponse::new("200", None, Some("done".to_string()))
+        });
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .workers(1)
+            .max_workers(1)
+            .queue_capacity(1);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let _queued = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(response.contains("Retry-After: 1"));
+
+        let mut response = String::new();
+        busy.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("done"));
+    }
+
+    #[test]
+    fn test_handler_panic_becomes_500() {
+        let router = Router::new()
+            .get("/boom", |_| panic!("handler failed"))
+            .get("/ping", |_| {
+                HttpResponse::new("200", None, Some("pong".to_string()))
+            });
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .workers(1)
+            .max_workers(1);
+        let handle = server.spawn().unwrap();
+
+        for (path, expected) in [("/boom", "HTTP/1.1 500"), ("/ping", "HTTP/1.1 200")] {
+            let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with(expected));
+        }
+    }
+
+    #[test]
+    fn test_slow_clients_get_408_and_idle_connections_close() {
+        let router = Router::new().post("/upload", |_| HttpResponse::new("200", None, None));
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .timeouts(
+                Timeouts::new()
+                    .idle(Duration::from_millis(200))
+                    .header_read(Duration::from_millis(300))
+                    .min_body_rate(1000),
+            );
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+
+        // 空闲连接超时后直接关闭，不返回响应
+        let mut idle = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        idle.read_to_string(&mut response).unwrap();
+        assert!(response.is_empty());
+
+        // 请求头迟迟不发完
+        let mut slow_header = TcpStream::connect(addr).unwrap();
+        slow_header.write_all(b"POST /upload HTTP/1.1\r\n").unwrap();
+        let start = std::time::Instant::now();
+        let mut response = String::new();
+        slow_header.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // 请求体传输速率过低
+        let mut slow_body = TcpStream::connect(addr).unwrap();
+        slow_body
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 100000\r\n\r\nabc")
+            .unwrap();
+        let mut response = String::new();
+        slow_body.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+
+    #[test]
+    fn test_oversized_requests_are_rejected() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let router = Router::new().post("/upload", |request: HttpRequest| {
+                HttpResponse::new("200", None, Some(request.body().to_string()))
+            });
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine)
+                .limits(
+                    Limits::new()
+                        .request_line(64)
+                        .headers(4)
+                        .header_bytes(256)
+                        .body(16),
+                );
+            let handle = server.spawn().unwrap();
+            let send = |request: &str| {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            };
+
+            let long_path = format!("POST /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+            assert!(send(&long_path).starts_with("HTTP/1.1 414 URI Too Long"));
+            let many_headers = format!("POST /upload HTTP/1.1\r\n{}\r\n", "X-A: 1\r\n".repeat(5));
+            assert!(send(&many_headers).starts_with("HTTP/1.1 431"));
+            let big_header = format!(
+                "POST /upload HTTP/1.1\r\nCookie: {}\r\n\r\n",
+                "c".repeat(300)
+            );
+            assert!(send(&big_header).starts_with("HTTP/1.1 431"));
+            // 声明的长度过大时不等待请求体
+            let response = send("POST /upload HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 413 Content Too Large"));
+            assert!(response.contains("Connection: close"));
+            let chunked = "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                           10\r\n0123456789abcdef\r\n1\r\nx\r\n0\r\n\r\n";
+            assert!(send(chunked).starts_with("HTTP/1.1 413"));
+
+            // 限制以内的请求正常处理
+            let response = send(
+                "POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Length: 5\r\n\r\nhello",
+            );
+            assert!(response.starts_with("HTTP/1.1 200"));
+            assert!(response.ends_with("hello"));
         }
     }
 
-    fn set_work_dir(&self) {
-        if !Path::new(self.work_dir).exists() {
-            error!("Work directory {} does not exist", self.work_dir);
-            std::process::exit(1);
+    #[test]
+    fn test_chunked_trailers_and_ambiguous_framing() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let router = Router::new().post("/upload", |request: HttpRequest| {
+                let digest = request.trailer("Digest").unwrap_or("none");
+                HttpResponse::new("200", None, Some(format!("{} {digest}", request.body())))
+            });
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine);
+            let handle = server.spawn().unwrap();
+            let send = |request: &str| {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            };
+
+            let response = send(
+                "POST /upload HTTP/1.1\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 5;ext=1\r\nhello\r\n0\r\nDigest: abc\r\n\r\n",
+            );
+            assert!(response.ends_with("hello abc"), "{response}");
+
+            // 同时带有 Content-Length 和 Transfer-Encoding 的请求可能被用来夹带请求
+            let response = send(
+                "POST /upload HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 0\r\n\r\nGET /admin HTTP/1.1\r\n\r\n",
+            );
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+            assert_eq!(response.matches("HTTP/1.1").count(), 1);
+            let response =
+                send("POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nnot-hex\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 400"));
+        }
+    }
+
+    #[test]
+    fn test_binary_request_bodies_round_trip() {
+        // 不是合法 UTF-8 的字节，经过文本转换会被替换为 U+FFFD
+        let payload: Vec<u8> = vec![0x00, 0xff, 0xfe, 0x80, b'\r', b'\n', 0xc3, 0x28];
+        for engine in [Engine::Threaded, Engine::Event] {
+            let router = Router::new().post("/echo", |request: HttpRequest| {
+                HttpResponse::new_binary("200", None, Some(request.body_bytes().to_vec()))
+            });
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine);
+            let handle = server.spawn().unwrap();
+            let send = |head: &str, body: &[u8]| {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.write_all(head.as_bytes()).unwrap();
+                stream.write_all(body).unwrap();
+                let mut response = Vec::new();
+                stream.read_to_end(&mut response).unwrap();
+                response
+            };
+
+            let head = format!(
+                "POST /echo HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+                payload.len()
+            );
+            let response = send(&head, &payload);
+            assert!(response.ends_with(&payload), "{engine:?}: {response:?}");
+
+            let mut chunked = format!("{:x}\r\n", payload.len()).into_bytes();
+            chunked.extend_from_slice(&payload);
+            chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+            let response = send(
+                "POST /echo HTTP/1.1\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n",
+                &chunked,
+            );
+            assert!(response.ends_with(&payload), "{engine:?}: {response:?}");
+        }
+    }
+
+    #[test]
+    fn test_head_requests_use_get_routes_without_body() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let router = Router::new().get("/hello", |_| {
+                HttpResponse::new("200", None, Some("hello".to_string()))
+            });
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine);
+            let handle = server.spawn().unwrap();
+            let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+            // 同一个连接上的下一个响应不能被 HEAD 响应的响应体打乱
+            stream
+                .write_all(
+                    b"HEAD /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, rest) = response.split_once("\r\n\r\n").unwrap();
+            assert!(
+                head.starts_with("HTTP/1.1 200 OK"),
+                "{engine:?}: {response}"
+            );
+            assert!(head.contains("Content-Length: 5"));
+            assert!(
+                rest.starts_with("HTTP/1.1 200 OK"),
+                "{engine:?}: {response}"
+            );
+            assert!(rest.ends_with("\r\n\r\nhello"));
+            handle.shutdown();
+        }
+    }
+
+    #[test]
+    fn test_streaming_responses() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let router = Router::new().get("/report", |_| {
+                let lines = (1..=3).map(|i| format!("line {i}\n"));
+                let mut response = HttpResponse::from_chunks("200", None, lines);
+                response.set_trailers(|| HashMap::from([("X-Lines".to_string(), "3".to_string())]));
+                response
+            });
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine);
+            let handle = server.spawn().unwrap();
+            let send = |request: &str| {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            };
+
+            let response = send("GET /report HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(response.contains("Transfer-Encoding: chunked"));
+            assert!(!response.contains("Content-Length"));
+            assert!(response.ends_with(
+                "7\r\nline 1\n\r\n7\r\nline 2\n\r\n7\r\nline 3\n\r\n0\r\nX-Lines: 3\r\n\r\n"
+            ));
+
+            // HTTP/1.0 客户端收到原始数据，连接关闭表示响应结束
+            let response = send("GET /report HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+            assert!(response.contains("Connection: close"));
+            assert!(!response.contains("Transfer-Encoding"));
+            assert!(response.ends_with("\r\n\r\nline 1\nline 2\nline 3\n"));
+        }
+    }
+
+    #[test]
+    fn test_expect_100_continue() {
+        // 设置统计路径时路由器外面多一层处理器，检查同样要交给路由器
+        let cases = [Engine::Threaded, Engine::Event]
+            .into_iter()
+            .flat_map(|engine| [(engine, false), (engine, true)]);
+        for (engine, metrics) in cases {
+            let private = Chain::new(|_| HttpResponse::new("200", None, None))
+                .with(BasicAuth::new("test").user("admin", "secret"));
+            let router = Router::new()
+                .post("/upload", |request: HttpRequest| {
+                    HttpResponse::new("200", None, Some(request.body().to_string()))
+                })
+                .post("/private", private);
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine)
+                .limits(Limits::new().body(1024));
+            let server = if metrics {
+                server.metrics_path("/metrics")
+            } else {
+                server
+            };
+            let handle = server.spawn().unwrap();
+            // 只发送请求头，读取服务器在收到请求体之前返回的响应
+            let send_head = |path: &str, expect: &str, length: usize| {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(2)))
+                    .unwrap();
+                let head = format!(
+                    "POST {path} HTTP/1.1\r\nExpect: {expect}\r\nContent-Length: {length}\r\n\r\n"
+                );
+                stream.write_all(head.as_bytes()).unwrap();
+                let mut response = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !response.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    assert!(n > 0, "connection closed without a response");
+                    response.extend_from_slice(&buf[..n]);
+                }
+                (stream, String::from_utf8(response).unwrap())
+            };
+
+            let (mut stream, interim) = send_head("/upload", "100-continue", 5);
+            assert_eq!(interim, "HTTP/1.1 100 Continue\r\n\r\n");
+            stream.write_all(b"hello").unwrap();
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).unwrap();
+            let response = String::from_utf8_lossy(&buf[..n]);
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.ends_with("hello"));
+
+            // 检查没有通过时直接返回最终响应，不等待请求体
+            let (_, response) = send_head("/missing", "100-continue", 5);
+            assert!(response.starts_with("HTTP/1.1 404"));
+            let (_, response) = send_head("/private", "100-continue", 5);
+            assert!(response.starts_with("HTTP/1.1 401"));
+            let (_, response) = send_head("/upload", "100-continue", 4096);
+            assert!(response.starts_with("HTTP/1.1 413"));
+            let (_, response) = send_head("/upload", "something-else", 5);
+            assert!(response.starts_with("HTTP/1.1 417 Expectation Failed"));
+            assert!(response.contains("Connection: close"));
         }
-        std::env::set_current_dir(self.work_dir).unwrap();
+    }
+
+    /// 生成自签名证书并写入 `{name}.pem` 和 `{name}.key`，返回客户端需要信任的证书
+    fn write_cert(dir: &Path, name: &str, server_name: &str) -> CertificateDer<'static> {
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec![server_name.to_string()]).unwrap();
+        fs::write(dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+        fs::write(dir.join(format!("{name}.key")), signing_key.serialize_pem()).unwrap();
+        cert.der().clone()
+    }
+
+    /// 通过 HTTPS 发送一个请求，返回响应和 ALPN 协商的协议
+    fn https_get(
+        addr: SocketAddr,
+        server_name: &str,
+        trusted: &CertificateDer<'static>,
+        versions: &[&'static rustls::SupportedProtocolVersion],
+    ) -> io::Result<(String, Option<Vec<u8>>)> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(trusted.clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let mut connection = rustls::ClientConnection::new(Arc::new(config), server_name).unwrap();
+        let mut tcp = TcpStream::connect(addr)?;
+        tcp.set_read_timeout(Some(Duration::from_secs(2)))?;
+        let mut stream = rustls::Stream::new(&mut connection, &mut tcp);
+        stream.write_all(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok((response, connection.alpn_protocol().map(<[u8]>::to_vec)))
+    }
+
+    #[test]
+    fn test_https_sni_alpn_versions_and_reload() {
+        let dir = env::temp_dir().join(format!("web-server-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let localhost = write_cert(&dir, "default", "localhost");
+        let wildcard = write_cert(&dir, "wildcard", "*.example.test");
+        let tls = TlsConfig::new(dir.join("default.pem"), dir.join("default.key")).sni(
+            "*.example.test",
+            dir.join("wildcard.pem"),
+            dir.join("wildcard.key"),
+        );
+        let router = Router::new().get("/hello", |_| {
+            HttpResponse::new("200", None, Some("hello".to_string()))
+        });
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .tls(tls.clone());
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr();
+        let all_versions = rustls::DEFAULT_VERSIONS;
+
+        let (response, alpn) = https_get(addr, "localhost", &localhost, all_versions).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("hello"));
+        assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+        // TLS 1.2 默认也允许
+        let (response, _) =
+            https_get(addr, "localhost", &localhost, &[&rustls::version::TLS12]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        // 按 SNI 选择通配符证书
+        let (response, _) = https_get(addr, "www.example.test", &wildcard, all_versions).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(https_get(addr, "www.example.test", &localhost, all_versions).is_err());
+
+        // 替换证书文件后重新加载，新连接使用新证书
+        let renewed = write_cert(&dir, "default", "localhost");
+        handle.reload_tls().unwrap();
+        assert!(https_get(addr, "localhost", &renewed, all_versions).is_ok());
+        assert!(https_get(addr, "localhost", &localhost, all_versions).is_err());
+        // 新文件无效时保留原来的证书
+        fs::write(dir.join("default.key"), "broken").unwrap();
+        assert!(matches!(handle.reload_tls(), Err(ServerError::Tls(_))));
+        assert!(https_get(addr, "localhost", &renewed, all_versions).is_ok());
+        handle.shutdown();
+
+        // 只允许 TLS 1.3 时拒绝 TLS 1.2 客户端
+        let current = write_cert(&dir, "default", "localhost");
+        let strict = tls.clone().min_version(TlsVersion::Tls13);
+        let handle = HttpServer::new("127.0.0.1", 0, ".")
+            .tls(strict)
+            .spawn()
+            .unwrap();
+        let tls12 = &[&rustls::version::TLS12];
+        assert!(https_get(handle.local_addr(), "localhost", &current, tls12).is_err());
+        handle.shutdown();
+
+        // 阻塞模式不支持 HTTP/2，ALPN 中的 h2 被忽略
+        let handle = HttpServer::new("127.0.0.1", 0, ".")
+            .router(Router::new().get("/hello", |_| {
+                HttpResponse::new("200", None, Some("hello".to_string()))
+            }))
+            .tls(tls.clone().alpn(&["h2", "http/1.1"]))
+            .spawn()
+            .unwrap();
+        let (response, alpn) =
+            https_get(handle.local_addr(), "localhost", &current, all_versions).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+        handle.shutdown();
+
+        let event = HttpServer::new("127.0.0.1", 0, ".")
+            .engine(Engine::Event)
+            .tls(tls);
+        assert!(matches!(event.spawn(), Err(ServerError::Tls(_))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_http_redirect_and_hsts() {
+        let dir = env::temp_dir().join(format!("web-server-hsts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let localhost = write_cert(&dir, "cert", "localhost");
+        let router = Router::new().get("/hello", |_| {
+            HttpResponse::new("200", None, Some("hello".to_string()))
+        });
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .tls(TlsConfig::new(dir.join("cert.pem"), dir.join("cert.key")))
+            .hsts(Hsts::new(Duration::from_secs(600)).include_subdomains(true))
+            .redirect_http(0);
+        let handle = server.spawn().unwrap();
+        let https_port = handle.local_addr().port();
+        let redirect_addr = handle.redirect_addr().unwrap();
+
+        let (response, _) = https_get(
+            handle.local_addr(),
+            "localhost",
+            &localhost,
+            rustls::DEFAULT_VERSIONS,
+        )
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Strict-Transport-Security: max-age=600; includeSubDomains"));
+
+        let send = |request: &str| {
+            let mut stream = TcpStream::connect(redirect_addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response =
+            send("GET /a/b?x=1 HTTP/1.1\r\nHost: localhost:80\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"));
+        assert!(response.contains(&format!(
+            "Location: https://localhost:{https_port}/a/b?x=1\r\n"
+        )));
+        // 明文响应不能带 HSTS
+        assert!(!response.contains("Strict-Transport-Security"));
+        let response = send(
+            "POST /submit HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi",
+        );
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect"));
+        handle.shutdown();
+
+        // 没有配置 TLS 时不能开启重定向
+        let plain = HttpServer::new("127.0.0.1", 0, ".").redirect_http(0);
+        assert!(matches!(plain.spawn(), Err(ServerError::Tls(_))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_metrics_endpoint_reports_pool_stats() {
+        let router = Router::new().get("/ping", |_| HttpResponse::new("200", None, None));
+        let server = HttpServer::new("127.0.0.1", 0, ".")
+            .router(router)
+            .workers(2)
+            .metrics_path("/metrics");
+        let handle = server.spawn().unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert!(get("/ping").starts_with("HTTP/1.1 200"));
+        let metrics = get("/metrics");
+        assert!(metrics.contains("threadpool_workers 2"));
+        assert!(metrics.contains("# TYPE threadpool_jobs_completed_total counter"));
+        assert!(metrics.contains("server_requests_total 1"));
     }
 }
@@ -1,3 +1,19 @@
+pub mod accesslog;
+#[cfg(feature = "async")]
+pub mod asyncserver;
+mod event;
 pub mod handler;
+#[cfg(feature = "async")]
+mod http2;
 pub mod httpserver;
+pub mod logfile;
+mod metrics;
+pub mod middleware;
 pub mod route;
+mod shutdown;
+pub mod sse;
+pub mod timeout;
+pub mod tls;
+#[cfg(feature = "async")]
+mod upgrade;
+pub mod websocket;
@@ -0,0 +1,433 @@
+use crate::accesslog::utc;
+use crate::shutdown::ServerState;
+use flate2::Compression as GzipLevel;
+use flate2::write::GzEncoder;
+use log::{error, info};
+use signal_hook::consts::SIGUSR1;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 所有打开的日志文件，收到 SIGUSR1 时逐个重新打开
+static OPEN_FILES: Mutex<Vec<Weak<Mutex<RotatingFile>>>> = Mutex::new(Vec::new());
+
+/// 日志文件的轮转配置
+///
+/// 轮转时当前文件重命名为 `<文件名>.<UTC 时间>`，例如 `access.log.20261019-083000`，
+/// 然后在原路径上创建新文件。
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    max_files: usize,
+    compress: bool,
+}
+
+impl LogFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            max_size: None,
+            interval: None,
+            max_files: 7,
+            compress: false,
+        }
+    }
+
+    /// 文件超过该字节数后轮转，默认不按大小轮转
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// 按固定间隔轮转，边界按 UTC 对齐，例如一天的间隔在每天 0 点轮转
+    pub fn rotate_every(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// 保留的轮转文件个数，超过时删除最旧的，`0` 表示全部保留，默认为 7
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// 轮转后在后台线程中把文件压缩为 `.gz`
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// 以追加方式打开日志文件，文件不存在时创建
+    pub fn open(self) -> io::Result<LogWriter> {
+        let mut file = RotatingFile::open(self)?;
+        // 上次运行留下的文件属于之前的时间段，先轮转出去
+        if let Some(interval) = file.config.interval
+            && file.size > 0
+            && let Ok(modified) = file.file.metadata().and_then(|m| m.modified())
+            && period_end(modified, interval) <= SystemTime::now()
+        {
+            file.rotate()?;
+        }
+        let file = Arc::new(Mutex::new(file));
+        let mut open_files = OPEN_FILES.lock().unwrap_or_else(|e| e.into_inner());
+        open_files.retain(|file| file.strong_count() > 0);
+        open_files.push(Arc::downgrade(&file));
+        Ok(LogWriter(file))
+    }
+}
+
+/// 打开的日志文件，可以在多个线程和多个日志之间共用
+///
+/// 每次写入都会检查是否需要轮转，写入的内容不会被拆到两个文件中。
+#[derive(Clone)]
+pub struct LogWriter(Arc<Mutex<RotatingFile>>);
+
+impl LogWriter {
+    /// 关闭并重新打开原路径上的文件，配合 logrotate 等外部工具使用
+    pub fn reopen(&self) -> io::Result<()> {
+        self.lock().reopen()
+    }
+
+    /// 立即轮转
+    pub fn rotate(&self) -> io::Result<()> {
+        self.lock().rotate()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RotatingFile> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().file.flush()
+    }
+}
+
+struct RotatingFile {
+    config: LogFile,
+    file: File,
+    size: u64,
+    /// 下一次按时间轮转的时刻
+    next_rotation: Option<SystemTime>,
+}
+
+impl RotatingFile {
+    fn open(config: LogFile) -> io::Result<Self> {
+        let file = open_append(&config.path)?;
+        let size = file.metadata()?.len();
+        let next_rotation = config
+            .interval
+            .map(|interval| period_end(SystemTime::now(), interval));
+        Ok(Self {
+            config,
+            file,
+            size,
+            next_rotation,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let due = self
+            .next_rotation
+            .is_some_and(|next| SystemTime::now() >= next);
+        let full = self
+            .config
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + buf.len() as u64 > max_size);
+        // 轮转失败时继续写入当前文件，不能丢失日志；
+        // 这里持有锁，不能通过 log 宏报告，否则写错误日志时会死锁
+        if (due || full)
+            && let Err(e) = self.rotate()
+        {
+            eprintln!("Failed to rotate {}: {e}", self.config.path.display());
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = open_append(&self.config.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let now = SystemTime::now();
+        if let Some(interval) = self.config.interval {
+            self.next_rotation = Some(period_end(now, interval));
+        }
+        self.file.flush()?;
+        let rotated = rotated_path(&self.config.path, now);
+        fs::rename(&self.config.path, &rotated)?;
+        self.reopen()?;
+
+        let config = self.config.clone();
+        if config.compress {
+            // 压缩大文件比较慢，放到后台线程，避免阻塞写日志的线程
+            let result = thread::Builder::new()
+                .name("web-log-gzip".to_string())
+                .spawn(move || {
+                    if let Err(e) = gzip(&rotated) {
+                        error!("Failed to compress {}: {e}", rotated.display());
+                    }
+                    if let Err(e) = prune(&config.path, config.max_files) {
+                        error!("Failed to remove old log files: {e}");
+                    }
+                });
+            if let Err(e) = result {
+                eprintln!("Failed to start log compression: {e}");
+            }
+        } else if let Err(e) = prune(&config.path, config.max_files) {
+            // 调用方持有锁，同 write 一样不能通过 log 宏报告
+            eprintln!("Failed to remove old log files: {e}");
+        }
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// 包含 `time` 的时间段的结束时刻，时间段按 UNIX 纪元对齐
+fn period_end(time: SystemTime, interval: Duration) -> SystemTime {
+    let interval = interval.as_secs().max(1);
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs((seconds / interval + 1) * interval)
+}
+
+/// 轮转后的文件名，同一秒内多次轮转时加上序号
+fn rotated_path(path: &Path, time: SystemTime) -> PathBuf {
+    let (year, month, day, hour, minute, second) = utc(time);
+    let stamp = format!("{year}{month:02}{day:02}-{hour:02}{minute:02}{second:02}");
+    let base = format!("{}.{stamp}", path.display());
+    let taken = |candidate: &str| {
+        Path::new(candidate).exists() || Path::new(&format!("{candidate}.gz")).exists()
+    };
+    let mut candidate = base.clone();
+    let mut sequence = 1;
+    while taken(&candidate) {
+        candidate = format!("{base}-{sequence}");
+        sequence += 1;
+    }
+    PathBuf::from(candidate)
+}
+
+fn gzip(path: &Path) -> io::Result<()> {
+    let mut target = path.as_os_str().to_owned();
+    target.push(".gz");
+    let mut source = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(&target)?, GzipLevel::default());
+    io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)
+}
+
+/// 按文件名中的时间从旧到新排列的轮转文件，包括压缩过的
+fn rotated_files(path: &Path) -> Vec<PathBuf> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{name}.");
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<(String, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let stamp = file_name.strip_prefix(&prefix)?;
+            // 只处理 `<年月日>-<时分秒>` 格式的文件，跳过其他工具生成的文件
+            let is_rotated = stamp.len() >= 15
+                && stamp.as_bytes()[..8].iter().all(u8::is_ascii_digit)
+                && stamp.as_bytes()[8] == b'-'
+                && stamp.as_bytes()[9..15].iter().all(u8::is_ascii_digit);
+            is_rotated.then(|| (stamp.trim_end_matches(".gz").to_string(), entry.path()))
+        })
+        .collect();
+    files.sort();
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// 删除超出保留个数的最旧的轮转文件，某个文件删除失败时继续删除其余的，返回第一个错误
+fn prune(path: &Path, max_files: usize) -> io::Result<()> {
+    if max_files == 0 {
+        return Ok(());
+    }
+    let files = rotated_files(path);
+    let excess = files.len().saturating_sub(max_files);
+    let mut result = Ok(());
+    for file in &files[..excess] {
+        if let Err(e) = fs::remove_file(file)
+            && result.is_ok()
+        {
+            result = Err(io::Error::new(e.kind(), format!("{}: {e}", file.display())));
+        }
+    }
+    result
+}
+
+/// 重新打开所有日志文件，文件被外部工具移走后在原路径上创建新文件
+pub fn reopen_all() {
+    let open_files: Vec<_> = OPEN_FILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for file in open_files {
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.reopen() {
+            eprintln!("Failed to reopen {}: {e}", file.config.path.display());
+        }
+    }
+}
+
+/// 收到 SIGUSR1 时重新打开所有日志文件，直到服务器停止
+pub(crate) fn watch_reopen(state: Arc<ServerState>) -> io::Result<()> {
+    let signaled = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGUSR1, Arc::clone(&signaled))?;
+
+    thread::Builder::new()
+        .name("web-log-reopen".to_string())
+        .spawn(move || {
+            while !state.is_stopping() {
+                if signaled.swap(false, Ordering::SeqCst) {
+                    reopen_all();
+                    info!("Received SIGUSR1, reopened log files");
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::env;
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("web-server-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_size_rotation_and_retention() {
+        let dir = temp_dir("log-size");
+        let path = dir.join("app.log");
+        let mut writer = LogFile::new(&path)
+            .max_size(10)
+            .max_files(2)
+            .open()
+            .unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        // 每行都会让文件超过 10 字节，写入前轮转；只保留最新的两个轮转文件
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        let rotated: Vec<String> = rotated_files(&path)
+            .iter()
+            .map(|file| fs::read_to_string(file).unwrap())
+            .collect();
+        assert_eq!(rotated, ["second\n", "third\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_failure_does_not_stop_rotation() {
+        let dir = temp_dir("log-prune");
+        let path = dir.join("app.log");
+        // 同名格式的目录无法用 remove_file 删除，清理时必然失败
+        let stuck = dir.join("app.log.20000101-000000");
+        fs::create_dir(&stuck).unwrap();
+        let mut writer = LogFile::new(&path)
+            .max_size(10)
+            .max_files(1)
+            .open()
+            .unwrap();
+        for line in ["first\n", "second\n", "third\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        assert!(prune(&path, 1).is_err());
+        // 删除失败的文件保留，其余超出个数的文件照常删除
+        let rotated = rotated_files(&path);
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(rotated[0], stuck);
+        assert_eq!(fs::read_to_string(&rotated[1]).unwrap(), "second\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compress_and_reopen() {
+        let dir = temp_dir("log-gzip");
+        let path = dir.join("access.log");
+        let mut writer = LogFile::new(&path).compress(true).open().unwrap();
+        writer.write_all(b"before rotation\n").unwrap();
+        writer.rotate().unwrap();
+        writer.write_all(b"after rotation\n").unwrap();
+
+        let mut compressed = Vec::new();
+        for _ in 0..50 {
+            compressed = rotated_files(&path);
+            if compressed.len() == 1 && compressed[0].extension().is_some_and(|ext| ext == "gz") {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let mut content = String::new();
+        GzDecoder::new(File::open(&compressed[0]).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "before rotation\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after rotation\n");
+
+        // logrotate 移走文件后，重新打开时在原路径上创建新文件
+        let moved = dir.join("access.log.moved");
+        fs::rename(&path, &moved).unwrap();
+        writer.write_all(b"still old\n").unwrap();
+        reopen_all();
+        writer.write_all(b"new file\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new file\n");
+        assert!(fs::read_to_string(&moved).unwrap().ends_with("still old\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_period_end_is_aligned() {
+        let day = Duration::from_secs(86400);
+        let time = UNIX_EPOCH + Duration::from_secs(971_185_536);
+        assert_eq!(
+            period_end(time, day),
+            UNIX_EPOCH + Duration::from_secs(971_222_400)
+        );
+        assert_eq!(
+            rotated_path(Path::new("/nonexistent/app.log"), time),
+            PathBuf::from("/nonexistent/app.log.20001010-134536")
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::handler::Handler;
+use crate::shutdown::ServerState;
+use http::httprequest::HttpRequest;
+use http::httpresponse::HttpResponse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use threadpool::threadpool::PoolMonitor;
+
+/// 在固定路径上返回服务器统计数据，其他请求交给内部处理器
+pub(crate) struct MetricsHandler {
+    path: String,
+    monitor: PoolMonitor,
+    state: Arc<ServerState>,
+    inner: Arc<dyn Handler>,
+}
+
+impl MetricsHandler {
+    pub(crate) fn new(
+        path: &str,
+        monitor: PoolMonitor,
+        state: Arc<ServerState>,
+        inner: Arc<dyn Handler>,
+    ) -> Self {
+        Self {
+            path: path.to_string(),
+            monitor,
+            state,
+            inner,
+        }
+    }
+
+    fn render(&self) -> String {
+        let stats = self.monitor.stats();
+        let metrics: [(&str, &str, String); 11] = [
+            ("threadpool_workers", "gauge", stats.workers.to_string()),
+            (
+                "threadpool_active_workers",
+                "gauge",
+                stats.active_workers.to_string(),
+            ),
+            (
+                "threadpool_idle_workers",
+                "gauge",
+                stats.idle_workers.to_string(),
+            ),
+            (
+                "threadpool_queue_depth",
+                "gauge",
+                stats.queue_depth.to_string(),
+            ),
+            (
+                "threadpool_jobs_completed_total",
+                "counter",
+                stats.jobs_completed.to_string(),
+            ),
+            (
+                "threadpool_jobs_panicked_total",
+                "counter",
+                stats.jobs_panicked.to_string(),
+            ),
+            (
+                "threadpool_job_wait_seconds_avg",
+                "gauge",
+                stats.average_wait.as_secs_f64().to_string(),
+            ),
+            (
+                "threadpool_job_run_seconds_avg",
+                "gauge",
+                stats.average_run.as_secs_f64().to_string(),
+            ),
+            (
+                "server_active_connections",
+                "gauge",
+                self.state.active_connections().to_string(),
+            ),
+            (
+                "server_connections_total",
+                "counter",
+                self.state.connections_served().to_string(),
+            ),
+            (
+                "server_requests_total",
+                "counter",
+                self.state.requests_served().to_string(),
+            ),
+        ];
+
+        let mut body = String::new();
+        for (name, kind, value) in metrics {
+            let _ = writeln!(body, "# TYPE {name} {kind}\n{name} {value}");
+        }
+        body
+    }
+}
+
+impl Handler for MetricsHandler {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        if request.path() != self.path {
+            return self.inner.handle_request(request);
+        }
+        let mut header = HashMap::new();
+        header.insert("Content-Type", "text/plain; version=0.0.4");
+        HttpResponse::new("200", Some(header), Some(self.render()))
+    }
+
+    /// 统计路径以外的请求交给内部处理器检查
+    fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        if request.path() == self.path {
+            None
+        } else {
+            self.inner.check_continue(request)
+        }
+    }
+}
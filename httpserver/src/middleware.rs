@@ -0,0 +1,429 @@
+use crate::handler::Handler;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Compression as GzipLevel;
+use flate2::write::GzEncoder;
+use http::httprequest::HttpRequest;
+use http::httpresponse::HttpResponse;
+use log::{info, warn};
+use std::io::Write;
+use std::time::Instant;
+
+/// 包裹在处理器外层的中间件
+///
+/// 中间件可以修改请求后调用 `next.run(request)` 继续处理，
+/// 也可以不调用 `next` 直接返回响应（短路），还可以修改 `next` 返回的响应。
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static>;
+
+    /// 见 [`Handler::check_continue`]，返回响应时不再检查后面的中间件和处理器
+    fn check_continue(&self, _request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        None
+    }
+}
+
+/// 中间件链中剩余的部分
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middlewares: &'a [Box<dyn Middleware>], handler: &'a dyn Handler) -> Self {
+        Self {
+            middlewares,
+            handler,
+        }
+    }
+
+    pub fn run(self, request: HttpRequest) -> HttpResponse<'static> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(request, Next::new(rest, self.handler)),
+            None => self.handler.handle_request(request),
+        }
+    }
+
+    /// 按顺序检查中间件和处理器是否接受 `Expect: 100-continue` 请求
+    pub(crate) fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        self.middlewares
+            .iter()
+            .find_map(|middleware| middleware.check_continue(request))
+            .or_else(|| self.handler.check_continue(request))
+    }
+}
+
+/// 给单个处理器套上中间件，可以直接注册为路由或挂载点
+///
+/// 中间件按添加顺序从外到内执行。
+pub struct Chain {
+    middlewares: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Chain {
+    pub fn new(handler: impl Handler + 'static) -> Self {
+        Self {
+            middlewares: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        Next::new(&self.middlewares, self.handler.as_ref()).run(request)
+    }
+
+    fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        Next::new(&self.middlewares, self.handler.as_ref()).check_continue(request)
+    }
+}
+
+/// 每个请求处理完成后记录一行日志
+pub struct RequestLogger;
+
+impl Middleware for RequestLogger {
+    fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static> {
+        let method = request.method().as_str();
+        let path = request.resource_path().to_string();
+        let start = Instant::now();
+        let response = next.run(request);
+        info!(
+            "{method} {path} -> {} ({:.3}ms)",
+            response.status_code(),
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+        response
+    }
+}
+
+/// 在响应头 `X-Response-Time` 中返回处理耗时
+pub struct ResponseTime;
+
+impl Middleware for ResponseTime {
+    fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static> {
+        let start = Instant::now();
+        let mut response = next.run(request);
+        let elapsed = format!("{:.3}ms", start.elapsed().as_secs_f64() * 1000.0);
+        response.set_header("X-Response-Time", &elapsed);
+        response
+    }
+}
+
+/// 给响应补充固定的响应头，处理器已经设置的同名响应头不会被覆盖
+pub struct DefaultHeaders {
+    headers: Vec<(String, String)>,
+}
+
+impl DefaultHeaders {
+    pub fn new() -> Self {
+        Self {
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl Default for DefaultHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for DefaultHeaders {
+    fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static> {
+        let mut response = next.run(request);
+        for (key, value) in &self.headers {
+            if response.header(key).is_none() {
+                response.set_header(key, value);
+            }
+        }
+        response
+    }
+}
+
+/// HTTP Basic 认证，认证失败时直接返回 `401`
+pub struct BasicAuth {
+    realm: String,
+    credentials: Vec<(String, String)>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str) -> Self {
+        Self {
+            realm: realm.to_string(),
+            credentials: Vec::new(),
+        }
+    }
+
+    pub fn user(mut self, username: &str, password: &str) -> Self {
+        self.credentials
+            .push((username.to_string(), password.to_string()));
+        self
+    }
+
+    fn unauthorized(&self, request: &HttpRequest) -> HttpResponse<'static> {
+        warn!("Unauthorized request to {}", request.resource_path());
+        let mut response = HttpResponse::new("401", None, None);
+        let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm);
+        response.set_header("WWW-Authenticate", &challenge);
+        response
+    }
+
+    fn authorized(&self, request: &HttpRequest) -> bool {
+        let Some(encoded) = request
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+        let Ok(decoded) = STANDARD.decode(encoded.trim()) else {
+            return false;
+        };
+        let decoded = String::from_utf8_lossy(&decoded);
+        match decoded.split_once(':') {
+            Some((username, password)) => self
+                .credentials
+                .iter()
+                .any(|(u, p)| u == username && p == password),
+            None => false,
+        }
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static> {
+        if self.authorized(&request) {
+            return next.run(request);
+        }
+        self.unauthorized(&request)
+    }
+
+    /// 未认证的请求不需要上传请求体
+    fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        (!self.authorized(request)).then(|| self.unauthorized(request))
+    }
+}
+
+/// 客户端支持时用 gzip 压缩文本类响应
+pub struct Compression {
+    min_size: usize,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Self { min_size: 1024 }
+    }
+
+    /// 小于该字节数的响应体不压缩
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    fn compressible(content_type: &str) -> bool {
+        content_type.starts_with("text/")
+            || content_type.starts_with("application/json")
+            || content_type.starts_with("application/javascript")
+            || content_type.starts_with("application/xml")
+            || content_type.starts_with("image/svg+xml")
+            || content_type.starts_with("application/wasm")
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static> {
+        let accept_gzip = request.header("Accept-Encoding").is_some_and(accepts_gzip);
+        let mut response = next.run(request);
+        // 流式响应体的长度未知，不压缩
+        if response.is_streaming()
+            || response.header("Content-Encoding").is_some()
+            || response.body_bytes().len() < self.min_size
+            || !Self::compressible(response.header("Content-Type").unwrap_or(""))
+        {
+            return response;
+        }
+        // 是否压缩取决于 Accept-Encoding，未压缩的响应同样需要告知缓存
+        add_vary(&mut response, "Accept-Encoding");
+        if !accept_gzip {
+            return response;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+        let compressed = encoder
+            .write_all(response.body_bytes())
+            .and_then(|_| encoder.finish());
+        match compressed {
+            Ok(compressed) => {
+                response.set_binary_body(Some(compressed));
+                response.set_header("Content-Encoding", "gzip");
+            }
+            Err(e) => warn!("gzip compression failed: {e}"),
+        }
+        response
+    }
+}
+
+/// 按 q 值判断客户端是否接受 gzip，`q=0` 表示不接受，
+/// 没有单独列出 gzip 时使用 `*` 的 q 值
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut gzip = None;
+    let mut any = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            // 无法解析的 q 值按不接受处理
+            .map_or(1.0, |(_, value)| value.trim().parse::<f32>().unwrap_or(0.0));
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(quality);
+        } else if coding == "*" {
+            any = Some(quality);
+        }
+    }
+    gzip.or(any).is_some_and(|quality| quality > 0.0)
+}
+
+/// 在 Vary 响应头中追加字段，已经存在时不重复添加
+fn add_vary(response: &mut HttpResponse<'static>, field: &str) {
+    let vary = match response.header("Vary") {
+        Some(vary)
+            if vary
+                .split(',')
+                .any(|item| item.trim() == "*" || item.trim().eq_ignore_ascii_case(field)) =>
+        {
+            return;
+        }
+        Some(vary) if !vary.trim().is_empty() => format!("{vary}, {field}"),
+        _ => field.to_string(),
+    };
+    response.set_header("Vary", &vary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::route::Router;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn hello(_: HttpRequest) -> HttpResponse<'static> {
+        HttpResponse::new("200", None, Some("hello ".repeat(500)))
+    }
+
+    fn request(headers: &str) -> HttpRequest {
+        format!("GET /secret HTTP/1.1\r\n{headers}\r\n").into()
+    }
+
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn handle(&self, request: HttpRequest, next: Next) -> HttpResponse<'static> {
+            let mut response = next.run(request);
+            let tags = format!("{}{}", response.header("X-Tags").unwrap_or(""), self.0);
+            response.set_header("X-Tags", &tags);
+            response
+        }
+    }
+
+    #[test]
+    fn test_middlewares_run_in_order() {
+        let router = Router::new()
+            .middleware(Tag("router"))
+            .mount("/", Chain::new(hello).with(Tag("a")).with(Tag("b")));
+        let response = router.handle_request(request(""));
+        assert_eq!(response.header("X-Tags"), Some("barouter"));
+    }
+
+    #[test]
+    fn test_basic_auth_short_circuits() {
+        let chain = Chain::new(hello).with(BasicAuth::new("test").user("admin", "secret"));
+
+        let response = chain.handle_request(request(""));
+        assert_eq!(response.status_code(), "401");
+        assert!(response.header("WWW-Authenticate").is_some());
+
+        let response = chain.handle_request(request("Authorization: Basic YWRtaW46c2VjcmV0\r\n"));
+        assert_eq!(response.status_code(), "200");
+    }
+
+    #[test]
+    fn test_compression_and_default_headers() {
+        let chain = Chain::new(hello)
+            .with(Compression::new())
+            .with(DefaultHeaders::new().header("Server", "web-server"));
+        let response = chain.handle_request(request("Accept-Encoding: gzip, deflate\r\n"));
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Server"), Some("web-server"));
+
+        let mut decoded = String::new();
+        GzDecoder::new(response.body_bytes())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello ".repeat(500));
+
+        let response = chain.handle_request(request(""));
+        assert_eq!(response.header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_compression_respects_q_values() {
+        let chain = Chain::new(hello).with(Compression::new());
+        let encoding = |accept: &str| {
+            let response = chain.handle_request(request(&format!("Accept-Encoding: {accept}\r\n")));
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"), "{accept}");
+            response.header("Content-Encoding").map(str::to_string)
+        };
+
+        assert_eq!(encoding("gzip;q=0.5, br"), Some("gzip".to_string()));
+        assert_eq!(encoding("GZIP"), Some("gzip".to_string()));
+        assert_eq!(encoding("*"), Some("gzip".to_string()));
+        assert_eq!(encoding("gzip;q=0"), None);
+        assert_eq!(encoding("gzip; q=0.000, deflate"), None);
+        assert_eq!(encoding("*;q=0"), None);
+        assert_eq!(encoding("br, *;q=0"), None);
+        assert_eq!(encoding("gzip;q=1, *;q=0"), Some("gzip".to_string()));
+        assert_eq!(encoding("deflate"), None);
+    }
+
+    #[test]
+    fn test_compression_vary_only_for_compressible_responses() {
+        let chain = Chain::new(hello).with(Compression::new());
+        let response = chain.handle_request(request(""));
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+
+        let varied = |_: HttpRequest| {
+            let mut response = HttpResponse::new("200", None, Some("hello ".repeat(500)));
+            response.set_header("Vary", "Origin");
+            response
+        };
+        let response = Chain::new(varied)
+            .with(Compression::new())
+            .handle_request(request("Accept-Encoding: gzip\r\n"));
+        assert_eq!(response.header("Vary"), Some("Origin, Accept-Encoding"));
+
+        // 太小的响应不论客户端是否支持都不压缩
+        let response = Chain::new(hello)
+            .with(Compression::new().min_size(1 << 20))
+            .handle_request(request("Accept-Encoding: gzip\r\n"));
+        assert_eq!(response.header("Vary"), None);
+    }
+}
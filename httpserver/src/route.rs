@@ -1,116 +1,757 @@
-use crate::handler::{Handler, StaticResourceHandler};
-use http::httprequest::HttpRequest;
-use log::error;
-use std::io::{BufRead, BufReader, Read};
-use std::net::TcpStream;
+use crate::accesslog::{AccessEntry, AccessLog, CountingWriter};
+use crate::handler::{Handler, NotFoundHandler};
+use crate::middleware::{Middleware, Next};
+use crate::shutdown::ServerState;
+use crate::timeout::{TimedStream, Timeouts, is_timeout, request_timeout_response};
+use crate::tls::{Stream, UpgradedStream};
+use http::chunked::ChunkedReader;
+use http::httprequest::{HttpMethod, HttpRequest, HttpVersion};
+use http::httpresponse::{HttpResponse, status_text};
+use http::parser::{self, Framing, Limits, ParseError};
+use log::{error, warn};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
 
-pub struct Route {}
+/// 路径模式中的一段
+#[derive(Debug, PartialEq)]
+enum Segment {
+    /// 普通路径段，需要完全相同
+    Static(String),
+    /// `:name`，匹配任意一段
+    Param(String),
+    /// `*name`，匹配剩余的所有路径段，只能出现在最后
+    Wildcard(String),
+}
 
-impl Route {
-    pub fn route(mut connection: TcpStream) {
-        let mut buffer = BufReader::new(&connection);
+#[derive(Debug, PartialEq)]
+struct PathPattern {
+    segments: Vec<Segment>,
+}
 
-        // 读取完整的HTTP请求
-        let request_string = match Self::read_full_request(&mut buffer) {
-            Ok(content) => content,
-            Err(e) => {
-                error!("Error reading request: {e}");
-                return;
+impl PathPattern {
+    fn parse(pattern: &str) -> PathPattern {
+        let mut segments = Vec::new();
+        for part in split_path(pattern) {
+            if let Some(name) = part.strip_prefix(':') {
+                segments.push(Segment::Param(name.to_string()));
+            } else if let Some(name) = part.strip_prefix('*') {
+                segments.push(Segment::Wildcard(name.to_string()));
+                break;
+            } else {
+                segments.push(Segment::Static(part.to_string()));
             }
-        };
+        }
+        PathPattern { segments }
+    }
 
-        let request: HttpRequest = HttpRequest::from(request_string);
+    /// 匹配成功时返回提取出的路径参数
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let parts: Vec<&str> = split_path(path).collect();
+        let mut params = Vec::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(expected) => {
+                    if parts.get(index) != Some(&expected.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = parts.get(index)?;
+                    params.push((name.clone(), percent_decode(value)));
+                }
+                Segment::Wildcard(name) => {
+                    let rest = parts.get(index..).unwrap_or_default().join("/");
+                    params.push((name.clone(), percent_decode(&rest)));
+                    return Some(params);
+                }
+            }
+        }
+        if parts.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
 
-        let response = StaticResourceHandler::handle_request(request);
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
 
-        if let Err(e) = response.send_response(&mut connection) {
-            error!("Error sending response: {e}");
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = value.get(index + 1..index + 3);
+        if let (b'%', Some(byte)) = (
+            bytes[index],
+            hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+        ) {
+            decoded.push(byte);
+            index += 3;
+            continue;
         }
+        decoded.push(bytes[index]);
+        index += 1;
     }
+    String::from_utf8_lossy(&decoded).to_string()
+}
 
-    /// 读取完整的HTTP请求内容
-    fn read_full_request(buffer: &mut BufReader<&TcpStream>) -> Result<String, std::io::Error> {
-        let mut request_lines = Vec::new();
-        let mut body = String::new();
-        let mut content_length = 0;
-        let mut transfer_encoding = String::new();
-        // 读取请求行和请求头
-        for line in buffer.lines() {
-            let line = line?;
-            if line.is_empty() {
-                break; // 空行表示请求头结束
-            }
-            request_lines.push(line.clone());
+struct RouteEntry {
+    method: HttpMethod,
+    pattern: PathPattern,
+    handler: Box<dyn Handler>,
+}
+
+struct Mount {
+    prefix: String,
+    handler: Box<dyn Handler>,
+}
+
+/// 按请求方法和路径分发请求的路由器
+///
+/// 匹配顺序：先按注册顺序匹配路由，没有注册 `HEAD` 路由时 `HEAD` 请求使用对应的 `GET` 路由；
+/// 路径匹配但方法不匹配时返回 `405`；
+/// 然后按最长前缀匹配挂载点；都没有匹配时交给 `fallback`。
+/// 路由器本身也实现了 [`Handler`]，可以作为子路由挂载到其他路由器上。
+/// 通过 [`Router::middleware`] 添加的中间件作用于该路由器处理的所有请求。
+pub struct Router {
+    routes: Vec<RouteEntry>,
+    mounts: Vec<Mount>,
+    fallback: Box<dyn Handler>,
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            mounts: Vec::new(),
+            fallback: Box::new(NotFoundHandler {}),
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// 添加作用于整个路由器的中间件，按添加顺序从外到内执行
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// 注册路由，`pattern` 支持 `:name` 参数和末尾的 `*name` 通配
+    pub fn route(
+        mut self,
+        method: HttpMethod,
+        pattern: &str,
+        handler: impl Handler + 'static,
+    ) -> Self {
+        self.routes.push(RouteEntry {
+            method,
+            pattern: PathPattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(HttpMethod::GET, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(HttpMethod::POST, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(HttpMethod::PUT, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(HttpMethod::DELETE, pattern, handler)
+    }
+
+    pub fn patch(self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.route(HttpMethod::PATCH, pattern, handler)
+    }
+
+    /// 把处理器（或子路由器）挂载到前缀下，处理器看到的路径会去掉该前缀
+    pub fn mount(mut self, prefix: &str, handler: impl Handler + 'static) -> Self {
+        let prefix = format!("/{}", split_path(prefix).collect::<Vec<_>>().join("/"));
+        self.mounts.push(Mount {
+            prefix,
+            handler: Box::new(handler),
+        });
+        // 最长前缀优先
+        self.mounts
+            .sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+        self
+    }
+
+    /// 没有任何路由和挂载点匹配时使用的处理器，默认返回 404
+    pub fn fallback(mut self, handler: impl Handler + 'static) -> Self {
+        self.fallback = Box::new(handler);
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        let dispatch = |request| self.dispatch(request);
+        Next::new(&self.middlewares, &dispatch).run(request)
+    }
 
-            // 解析请求头
-            if line.contains(":") {
-                let parts: Vec<&str> = line.splitn(2, ':').collect();
-                if parts.len() == 2 {
-                    let key = parts[0].trim().to_lowercase();
-                    let value = parts[1].trim();
+    fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        if let Some(response) = self
+            .middlewares
+            .iter()
+            .find_map(|middleware| middleware.check_continue(request))
+        {
+            return Some(response);
+        }
+        // 路由不存在或方法不允许时不需要读取请求体
+        match self.resolve(request.clone()) {
+            Target::Handler(handler, request) => handler.check_continue(&request),
+            Target::MethodNotAllowed(allow) => Some(method_not_allowed(&allow)),
+        }
+    }
+}
+
+/// 路由匹配的结果
+enum Target<'a> {
+    /// 处理请求的处理器，以及设置好路径参数或去掉挂载前缀的请求
+    Handler(&'a dyn Handler, Box<HttpRequest>),
+    /// 路径匹配但方法不匹配，带有允许的方法列表
+    MethodNotAllowed(String),
+}
 
-                    if key == "content-length" {
-                        content_length = value.parse::<usize>().unwrap_or(0);
-                    } else if key == "transfer-encoding" {
-                        transfer_encoding = value.to_string();
+impl Router {
+    fn dispatch(&self, request: HttpRequest) -> HttpResponse<'static> {
+        match self.resolve(request) {
+            Target::Handler(handler, request) => handler.handle_request(*request),
+            Target::MethodNotAllowed(allow) => method_not_allowed(&allow),
+        }
+    }
+
+    fn resolve(&self, mut request: HttpRequest) -> Target<'_> {
+        let path = request.path().to_string();
+
+        let mut allowed: Vec<HttpMethod> = Vec::new();
+        // HEAD 请求在没有显式的 HEAD 路由时使用的 GET 路由
+        let mut get_route = None;
+        for entry in &self.routes {
+            if let Some(params) = entry.pattern.matches(&path) {
+                if entry.method == *request.method() {
+                    for (name, value) in params {
+                        request.set_param(&name, &value);
                     }
+                    return Target::Handler(entry.handler.as_ref(), Box::new(request));
+                }
+                if entry.method == HttpMethod::GET && get_route.is_none() {
+                    get_route = Some((entry, params));
+                }
+                if !allowed.contains(&entry.method) {
+                    allowed.push(entry.method);
                 }
             }
         }
 
-        // 读取请求体（如果存在）
-        if content_length > 0 {
-            let mut body_buffer = vec![0u8; content_length];
-            buffer.read_exact(&mut body_buffer)?;
-            body = String::from_utf8_lossy(&body_buffer).to_string();
-        } else if transfer_encoding == "chunked" {
-            // 处理 chunked 编码
-            if transfer_encoding == "chunked" {
-                body = Self::read_chunked_body(buffer)?;
+        // 服务器发送响应时会去掉 HEAD 请求的响应体
+        if *request.method() == HttpMethod::HEAD
+            && let Some((entry, params)) = get_route
+        {
+            for (name, value) in params {
+                request.set_param(&name, &value);
             }
+            return Target::Handler(entry.handler.as_ref(), Box::new(request));
         }
 
-        // 组合完整的请求内容
-        let mut full_request = request_lines.join("\r\n");
-        full_request.push_str("\r\n\r\n");
-        if !body.is_empty() {
-            full_request.push_str(&body);
+        // 支持 GET 的路径同样支持 HEAD
+        if allowed.contains(&HttpMethod::GET) && !allowed.contains(&HttpMethod::HEAD) {
+            let index = allowed
+                .iter()
+                .position(|method| *method == HttpMethod::GET)
+                .map_or(0, |index| index + 1);
+            allowed.insert(index, HttpMethod::HEAD);
+        }
+
+        if !allowed.is_empty() {
+            let allow = allowed
+                .iter()
+                .map(|method| method.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Target::MethodNotAllowed(allow);
+        }
+
+        for mount in &self.mounts {
+            if let Some(rest) = strip_mount_prefix(&mount.prefix, &path) {
+                let resource = match request.query() {
+                    Some(query) => format!("{rest}?{query}"),
+                    None => rest,
+                };
+                request.set_resource_path(resource);
+                if mount.prefix != "/" {
+                    request.push_mount_prefix(&mount.prefix);
+                }
+                return Target::Handler(mount.handler.as_ref(), Box::new(request));
+            }
         }
 
-        Ok(full_request)
+        Target::Handler(self.fallback.as_ref(), Box::new(request))
     }
+}
+
+fn method_not_allowed(allow: &str) -> HttpResponse<'static> {
+    let mut response = HttpResponse::new("405", None, None);
+    response.set_header("Allow", allow);
+    response
+}
+
+/// 前缀按路径段匹配，`/static` 匹配 `/static/a.css` 但不匹配 `/statics`
+fn strip_mount_prefix(prefix: &str, path: &str) -> Option<String> {
+    if prefix == "/" {
+        return Some(path.to_string());
+    }
+    let rest = path.strip_prefix(prefix)?;
+    if rest.is_empty() {
+        Some("/".to_string())
+    } else if rest.starts_with('/') {
+        Some(rest.to_string())
+    } else {
+        None
+    }
+}
+
+/// 读取请求时的数据来源，读完请求头后会被通知开始读取请求体
+pub(crate) trait RequestSource: Read {
+    fn start_body(&mut self) {}
+
+    /// 发送 `100 Continue` 临时响应，通知客户端继续发送请求体
+    fn send_continue(&mut self) -> io::Result<()>;
+}
+
+impl RequestSource for &TcpStream {
+    fn send_continue(&mut self) -> io::Result<()> {
+        self.write_all(CONTINUE)
+    }
+}
 
-    /// 读取 chunked 编码的请求体
-    fn read_chunked_body(buffer: &mut BufReader<&TcpStream>) -> Result<String, std::io::Error> {
-        let mut body = String::new();
+/// `100 Continue` 临时响应
+pub(crate) const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// 从连接上读到的内容
+enum Incoming {
+    /// 对方已经关闭连接
+    Closed,
+    /// 完整的请求，包括 chunked 请求体之后的 trailer
+    Request(Box<HttpRequest>),
+    /// 没有读取请求体就直接返回的响应，`Expect` 检查没有通过，附带只有请求头的请求
+    Rejected(Box<HttpRequest>, HttpResponse<'static>),
+}
+
+/// 对请求中 `Expect` 请求头的处理方式
+pub(crate) enum Expectation {
+    /// 没有需要处理的期望，直接读取请求体
+    None,
+    /// 先发送 `100 Continue` 再读取请求体
+    Continue,
+    /// 直接发送该响应并关闭连接，不读取请求体
+    Reject(HttpResponse<'static>),
+}
+
+/// 检查请求的 `Expect` 请求头，`request` 中只有请求头
+pub(crate) fn check_expectation(handler: &dyn Handler, request: &HttpRequest) -> Expectation {
+    let Some(expect) = request.header("Expect") else {
+        return Expectation::None;
+    };
+    // HTTP/1.0 客户端不理解 100 Continue，按 RFC 9110 忽略
+    if !matches!(request.version(), HttpVersion::HTTP11 | HttpVersion::HTTP20) {
+        return Expectation::None;
+    }
+    let mut response = if !expect.trim().eq_ignore_ascii_case("100-continue") {
+        HttpResponse::new("417", None, Some("Expectation Failed".to_string()))
+    } else {
+        match handler.check_continue(request) {
+            Some(response) => response,
+            None => return Expectation::Continue,
+        }
+    };
+    // 请求体还在连接上没有读取，响应后只能关闭连接
+    response.set_header("Connection", "close");
+    Expectation::Reject(response)
+}
+
+pub struct Route {}
+
+impl Route {
+    /// 处理一个连接上的所有请求，直到连接关闭、不再 keep-alive 或服务器停止
+    ///
+    /// `hsts` 为 HTTPS 连接上需要加到响应中的 `Strict-Transport-Security` 值，
+    /// 设置了 `access_log` 时每个响应发送完后写一行访问日志。
+    pub(crate) fn route(
+        connection: Stream,
+        handler: &dyn Handler,
+        state: &ServerState,
+        timeouts: &Timeouts,
+        limits: &Limits,
+        hsts: Option<&str>,
+        access_log: Option<&AccessLog>,
+    ) {
+        let Some(guard) = state.register(connection.tcp()) else {
+            return;
+        };
+        if let Err(e) = connection
+            .tcp()
+            .set_write_timeout(Some(timeouts.write_timeout()))
+        {
+            error!("Error setting write timeout: {e}");
+        }
+        let mut buffer = BufReader::new(TimedStream::new(&connection, *timeouts));
+        let mut upgrade = None;
 
         loop {
-            let mut size_line = String::new();
-            buffer.read_line(&mut size_line)?;
+            // 缓冲区里还有数据说明下一个请求已经开始
+            let pending = !buffer.buffer().is_empty();
+            buffer.get_mut().wait_request(pending);
 
-            // 解析块大小
-            let size_str = size_line.trim_end_matches("\r\n");
-            let chunk_size = usize::from_str_radix(size_str, 16).unwrap_or(0);
+            // 读取完整的HTTP请求
+            let incoming = match Self::read_full_request(&mut buffer, limits, handler) {
+                Ok(incoming) => incoming,
+                Err(e) if is_timeout(&e) && buffer.get_ref().in_request() => {
+                    Self::reject_timeout(&connection, access_log);
+                    break;
+                }
+                Err(e) if is_closed(&e) => break,
+                Err(e) => {
+                    match rejection(&e) {
+                        Some(error) => Self::reject(&connection, error, access_log),
+                        None => error!("Error reading request: {e}"),
+                    }
+                    break;
+                }
+            };
+            let request = match incoming {
+                Incoming::Request(request) => *request,
+                // 没有请求行说明对方已经关闭连接
+                Incoming::Closed => break,
+                Incoming::Rejected(request, mut response) => {
+                    set_hsts(&mut response, hsts);
+                    let entry =
+                        access_log.map(|_| AccessEntry::new(&request, peer_addr(&connection)));
+                    if let Err(e) = Self::send(&connection, response, access_log, entry) {
+                        error!("Error sending response: {e}");
+                    }
+                    state.record_request();
+                    break;
+                }
+            };
+            guard.set_busy(true);
 
-            if chunk_size == 0 {
-                // 读取最后的空行
-                let mut empty_line = String::new();
-                buffer.read_line(&mut empty_line)?;
+            let entry = access_log.map(|_| AccessEntry::new(&request, peer_addr(&connection)));
+            let (mut response, keep_alive) = Self::respond(handler, request, state);
+            set_hsts(&mut response, hsts);
+            let on_upgrade = response.take_upgrade();
+            if let Err(e) = Self::send(&connection, response, access_log, entry) {
+                error!("Error sending response: {e}");
                 break;
             }
+            state.record_request();
+            // 升级后的连接长期空闲是正常的，服务器停止时直接关闭
+            guard.set_busy(false);
+
+            if let Some(on_upgrade) = on_upgrade {
+                // 缓冲区中剩余的数据已经属于升级后的协议
+                upgrade = Some((on_upgrade, buffer.buffer().to_vec()));
+                break;
+            }
+            // 在请求边界检查是否需要停止
+            if !keep_alive || state.is_stopping() {
+                break;
+            }
+        }
+        drop(buffer);
+        match upgrade {
+            Some((on_upgrade, leftover)) => {
+                if let Err(e) = connection.tcp().set_read_timeout(None) {
+                    error!("Error clearing read timeout: {e}");
+                }
+                // 回调返回时连接随 UpgradedStream 一起关闭
+                on_upgrade.run(Box::new(UpgradedStream::new(connection, leftover)));
+            }
+            None => connection.close(),
+        }
+    }
+
+    /// 发送响应，设置了 `access_log` 时按实际写出的响应体字节数写一行访问日志
+    fn send(
+        connection: &Stream,
+        response: HttpResponse<'_>,
+        access_log: Option<&AccessLog>,
+        entry: Option<AccessEntry>,
+    ) -> io::Result<()> {
+        let status = response.status_code().to_string();
+        let mut writer = CountingWriter::new(connection);
+        let sent = response.send_response(&mut writer);
+        if let (Some(access_log), Some(mut entry)) = (access_log, entry) {
+            entry.finish(&status, writer.body_bytes());
+            access_log.log(&entry);
+        }
+        sent
+    }
+
+    /// 请求没有在限定时间内读完，返回 `408` 后关闭连接
+    fn reject_timeout(connection: &Stream, access_log: Option<&AccessLog>) {
+        let peer = peer_addr(connection);
+        match peer {
+            Some(peer) => warn!("Request from {peer} timed out"),
+            None => warn!("Request timed out"),
+        }
+        let entry = access_log.map(|_| AccessEntry::unparsed(peer));
+        if let Err(e) = Self::send(connection, request_timeout_response(), access_log, entry) {
+            warn!("Error sending 408 response: {e}");
+        }
+    }
+
+    /// 请求超出大小限制，返回对应的错误响应后关闭连接
+    fn reject(connection: &Stream, error: ParseError, access_log: Option<&AccessLog>) {
+        let peer = peer_addr(connection);
+        match peer {
+            Some(peer) => warn!("Rejected request from {peer}: {error}"),
+            None => warn!("Rejected request: {error}"),
+        }
+        let entry = access_log.map(|_| AccessEntry::unparsed(peer));
+        if let Err(e) = Self::send(connection, rejection_response(error), access_log, entry) {
+            warn!("Error sending {} response: {e}", error.status_code());
+        }
+    }
 
-            // 读取块数据
-            let mut chunk_data = vec![0u8; chunk_size];
-            buffer.read_exact(&mut chunk_data)?;
-            body.push_str(&String::from_utf8_lossy(&chunk_data));
+    /// 处理一个完整的请求，返回响应以及连接是否继续保持
+    ///
+    /// 阻塞模式和事件驱动模式共用，响应中已经设置好 `Connection` 头。
+    pub(crate) fn respond(
+        handler: &dyn Handler,
+        request: HttpRequest,
+        state: &ServerState,
+    ) -> (HttpResponse<'static>, bool) {
+        let keep_alive = Self::keep_alive(&request);
+        let http11 = *request.version() == HttpVersion::HTTP11;
+        let head = *request.method() == HttpMethod::HEAD;
 
-            // 读取块结束的 \r\n
-            let mut end_line = String::new();
-            buffer.read_line(&mut end_line)?;
+        let mut response = Self::handle_catching_panic(handler, request);
+        // HEAD 请求的响应只有响应头，长度和 GET 请求相同
+        response.set_omit_body(head);
+        if response.is_upgrade() {
+            // 服务器停止时不再建立长期占用连接的新会话
+            if state.is_stopping() {
+                let mut response =
+                    HttpResponse::new("503", None, Some("Service Unavailable".to_string()));
+                response.set_header("Connection", "close");
+                return (response, false);
+            }
+            // 升级后连接不再处理 HTTP 请求，其他状态码的响应体以关闭连接表示结束
+            let connection = if response.status_code() == "101" {
+                "Upgrade"
+            } else {
+                "close"
+            };
+            response.set_header("Connection", connection);
+            return (response, false);
         }
+        // HTTP/1.0 不支持 chunked 编码，流式响应体通过关闭连接表示结束
+        let close_delimited = response.is_streaming() && !http11;
+        if close_delimited {
+            response.set_chunked(false);
+        }
+        // 处理期间服务器可能开始停止，此时通知客户端关闭连接
+        let keep_alive = keep_alive && !close_delimited && !state.is_stopping();
+        response.set_header(
+            "Connection",
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+        (response, keep_alive)
+    }
 
-        Ok(body)
+    /// 处理器 panic 时返回 `500`，不影响工作线程和其他请求
+    pub(crate) fn handle_catching_panic(
+        handler: &dyn Handler,
+        request: HttpRequest,
+    ) -> HttpResponse<'static> {
+        let target = format!("{} {}", request.method().as_str(), request.resource_path());
+        match panic::catch_unwind(AssertUnwindSafe(|| handler.handle_request(request))) {
+            Ok(response) => response,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+                    .unwrap_or("unknown panic payload");
+                error!("Handler panicked on {target}: {message}");
+                HttpResponse::new("500", None, Some("Internal Server Error".to_string()))
+            }
+        }
+    }
+
+    /// HTTP/1.1 默认保持连接，HTTP/1.0 需要显式声明 keep-alive
+    pub(crate) fn keep_alive(request: &HttpRequest) -> bool {
+        let connection = request.header("Connection").unwrap_or("");
+        let has_token = |token: &str| {
+            connection
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+        match request.version() {
+            HttpVersion::HTTP11 => !has_token("close"),
+            _ => has_token("keep-alive"),
+        }
     }
+
+    /// 读取完整的HTTP请求内容和 chunked 请求体之后的 trailer
+    ///
+    /// 超出 `limits` 或请求体长度无法确定时返回包含 [`ParseError`] 的错误。
+    fn read_full_request<R: RequestSource>(
+        buffer: &mut BufReader<R>,
+        limits: &Limits,
+        handler: &dyn Handler,
+    ) -> Result<Incoming, std::io::Error> {
+        let mut request_lines = Vec::new();
+        let mut body = Vec::new();
+        let mut trailers = HashMap::new();
+
+        // 读取请求行
+        match read_line(buffer, limits.max_request_line(), ParseError::UriTooLong)? {
+            Some(line) if !line.is_empty() => request_lines.push(line),
+            _ => return Ok(Incoming::Closed),
+        }
+        // 读取请求头，每一行最多只能使用剩余的字节数
+        let mut header_budget = limits.max_header_bytes();
+        while let Some(line) = read_line(buffer, header_budget, ParseError::HeadersTooLarge)? {
+            if line.is_empty() {
+                break; // 空行表示请求头结束
+            }
+            if request_lines.len() > limits.max_headers() {
+                return Err(ParseError::HeadersTooLarge.into());
+            }
+            header_budget = header_budget.saturating_sub(line.len() + 2);
+            request_lines.push(line);
+        }
+        let headers = request_lines[1..]
+            .iter()
+            .filter_map(|line| line.split_once(':'));
+        let framing = parser::framing(headers, limits)?;
+
+        // 客户端等待 100 Continue 之后才会发送请求体
+        if framing != Framing::Empty {
+            let mut head = request_lines.join("\r\n");
+            head.push_str("\r\n\r\n");
+            let request = HttpRequest::from(head);
+            match check_expectation(handler, &request) {
+                Expectation::Continue => buffer.get_mut().send_continue()?,
+                Expectation::None => {}
+                Expectation::Reject(response) => {
+                    return Ok(Incoming::Rejected(Box::new(request), response));
+                }
+            }
+        }
+
+        buffer.get_mut().start_body();
+
+        // 读取请求体（如果存在），长度已经检查过，不按客户端声明的长度预先分配内存
+        match framing {
+            Framing::Empty => {}
+            Framing::Length(length) => {
+                (&mut *buffer).take(length as u64).read_to_end(&mut body)?;
+                if body.len() < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            Framing::Chunked => {
+                let mut reader = ChunkedReader::new(&mut *buffer, limits);
+                reader.read_to_end(&mut body)?;
+                trailers = reader.into_trailers();
+            }
+        }
+
+        // 请求体保留原始字节，二进制上传不能经过文本转换
+        let mut head = request_lines.join("\r\n");
+        head.push_str("\r\n\r\n");
+        let mut request = HttpRequest::from(head);
+        request.set_body(body);
+        request.set_trailers(trailers);
+        Ok(Incoming::Request(Box::new(request)))
+    }
+}
+
+/// 读取一行（不含行尾的 `\r\n`），超过 `limit` 字节时返回 `error`，连接已关闭时返回 `None`
+fn read_line<R: Read>(
+    buffer: &mut BufReader<R>,
+    limit: usize,
+    error: ParseError,
+) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let max = limit.saturating_add(2) as u64;
+    let n = (&mut *buffer).take(max).read_until(b'\n', &mut line)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    } else if n as u64 == max {
+        return Err(error.into());
+    }
+    if line.len() > limit {
+        return Err(error.into());
+    }
+    Ok(Some(String::from_utf8_lossy(&line).to_string()))
+}
+
+/// 读取错误是否由请求超限引起
+pub(crate) fn rejection(e: &io::Error) -> Option<ParseError> {
+    e.get_ref()?.downcast_ref::<ParseError>().copied()
+}
+
+/// 请求超出限制时返回的响应，之后关闭连接
+pub(crate) fn rejection_response(error: ParseError) -> HttpResponse<'static> {
+    let status_code = error.status_code();
+    let body = status_text(status_code).to_string();
+    let mut response = HttpResponse::new(status_code, None, Some(body));
+    response.set_header("Connection", "close");
+    response
+}
+
+fn peer_addr(connection: &Stream) -> Option<SocketAddr> {
+    connection.tcp().peer_addr().ok()
+}
+
+/// 给 HTTPS 响应加上 `Strict-Transport-Security`，处理器已经设置时不覆盖
+fn set_hsts(response: &mut HttpResponse, hsts: Option<&str>) {
+    if let Some(hsts) = hsts
+        && response.header("Strict-Transport-Security").is_none()
+    {
+        response.set_header("Strict-Transport-Security", hsts);
+    }
+}
+
+/// 读超时或连接被重置都视为连接已关闭，不记录错误
+pub(crate) fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
 }
 
 #[cfg(test)]
@@ -136,12 +777,16 @@ mod tests {
         // 接受连接并读取请求
         if let Ok((stream, _)) = listener.accept() {
             let mut buffer = BufReader::new(&stream);
-            let result = Route::read_full_request(&mut buffer);
+            let result =
+                Route::read_full_request(&mut buffer, &Limits::default(), &NotFoundHandler {});
 
             assert!(result.is_ok());
-            let request_content = result.unwrap();
-            assert!(request_content.contains("POST /test HTTP/1.1"));
-            assert!(request_content.contains("Hello World"));
+            let Incoming::Request(request) = result.unwrap() else {
+                panic!("request should be complete");
+            };
+            assert_eq!(*request.method(), HttpMethod::POST);
+            assert_eq!(request.path(), "/test");
+            assert_eq!(request.body(), "Hello World");
         }
 
         handle.join().unwrap();
@@ -161,14 +806,103 @@ mod tests {
 
         if let Ok((stream, _)) = listener.accept() {
             let mut buffer = BufReader::new(&stream);
-            let result = Route::read_full_request(&mut buffer);
+            let result =
+                Route::read_full_request(&mut buffer, &Limits::default(), &NotFoundHandler {});
 
             assert!(result.is_ok());
-            let request_content = result.unwrap();
-            assert!(request_content.contains("GET / HTTP/1.1"));
-            assert!(!request_content.contains("Hello World"));
+            let Incoming::Request(request) = result.unwrap() else {
+                panic!("request should be complete");
+            };
+            assert_eq!(*request.method(), HttpMethod::GET);
+            assert_eq!(request.path(), "/");
+            assert!(request.body_bytes().is_empty());
         }
 
         handle.join().unwrap();
     }
+
+    fn request(method: &str, path: &str) -> HttpRequest {
+        format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").into()
+    }
+
+    fn echo(request: HttpRequest) -> HttpResponse<'static> {
+        let params: Vec<String> = {
+            let mut params: Vec<String> = request
+                .params()
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect();
+            params.sort();
+            params
+        };
+        HttpResponse::new(
+            "200",
+            None,
+            Some(format!("{} {}", request.resource_path(), params.join("&"))),
+        )
+    }
+
+    #[test]
+    fn test_router_extracts_params() {
+        let router = Router::new()
+            .get("/api/users/:id", echo)
+            .get("/static/*rest", echo);
+
+        let response = router.handle_request(request("GET", "/api/users/42?full=1"));
+        assert_eq!(response.body(), "/api/users/42?full=1 id=42");
+
+        let response = router.handle_request(request("GET", "/static/css/a%20b.css"));
+        assert_eq!(response.body(), "/static/css/a%20b.css rest=css/a b.css");
+
+        let response = router.handle_request(request("GET", "/api/users/42/posts"));
+        assert_eq!(response.status_code(), "404");
+    }
+
+    #[test]
+    fn test_router_method_not_allowed() {
+        let router = Router::new()
+            .get("/api/users/:id", echo)
+            .delete("/api/users/:id", echo);
+
+        let response = router.handle_request(request("POST", "/api/users/1"));
+        assert_eq!(response.status_code(), "405");
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, DELETE"));
+
+        // 没有 HEAD 路由时使用 GET 路由，路径参数照常提取
+        let response = router.handle_request(request("HEAD", "/api/users/7"));
+        assert_eq!(response.status_code(), "200");
+        assert_eq!(response.body(), "/api/users/7 id=7");
+
+        // 显式注册的 HEAD 路由优先，即使注册在 GET 之后
+        let router = Router::new()
+            .get("/ping", echo)
+            .route(HttpMethod::HEAD, "/ping", |_| {
+                HttpResponse::new("204", None, None)
+            });
+        let response = router.handle_request(request("HEAD", "/ping"));
+        assert_eq!(response.status_code(), "204");
+        let response = router.handle_request(request("PUT", "/ping"));
+        assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+    }
+
+    #[test]
+    fn test_router_mounts_handlers_and_sub_routers() {
+        let api = Router::new().get("/users/:id", echo);
+        let router = Router::new()
+            .mount("/api", api)
+            .mount("/files", echo)
+            .fallback(|_| HttpResponse::new("200", None, Some("fallback".to_string())));
+
+        let response = router.handle_request(request("GET", "/api/users/7"));
+        assert_eq!(response.body(), "/users/7 id=7");
+
+        let response = router.handle_request(request("GET", "/files/a.txt?v=1"));
+        assert_eq!(response.body(), "/a.txt?v=1 ");
+
+        let response = router.handle_request(request("GET", "/filesystem"));
+        assert_eq!(response.body(), "fallback");
+
+        let response = router.handle_request(request("GET", "/api/other"));
+        assert_eq!(response.status_code(), "404");
+    }
 }
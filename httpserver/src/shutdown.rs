@@ -0,0 +1,234 @@
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct TrackedConnection {
+    stream: TcpStream,
+    busy: bool,
+}
+
+#[derive(Default)]
+struct Tracker {
+    next_id: u64,
+    connections: HashMap<u64, TrackedConnection>,
+    /// 排空超时后置为 true，之后不再接受新的连接登记
+    closed: bool,
+}
+
+/// 服务器运行状态，负责停止 accept 循环和跟踪正在处理的连接
+pub(crate) struct ServerState {
+    local_addr: SocketAddr,
+    stopping: AtomicBool,
+    tracker: Mutex<Tracker>,
+    drained: Condvar,
+    /// 事件循环自己管理的连接数，这些连接不在 `tracker` 中
+    event_connections: AtomicUsize,
+    connections_served: AtomicU64,
+    requests_served: AtomicU64,
+}
+
+impl ServerState {
+    pub(crate) fn new(local_addr: SocketAddr) -> Self {
+        Self {
+            local_addr,
+            stopping: AtomicBool::new(false),
+            tracker: Mutex::default(),
+            drained: Condvar::new(),
+            event_connections: AtomicUsize::new(0),
+            connections_served: AtomicU64::new(0),
+            requests_served: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// 通知 accept 循环停止，可以重复调用
+    pub(crate) fn stop(&self) {
+        if self.stopping.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // accept 是阻塞的，连接一次自身把它唤醒
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(match wake_addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        if let Err(e) = TcpStream::connect(wake_addr) {
+            error!("Failed to wake up accept loop: {e}");
+        }
+    }
+
+    /// 登记一个连接，返回的守卫在 drop 时注销该连接
+    ///
+    /// 排空超时之后返回 `None`，调用方应直接关闭连接。
+    pub(crate) fn register(&self, stream: &TcpStream) -> Option<ConnectionGuard<'_>> {
+        let guard = self.track(stream)?;
+        self.connections_served.fetch_add(1, Ordering::Relaxed);
+        Some(guard)
+    }
+
+    /// 跟踪一个已经计数过的连接，例如事件循环交给工作线程的升级连接
+    pub(crate) fn track(&self, stream: &TcpStream) -> Option<ConnectionGuard<'_>> {
+        let clone = match stream.try_clone() {
+            Ok(clone) => clone,
+            Err(e) => {
+                warn!("Failed to track connection: {e}");
+                return None;
+            }
+        };
+        let mut tracker = self.tracker.lock().unwrap();
+        if tracker.closed {
+            return None;
+        }
+        let id = tracker.next_id;
+        tracker.next_id += 1;
+        tracker.connections.insert(
+            id,
+            TrackedConnection {
+                stream: clone,
+                busy: false,
+            },
+        );
+        Some(ConnectionGuard { state: self, id })
+    }
+
+    /// 登记一个由事件循环管理的连接，停止时由事件循环自己负责关闭
+    pub(crate) fn connection_opened(&self) {
+        self.event_connections.fetch_add(1, Ordering::Relaxed);
+        self.connections_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        self.event_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_request(&self) {
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 关闭所有空闲（正在等待下一个请求）的 keep-alive 连接
+    pub(crate) fn close_idle(&self) {
+        let tracker = self.tracker.lock().unwrap();
+        for connection in tracker.connections.values().filter(|c| !c.busy) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// 等待所有连接处理完成，超时返回 false
+    pub(crate) fn wait_drained(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut tracker = self.tracker.lock().unwrap();
+        while !tracker.connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            tracker = self
+                .drained
+                .wait_timeout(tracker, deadline - now)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+
+    /// 强制关闭所有仍在处理的连接，返回被中断的连接数
+    pub(crate) fn close_all(&self) -> usize {
+        let mut tracker = self.tracker.lock().unwrap();
+        tracker.closed = true;
+        for connection in tracker.connections.values() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+        tracker.connections.len()
+    }
+
+    pub(crate) fn active_connections(&self) -> usize {
+        self.tracker.lock().unwrap().connections.len()
+            + self.event_connections.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn connections_served(&self) -> u64 {
+        self.connections_served.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn requests_served(&self) -> u64 {
+        self.requests_served.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn log_summary(&self, aborted: usize) {
+        info!(
+            "Server on {} shut down: {} connections, {} requests served, {} connections aborted",
+            self.local_addr,
+            self.connections_served.load(Ordering::Relaxed),
+            self.requests_served.load(Ordering::Relaxed),
+            aborted
+        );
+    }
+
+    fn unregister(&self, id: u64) {
+        let mut tracker = self.tracker.lock().unwrap();
+        tracker.connections.remove(&id);
+        if tracker.connections.is_empty() {
+            self.drained.notify_all();
+        }
+    }
+
+    fn set_busy(&self, id: u64, busy: bool) {
+        let mut tracker = self.tracker.lock().unwrap();
+        if let Some(connection) = tracker.connections.get_mut(&id) {
+            connection.busy = busy;
+        }
+    }
+}
+
+pub(crate) struct ConnectionGuard<'a> {
+    state: &'a ServerState,
+    id: u64,
+}
+
+impl ConnectionGuard<'_> {
+    /// 标记连接是否正在处理请求，空闲连接在停止时会被直接关闭
+    pub(crate) fn set_busy(&self, busy: bool) {
+        self.state.set_busy(self.id, busy);
+    }
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.state.unregister(self.id);
+    }
+}
+
+/// 收到 SIGINT/SIGTERM 时停止服务器，第二次收到信号时立即退出进程
+pub(crate) fn watch_signals(state: Arc<ServerState>) -> io::Result<()> {
+    let signaled = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&signaled))?;
+        signal_hook::flag::register(signal, Arc::clone(&signaled))?;
+    }
+
+    thread::Builder::new()
+        .name("web-signal".to_string())
+        .spawn(move || {
+            while !state.is_stopping() {
+                if signaled.load(Ordering::SeqCst) {
+                    info!("Received shutdown signal, draining connections");
+                    state.stop();
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        })?;
+    Ok(())
+}
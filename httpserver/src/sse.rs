@@ -0,0 +1,377 @@
+use http::httprequest::HttpRequest;
+use http::httpresponse::{HttpResponse, Upgraded};
+use log::debug;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender};
+use std::time::{Duration, Instant};
+
+/// 默认的心跳间隔，没有事件时定期发送注释行，避免代理因空闲断开连接
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// 没有事件时检查连接是否已经断开的间隔，服务器停止时最多延迟这么久结束
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 一个 Server-Sent Events 事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// `data` 中的换行会拆分成多个 `data:` 行，客户端收到时重新用换行连接
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            event: None,
+            data: data.into(),
+            retry: None,
+        }
+    }
+
+    /// 事件 ID，客户端重连时通过 `Last-Event-ID` 请求头带回最后收到的 ID
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    /// 事件类型，客户端用 `addEventListener(type, ...)` 接收，默认为 `message`
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(single_line(event));
+        self
+    }
+
+    /// 连接断开后客户端等待多久重连
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// 按 `text/event-stream` 格式编码，以空行结束
+    fn encode(&self) -> String {
+        let mut encoded = String::new();
+        if let Some(id) = &self.id {
+            encoded.push_str(&format!("id: {id}\n"));
+        }
+        if let Some(event) = &self.event {
+            encoded.push_str(&format!("event: {event}\n"));
+        }
+        if let Some(retry) = self.retry {
+            encoded.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            encoded.push_str(&format!("data: {line}\n"));
+        }
+        encoded.push('\n');
+        encoded
+    }
+}
+
+/// `id` 和 `event` 中不能有换行，否则会被解析成另一个字段
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n', '\0'], "")
+}
+
+/// 向一个 SSE 连接推送事件，可以克隆后在任意线程中使用
+///
+/// 连接断开后 `send` 返回错误，所有 `EventSender` 都被 drop 后服务器结束响应并关闭连接。
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<Event>,
+    closed: Arc<AtomicBool>,
+}
+
+impl EventSender {
+    /// 发送一个事件，连接已经断开时返回包含该事件的错误
+    pub fn send(&self, event: Event) -> Result<(), SendError<Event>> {
+        if self.is_closed() {
+            return Err(SendError(event));
+        }
+        self.sender.send(event)
+    }
+
+    /// 客户端是否已经断开连接或服务器已经停止
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// `text/event-stream` 响应的配置
+///
+/// ```no_run
+/// use httpserver::route::Router;
+/// use httpserver::sse::{Event, EventStream};
+/// use std::thread;
+///
+/// let router = Router::new().get("/progress", |request| {
+///     let stream = EventStream::new(&request);
+///     // 客户端重连时从上次收到的事件之后继续
+///     let start: u32 = stream.last_event_id().and_then(|id| id.parse().ok()).unwrap_or(0);
+///     let (sender, response) = stream.into_response();
+///     thread::spawn(move || {
+///         for step in start + 1..=100 {
+///             let event = Event::new(format!("{step}%")).id(&step.to_string());
+///             if sender.send(event).is_err() {
+///                 break;
+///             }
+///         }
+///     });
+///     response
+/// });
+/// ```
+///
+/// 每个连接会一直占用一个工作线程，服务器停止时连接会被关闭，不会阻塞停止。
+/// 异步服务器上推送事件的循环在 tokio 的阻塞线程池中运行，同样一直占用一个线程；
+/// HTTP/2 连接上事件作为流的数据发送，发送端全部 drop 后结束流。
+pub struct EventStream {
+    last_event_id: Option<String>,
+    heartbeat: Duration,
+    retry: Option<Duration>,
+}
+
+impl EventStream {
+    pub fn new(request: &HttpRequest) -> Self {
+        Self {
+            last_event_id: request
+                .header("Last-Event-ID")
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty()),
+            heartbeat: HEARTBEAT_INTERVAL,
+            retry: None,
+        }
+    }
+
+    /// 客户端重连时带回的最后一个事件 ID
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// 没有事件时发送心跳注释的间隔，默认 15 秒
+    pub fn heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// 连接建立时通知客户端断开后等待多久重连
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// 创建响应，之后通过返回的 [`EventSender`] 推送事件
+    ///
+    /// 响应发送前推送的事件会先缓存起来，连接建立后立即发送。
+    pub fn into_response(self) -> (EventSender, HttpResponse<'static>) {
+        let (sender, receiver) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let sender = EventSender {
+            sender,
+            closed: Arc::clone(&closed),
+        };
+        let headers = HashMap::from([
+            ("Content-Type", "text/event-stream; charset=utf-8"),
+            ("Cache-Control", "no-cache"),
+        ]);
+        let response = HttpResponse::upgrade("200", Some(headers), move |mut connection| {
+            if let Err(e) = self.serve(connection.as_mut(), &receiver) {
+                debug!("Event stream ended: {e}");
+            }
+            closed.store(true, Ordering::SeqCst);
+            connection.close();
+        });
+        (sender, response)
+    }
+
+    /// 写出事件和心跳，直到所有发送端被 drop 或连接断开
+    fn serve(&self, connection: &mut dyn Upgraded, receiver: &Receiver<Event>) -> io::Result<()> {
+        if let Some(retry) = self.retry {
+            write!(connection, "retry: {}\n\n", retry.as_millis())?;
+            connection.flush()?;
+        }
+        let mut last_write = Instant::now();
+        loop {
+            let wait = self
+                .heartbeat
+                .saturating_sub(last_write.elapsed())
+                .min(CHECK_INTERVAL);
+            match receiver.recv_timeout(wait) {
+                Ok(event) => {
+                    connection.write_all(event.encode().as_bytes())?;
+                    // 一次写出已经排队的所有事件
+                    while let Ok(event) = receiver.try_recv() {
+                        connection.write_all(event.encode().as_bytes())?;
+                    }
+                    connection.flush()?;
+                    last_write = Instant::now();
+                }
+                Err(RecvTimeoutError::Timeout) if last_write.elapsed() >= self.heartbeat => {
+                    connection.write_all(b": heartbeat\n\n")?;
+                    connection.flush()?;
+                    last_write = Instant::now();
+                }
+                Err(RecvTimeoutError::Timeout) => check_open(connection)?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// 客户端不会在 SSE 连接上发送数据，能读到结束说明对方已经断开或服务器已经关闭连接
+fn check_open(connection: &mut dyn Upgraded) -> io::Result<()> {
+    connection.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let mut buffer = [0u8; 512];
+    match connection.read(&mut buffer) {
+        Ok(0) => Err(io::ErrorKind::ConnectionAborted.into()),
+        Ok(_) => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::httpserver::{Engine, HttpServer};
+    use crate::route::Router;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn test_event_encoding() {
+        let event = Event::new("line one\r\nline two")
+            .id("7\nid: 8")
+            .event("progress")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.encode(),
+            "id: 7id: 8\nevent: progress\nretry: 3000\ndata: line one\ndata: line two\n\n"
+        );
+        assert_eq!(Event::new("").encode(), "data: \n\n");
+    }
+
+    /// 读取一个以空行结束的事件块
+    fn read_block(reader: &mut impl BufRead) -> String {
+        let mut block = String::new();
+        loop {
+            let mut line = String::new();
+            assert_ne!(
+                reader.read_line(&mut line).unwrap(),
+                0,
+                "stream ended early"
+            );
+            if line == "\n" {
+                return block;
+            }
+            block.push_str(&line);
+        }
+    }
+
+    #[test]
+    fn test_event_stream_resume_heartbeat_and_disconnect() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            // 处理器把发送端交给测试，模拟其他线程推送事件
+            let senders = Arc::new(Mutex::new(Vec::new()));
+            let registry = Arc::clone(&senders);
+            let router = Router::new().get("/events", move |request: HttpRequest| {
+                let stream = EventStream::new(&request)
+                    .heartbeat(Duration::from_millis(200))
+                    .retry(Duration::from_secs(1));
+                let start: u32 = stream
+                    .last_event_id()
+                    .and_then(|id| id.parse().ok())
+                    .unwrap_or(0);
+                let (sender, response) = stream.into_response();
+                for id in start + 1..=start + 2 {
+                    let _ = sender.send(Event::new(format!("step {id}")).id(&id.to_string()));
+                }
+                registry.lock().unwrap().push(sender);
+                response
+            });
+            let handle = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine)
+                .spawn()
+                .unwrap();
+            let addr = handle.local_addr();
+
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+                .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 5\r\n\r\n")
+                .unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let head = read_head(&mut reader);
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(head.contains("Content-Type: text/event-stream"));
+            assert!(head.contains("Connection: close"));
+            assert!(!head.contains("Content-Length"));
+            assert!(!head.contains("Transfer-Encoding"));
+
+            assert_eq!(read_block(&mut reader), "retry: 1000\n");
+            assert_eq!(read_block(&mut reader), "id: 6\ndata: step 6\n");
+            assert_eq!(read_block(&mut reader), "id: 7\ndata: step 7\n");
+
+            // 其他线程推送的事件
+            let sender = senders.lock().unwrap().pop().unwrap();
+            let pusher = sender.clone();
+            thread::spawn(move || pusher.send(Event::new("a\nb").event("done")))
+                .join()
+                .unwrap()
+                .unwrap();
+            assert_eq!(read_block(&mut reader), "event: done\ndata: a\ndata: b\n");
+            // 没有事件时的心跳
+            assert_eq!(read_block(&mut reader), ": heartbeat\n");
+
+            // 客户端断开后发送端能感知到
+            drop(reader);
+            drop(stream);
+            let start = Instant::now();
+            while !sender.is_closed() {
+                assert!(
+                    start.elapsed() < Duration::from_secs(3),
+                    "disconnect not detected"
+                );
+                thread::sleep(Duration::from_millis(20));
+            }
+            assert!(sender.send(Event::new("lost")).is_err());
+
+            // 打开的事件流不会阻塞服务器停止
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut reader = BufReader::new(stream);
+            read_head(&mut reader);
+            assert_eq!(read_block(&mut reader), "retry: 1000\n");
+            let start = Instant::now();
+            handle.shutdown();
+            assert!(start.elapsed() < Duration::from_secs(2));
+            assert!(senders.lock().unwrap().iter().all(EventSender::is_closed));
+        }
+    }
+
+    fn read_head(reader: &mut impl BufRead) -> String {
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            assert_ne!(reader.read_line(&mut head).unwrap(), 0);
+        }
+        head
+    }
+}
@@ -0,0 +1,285 @@
+use crate::route::{CONTINUE, RequestSource};
+use crate::tls::Stream;
+use http::httpresponse::HttpResponse;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// 最低传输速率检查前的宽限时间，避免请求体刚开始传输时就被判定为过慢
+const MIN_RATE_GRACE: Duration = Duration::from_secs(1);
+
+/// 连接读写超时配置
+///
+/// 请求头和请求体的超时从收到第一个字节开始计算，客户端每次只发送少量数据也无法延长，
+/// 用于防止 slowloris 之类的慢速攻击长时间占用连接。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    idle: Duration,
+    header_read: Duration,
+    body_read: Duration,
+    write: Duration,
+    min_body_rate: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self {
+            idle: Duration::from_secs(5),
+            header_read: Duration::from_secs(10),
+            body_read: Duration::from_secs(60),
+            write: Duration::from_secs(30),
+            min_body_rate: 500,
+        }
+    }
+
+    /// keep-alive 连接等待下一个请求的最长时间，超时后直接关闭连接
+    pub fn idle(mut self, idle: Duration) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// 从收到请求的第一个字节到读完请求头的最长时间，超时返回 `408`
+    pub fn header_read(mut self, header_read: Duration) -> Self {
+        self.header_read = header_read;
+        self
+    }
+
+    /// 读取请求体的最长时间，超时返回 `408`
+    pub fn body_read(mut self, body_read: Duration) -> Self {
+        self.body_read = body_read;
+        self
+    }
+
+    /// 写出响应时单次写操作的最长阻塞时间
+    pub fn write(mut self, write: Duration) -> Self {
+        self.write = write;
+        self
+    }
+
+    /// 请求体的最低平均传输速率（字节/秒），低于该速率返回 `408`，`0` 表示不限制
+    pub fn min_body_rate(mut self, bytes_per_second: u64) -> Self {
+        self.min_body_rate = bytes_per_second;
+        self
+    }
+
+    pub(crate) fn write_timeout(&self) -> Duration {
+        self.write
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn idle_timeout(&self) -> Duration {
+        self.idle
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn header_read_timeout(&self) -> Duration {
+        self.header_read
+    }
+}
+
+/// 跟踪一个请求的读取进度，计算读取数据的截止时间
+pub(crate) struct RequestClock {
+    timeouts: Timeouts,
+    waiting_since: Instant,
+    /// 收到当前请求第一个字节的时间
+    started: Option<Instant>,
+    /// 开始读取请求体的时间
+    body_started: Option<Instant>,
+    body_received: u64,
+}
+
+impl RequestClock {
+    pub(crate) fn new(timeouts: Timeouts) -> Self {
+        Self {
+            timeouts,
+            waiting_since: Instant::now(),
+            started: None,
+            body_started: None,
+            body_received: 0,
+        }
+    }
+
+    /// 开始等待下一个请求
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new(self.timeouts);
+    }
+
+    /// 从连接上读到了 `n` 个字节
+    pub(crate) fn received(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        if self.body_started.is_some() {
+            self.body_received += n as u64;
+        }
+    }
+
+    /// 请求头已经读完，开始读取请求体
+    pub(crate) fn start_body(&mut self) {
+        self.body_started = Some(Instant::now());
+        self.body_received = 0;
+    }
+
+    /// 根据当前请求在缓冲区中已经收到的字节数更新进度，用于非阻塞连接
+    ///
+    /// `header_len` 为解析器得到的请求头长度，请求头还没有读完时为 `None`。
+    pub(crate) fn observe(&mut self, received: usize, header_len: Option<usize>) {
+        if received == 0 {
+            return;
+        }
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        let Some(header_len) = header_len else {
+            return;
+        };
+        if self.body_started.is_none() {
+            self.start_body();
+        }
+        self.body_received = received.saturating_sub(header_len) as u64;
+    }
+
+    /// 是否已经收到了当前请求的数据，此时超时需要返回 `408`
+    pub(crate) fn in_request(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// 下一次读取数据的截止时间
+    pub(crate) fn deadline(&self) -> Instant {
+        let timeouts = &self.timeouts;
+        match (self.started, self.body_started) {
+            (_, Some(body_started)) => {
+                let deadline = body_started + timeouts.body_read;
+                if timeouts.min_body_rate == 0 {
+                    return deadline;
+                }
+                // 已收到的数据按最低速率能换来的时间
+                let earned = Duration::from_secs_f64(
+                    self.body_received as f64 / timeouts.min_body_rate as f64,
+                );
+                deadline.min(body_started + earned.max(MIN_RATE_GRACE))
+            }
+            (Some(started), None) => started + timeouts.header_read,
+            (None, None) => self.waiting_since + timeouts.idle,
+        }
+    }
+}
+
+/// 每次读取前按请求进度设置读超时的连接
+pub(crate) struct TimedStream<'a> {
+    stream: &'a Stream,
+    clock: RequestClock,
+}
+
+impl<'a> TimedStream<'a> {
+    pub(crate) fn new(stream: &'a Stream, timeouts: Timeouts) -> Self {
+        Self {
+            stream,
+            clock: RequestClock::new(timeouts),
+        }
+    }
+
+    /// 开始等待下一个请求，`pending` 表示缓冲区中已经有下一个请求的数据
+    pub(crate) fn wait_request(&mut self, pending: bool) {
+        self.clock.reset();
+        if pending {
+            self.clock.received(1);
+        }
+    }
+
+    pub(crate) fn in_request(&self) -> bool {
+        self.clock.in_request()
+    }
+}
+
+impl Read for TimedStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self
+            .clock
+            .deadline()
+            .saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request read timed out",
+            ));
+        }
+        self.stream.tcp().set_read_timeout(Some(remaining))?;
+        let mut stream = self.stream;
+        let n = stream.read(buf)?;
+        self.clock.received(n);
+        Ok(n)
+    }
+}
+
+impl RequestSource for TimedStream<'_> {
+    fn start_body(&mut self) {
+        self.clock.start_body();
+    }
+
+    fn send_continue(&mut self) -> io::Result<()> {
+        let mut stream = self.stream;
+        stream.write_all(CONTINUE)
+    }
+}
+
+/// 读超时返回的错误类型
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// 请求没有在限定时间内读完时返回的响应
+pub(crate) fn request_timeout_response() -> HttpResponse<'static> {
+    let mut response = HttpResponse::new("408", None, Some("Request Timeout".to_string()));
+    response.set_header("Connection", "close");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_clock_deadlines() {
+        let timeouts = Timeouts::new()
+            .idle(Duration::from_secs(5))
+            .header_read(Duration::from_secs(10))
+            .body_read(Duration::from_secs(60))
+            .min_body_rate(1000);
+        let mut clock = RequestClock::new(timeouts);
+        let waiting_since = Instant::now();
+        assert!(!clock.in_request());
+        assert!(clock.deadline() <= waiting_since + Duration::from_secs(5));
+
+        clock.observe(b"GET / HTTP/1.1\r\n".len(), None);
+        assert!(clock.in_request());
+        let header_deadline = clock.deadline();
+        assert!(header_deadline > waiting_since + Duration::from_secs(9));
+        // 继续收到数据不会延长请求头的截止时间
+        clock.observe(b"GET / HTTP/1.1\r\nHost: a".len(), None);
+        assert_eq!(clock.deadline(), header_deadline);
+
+        // 请求体只收到 10 个字节，只能得到最低的宽限时间
+        let head = b"POST / HTTP/1.1\r\n\r\n".len();
+        clock.observe(head + 10, Some(head));
+        let body_deadline = clock.deadline();
+        assert!(body_deadline < Instant::now() + Duration::from_secs(2));
+        // 收到 5000 个字节后可以多等 5 秒
+        clock.observe(head + 5000, Some(head));
+        assert!(clock.deadline() > body_deadline + Duration::from_secs(3));
+
+        clock.reset();
+        assert!(!clock.in_request());
+    }
+}
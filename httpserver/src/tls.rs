@@ -0,0 +1,531 @@
+use crate::handler::Handler;
+use crate::httpserver::ServerError;
+use crate::shutdown::ServerState;
+use http::httprequest::{HttpMethod, HttpRequest};
+use http::httpresponse::{HttpResponse, Upgraded};
+use log::{error, info};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use signal_hook::consts::SIGHUP;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// 允许协商的最低 TLS 版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsVersion {
+    /// TLS 1.2 和 TLS 1.3
+    #[default]
+    Tls12,
+    /// 只允许 TLS 1.3
+    Tls13,
+}
+
+/// 一组 PEM 格式的证书链和私钥文件
+#[derive(Debug, Clone)]
+struct CertFiles {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl CertFiles {
+    fn new(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Self {
+        Self {
+            cert: cert.as_ref().to_path_buf(),
+            key: key.as_ref().to_path_buf(),
+        }
+    }
+
+    fn load(&self, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, ServerError> {
+        let cert = self.cert.display();
+        let chain = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| ServerError::Tls(format!("Failed to read certificate {cert}: {e}")))?;
+        if chain.is_empty() {
+            return Err(ServerError::Tls(format!("No certificate found in {cert}")));
+        }
+        let key = self.key.display();
+        let private_key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| ServerError::Tls(format!("Failed to read private key {key}: {e}")))?;
+        let certified = CertifiedKey::from_der(chain, private_key, provider).map_err(|e| {
+            ServerError::Tls(format!("Invalid certificate {cert} or key {key}: {e}"))
+        })?;
+        Ok(Arc::new(certified))
+    }
+}
+
+/// HTTPS 配置：证书、SNI、ALPN 和最低 TLS 版本
+///
+/// 证书和私钥在服务器启动时读取，之后可以通过 [`ServerHandle::reload_tls`] 或
+/// `SIGHUP` 信号重新读取，正在进行的连接继续使用旧证书。
+///
+/// [`ServerHandle::reload_tls`]: crate::httpserver::ServerHandle::reload_tls
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    default: CertFiles,
+    sni: Vec<(String, CertFiles)>,
+    alpn: Vec<Vec<u8>>,
+    min_version: TlsVersion,
+}
+
+impl TlsConfig {
+    /// 客户端没有发送 SNI 或没有匹配的证书时使用的证书链和私钥
+    pub fn new(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Self {
+        Self {
+            default: CertFiles::new(cert, key),
+            sni: Vec::new(),
+            alpn: vec![b"http/1.1".to_vec()],
+            min_version: TlsVersion::default(),
+        }
+    }
+
+    /// 客户端通过 SNI 请求 `server_name` 时使用的证书，支持 `*.example.com` 形式的通配符
+    pub fn sni(mut self, server_name: &str, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Self {
+        self.sni
+            .push((server_name.to_ascii_lowercase(), CertFiles::new(cert, key)));
+        self
+    }
+
+    /// ALPN 协商时服务器支持的协议，按优先级排列，默认只有 `http/1.1`
+    ///
+    /// 阻塞模式的服务器和关闭了 HTTP/2 的异步服务器会忽略其中的 `h2`。
+    pub fn alpn(mut self, protocols: &[&str]) -> Self {
+        self.alpn = protocols
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect();
+        self
+    }
+
+    /// 去掉 ALPN 中服务器不支持的协议
+    pub(crate) fn without_alpn(mut self, protocol: &str) -> Self {
+        self.alpn.retain(|p| p != protocol.as_bytes());
+        self
+    }
+
+    /// 允许协商的最低 TLS 版本，默认允许 TLS 1.2
+    pub fn min_version(mut self, min_version: TlsVersion) -> Self {
+        self.min_version = min_version;
+        self
+    }
+
+    /// 读取所有证书，生成 rustls 的服务器配置
+    pub(crate) fn build(&self) -> Result<Tls, ServerError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let resolver = Arc::new(CertResolver {
+            certs: RwLock::new(self.load(&provider)?),
+        });
+        let versions: &[&'static rustls::SupportedProtocolVersion] = match self.min_version {
+            TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+            TlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+        let mut server_config = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_protocol_versions(versions)
+            .map_err(|e| ServerError::Tls(e.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        server_config.alpn_protocols = self.alpn.clone();
+
+        Ok(Tls {
+            config: self.clone(),
+            provider,
+            resolver,
+            server_config: Arc::new(server_config),
+        })
+    }
+
+    fn load(&self, provider: &CryptoProvider) -> Result<Certs, ServerError> {
+        let mut by_name = HashMap::new();
+        for (server_name, files) in &self.sni {
+            by_name.insert(server_name.clone(), files.load(provider)?);
+        }
+        Ok(Certs {
+            default: self.default.load(provider)?,
+            by_name,
+        })
+    }
+}
+
+/// 已经加载的 TLS 配置
+pub(crate) struct Tls {
+    config: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    resolver: Arc<CertResolver>,
+    server_config: Arc<ServerConfig>,
+}
+
+impl Tls {
+    /// 在新接受的 TCP 连接上开始 TLS 握手，握手在第一次读写时完成
+    pub(crate) fn accept(&self, tcp: TcpStream) -> io::Result<Stream> {
+        let connection = ServerConnection::new(Arc::clone(&self.server_config))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Stream::Tls {
+            tcp: tcp.try_clone()?,
+            tls: Box::new(RefCell::new(StreamOwned::new(connection, tcp))),
+        })
+    }
+
+    /// rustls 的服务器配置，证书通过共享的选择器在重新加载后生效
+    #[cfg(feature = "async")]
+    pub(crate) fn server_config(&self) -> &Arc<ServerConfig> {
+        &self.server_config
+    }
+
+    /// 重新读取所有证书，任何一个读取失败时继续使用原来的证书
+    pub(crate) fn reload(&self) -> Result<(), ServerError> {
+        let certs = self.config.load(&self.provider)?;
+        *self.resolver.certs.write().unwrap() = certs;
+        info!("TLS certificates reloaded");
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Certs {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl Certs {
+    /// 按 SNI 选择证书：先精确匹配，再匹配上一级域名的通配符证书
+    fn find(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let Some(server_name) = server_name.map(str::to_ascii_lowercase) else {
+            return Arc::clone(&self.default);
+        };
+        let wildcard = server_name
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{parent}"));
+        self.by_name
+            .get(&server_name)
+            .or_else(|| wildcard.and_then(|wildcard| self.by_name.get(&wildcard)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+/// 按 SNI 选择证书，证书可以在运行时替换
+#[derive(Debug)]
+struct CertResolver {
+    certs: RwLock<Certs>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certs.read().unwrap().find(client_hello.server_name()))
+    }
+}
+
+/// HTTPS 响应中的 `Strict-Transport-Security` 配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    max_age: Duration,
+    include_subdomains: bool,
+    preload: bool,
+}
+
+impl Hsts {
+    /// 浏览器记住只使用 HTTPS 访问的时间
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// 同时要求所有子域名使用 HTTPS
+    pub fn include_subdomains(mut self, include_subdomains: bool) -> Self {
+        self.include_subdomains = include_subdomains;
+        self
+    }
+
+    /// 允许加入浏览器内置的 HSTS 预加载列表
+    pub fn preload(mut self, preload: bool) -> Self {
+        self.preload = preload;
+        self
+    }
+
+    /// `Strict-Transport-Security` 响应头的值
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+impl Default for Hsts {
+    /// 一年，不包括子域名
+    fn default() -> Self {
+        Self::new(Duration::from_secs(365 * 24 * 60 * 60))
+    }
+}
+
+/// 明文 HTTP 监听端口上的处理器，把所有请求重定向到对应的 `https://` 地址
+///
+/// `GET` 和 `HEAD` 返回 `301`，其他方法返回 `308` 要求客户端保留方法和请求体。
+pub(crate) struct HttpsRedirect {
+    /// HTTPS 监听的端口，`443` 时 `Location` 中省略端口
+    port: u16,
+    /// 请求没有 `Host` 时使用的主机名
+    default_host: String,
+}
+
+impl HttpsRedirect {
+    pub(crate) fn new(port: u16, default_host: &str) -> Self {
+        Self {
+            port,
+            default_host: default_host.to_string(),
+        }
+    }
+
+    /// 重定向的目标地址，保留路径和查询参数
+    fn location(&self, request: &HttpRequest) -> String {
+        let mut target = request.resource_path();
+        let mut host = request.header("Host").map(str::trim);
+        // 绝对形式的请求目标 `http://host/path`
+        if let Some(rest) = target.strip_prefix("http://") {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            host = Some(authority);
+            target = path;
+        }
+        let host = host
+            .filter(|host| !host.is_empty())
+            .unwrap_or(&self.default_host);
+        let host = strip_port(host);
+        let path = if target.starts_with('/') { target } else { "/" };
+        match self.port {
+            443 => format!("https://{host}{path}"),
+            port => format!("https://{host}:{port}{path}"),
+        }
+    }
+}
+
+impl Handler for HttpsRedirect {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        let status_code = match request.method() {
+            HttpMethod::GET | HttpMethod::HEAD => "301",
+            _ => "308",
+        };
+        let mut response = HttpResponse::new(status_code, None, None);
+        response.set_header("Location", &self.location(&request));
+        response
+    }
+
+    /// 不需要请求体，直接返回重定向
+    fn check_continue(&self, request: &HttpRequest) -> Option<HttpResponse<'static>> {
+        Some(self.handle_request(request.clone()))
+    }
+}
+
+/// 去掉 `Host` 中的端口，IPv6 地址保留方括号
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    }
+}
+
+/// 服务器一侧的连接，HTTPS 连接在 TCP 之上加一层 TLS
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    Tls {
+        /// 同一个 socket 的副本，用于设置超时和关闭连接
+        tcp: TcpStream,
+        tls: Box<RefCell<StreamOwned<ServerConnection, TcpStream>>>,
+    },
+}
+
+impl Stream {
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(tcp) | Stream::Tls { tcp, .. } => tcp,
+        }
+    }
+
+    /// 关闭连接前通知对方，TLS 连接发送 `close_notify`，否则对方无法区分截断和正常结束
+    pub(crate) fn close(&self) {
+        if let Stream::Tls { tls, .. } = self {
+            let mut tls = tls.borrow_mut();
+            let StreamOwned { conn, sock } = &mut *tls;
+            conn.send_close_notify();
+            let _ = conn.complete_io(sock);
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(tcp) => (&*tcp).read(buf),
+            Stream::Tls { tls, .. } => tls.borrow_mut().read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(tcp) => (&*tcp).write(buf),
+            Stream::Tls { tls, .. } => tls.borrow_mut().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(tcp) => (&*tcp).flush(),
+            Stream::Tls { tls, .. } => tls.borrow_mut().flush(),
+        }
+    }
+}
+
+/// 协议升级后交给处理器的连接，先读出升级前已经缓冲的数据
+pub(crate) struct UpgradedStream {
+    stream: Stream,
+    /// 读取 HTTP 请求时多读到的数据，属于升级后的协议
+    leftover: Vec<u8>,
+    position: usize,
+    closed: bool,
+}
+
+impl UpgradedStream {
+    pub(crate) fn new(stream: Stream, leftover: Vec<u8>) -> Self {
+        Self {
+            stream,
+            leftover,
+            position: 0,
+            closed: false,
+        }
+    }
+}
+
+impl Read for UpgradedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.leftover.len() {
+            let n = buf.len().min(self.leftover.len() - self.position);
+            buf[..n].copy_from_slice(&self.leftover[self.position..self.position + n]);
+            self.position += n;
+            return Ok(n);
+        }
+        (&self.stream).read(buf)
+    }
+}
+
+impl Write for UpgradedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&self.stream).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.stream).flush()
+    }
+}
+
+impl Upgraded for UpgradedStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.tcp().set_read_timeout(timeout)
+    }
+
+    fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.stream.close();
+            let _ = self.stream.tcp().shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for UpgradedStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// 收到 SIGHUP 时重新读取证书，直到服务器停止
+pub(crate) fn watch_reload(tls: Arc<Tls>, state: Arc<ServerState>) -> io::Result<()> {
+    let signaled = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, Arc::clone(&signaled))?;
+
+    thread::Builder::new()
+        .name("web-reload".to_string())
+        .spawn(move || {
+            while !state.is_stopping() {
+                if signaled.swap(false, Ordering::SeqCst) {
+                    info!("Received SIGHUP, reloading TLS certificates");
+                    if let Err(e) = tls.reload() {
+                        error!("{e}, keeping the previous certificates");
+                    }
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect(raw: &str) -> HttpResponse<'static> {
+        HttpsRedirect::new(8443, "example.com").handle_request(raw.to_string().into())
+    }
+
+    #[test]
+    fn test_redirect_preserves_path_and_query() {
+        let response = redirect("GET /a/b?x=1&y=2 HTTP/1.1\r\nHost: www.test:8080\r\n\r\n");
+        assert_eq!(response.status_code(), "301");
+        assert_eq!(
+            response.header("Location"),
+            Some("https://www.test:8443/a/b?x=1&y=2")
+        );
+
+        let response = redirect("POST /form HTTP/1.1\r\nHost: [::1]:80\r\n\r\n");
+        assert_eq!(response.status_code(), "308");
+        assert_eq!(response.header("Location"), Some("https://[::1]:8443/form"));
+
+        // 没有 Host 时使用配置的主机名，绝对形式的请求目标使用其中的主机
+        let response = redirect("GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(
+            response.header("Location"),
+            Some("https://example.com:8443/")
+        );
+        let response = redirect("GET http://other.test/x HTTP/1.1\r\nHost: ignored\r\n\r\n");
+        assert_eq!(
+            response.header("Location"),
+            Some("https://other.test:8443/x")
+        );
+
+        let response = HttpsRedirect::new(443, "example.com").handle_request(
+            "GET /x HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                .to_string()
+                .into(),
+        );
+        assert_eq!(response.header("Location"), Some("https://example.com/x"));
+    }
+
+    #[test]
+    fn test_hsts_header_value() {
+        assert_eq!(Hsts::default().header_value(), "max-age=31536000");
+        let hsts = Hsts::new(Duration::from_secs(60))
+            .include_subdomains(true)
+            .preload(true);
+        assert_eq!(
+            hsts.header_value(),
+            "max-age=60; includeSubDomains; preload"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::timeout::Timeouts;
+use http::httpresponse::{OnUpgrade, Upgraded};
+use log::{debug, warn};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch};
+
+/// 连接和回调之间每个方向最多缓存的数据块数
+const CHANNEL_CAPACITY: usize = 16;
+
+/// 每次从连接读取的最大字节数
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// 异步服务器上升级后的连接：回调在 tokio 的阻塞线程池中运行，
+/// 读写的数据通过 channel 和负责连接的异步任务交换
+pub(crate) struct Bridge {
+    /// 从连接读到的数据，发送端 drop 后回调读到结束
+    pub(crate) incoming: mpsc::Sender<Vec<u8>>,
+    /// 回调写出的数据，回调返回或关闭连接后接收端返回 `None`
+    pub(crate) outgoing: mpsc::Receiver<Vec<u8>>,
+}
+
+impl Bridge {
+    /// 在阻塞线程池中运行 `on_upgrade`，`leftover` 为读取 HTTP 请求时多读到的数据
+    pub(crate) fn spawn(on_upgrade: OnUpgrade, leftover: Vec<u8>) -> Self {
+        let (incoming, incoming_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let (outgoing_sender, outgoing) = mpsc::channel(CHANNEL_CAPACITY);
+        let stream = ChannelStream {
+            runtime: Handle::current(),
+            incoming: incoming_receiver,
+            outgoing: Some(outgoing_sender),
+            pending: leftover,
+            position: 0,
+            read_timeout: None,
+        };
+        tokio::task::spawn_blocking(move || on_upgrade.run(Box::new(stream)));
+        Self { incoming, outgoing }
+    }
+}
+
+/// 在 HTTP/1.x 连接和回调之间转发数据，直到回调结束、连接出错或服务器停止
+pub(crate) async fn serve<S>(
+    stream: &mut S,
+    bridge: Bridge,
+    stop: &mut watch::Receiver<bool>,
+    timeouts: Timeouts,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Bridge {
+        incoming,
+        mut outgoing,
+    } = bridge;
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut incoming = Some(incoming);
+    // 读到但回调还没有取走的数据，回调不读取时不再继续读连接
+    let mut unread: Option<Vec<u8>> = None;
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let sender = incoming.clone();
+        tokio::select! {
+            read = reader.read(&mut chunk), if incoming.is_some() && unread.is_none() => match read {
+                Ok(n) if n > 0 => unread = Some(chunk[..n].to_vec()),
+                // 对方关闭写端后回调读到结束，回调仍然可以继续写
+                _ => incoming = None,
+            },
+            permit = async { sender?.reserve_owned().await.ok() }, if unread.is_some() => {
+                match (permit, unread.take()) {
+                    (Some(permit), Some(data)) => {
+                        permit.send(data);
+                    }
+                    // 回调已经不再读取
+                    _ => incoming = None,
+                }
+            }
+            data = outgoing.recv() => {
+                let Some(data) = data else {
+                    break;
+                };
+                match tokio::time::timeout(timeouts.write_timeout(), writer.write_all(&data)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        debug!("Error writing to upgraded connection: {e}");
+                        break;
+                    }
+                    Err(_) => {
+                        warn!("Timed out writing to upgraded connection");
+                        break;
+                    }
+                }
+            }
+            // 和阻塞模式一样，服务器停止时直接关闭升级后的连接
+            _ = stop.changed() => break,
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// 交给升级回调的连接，读写都在阻塞线程中进行
+struct ChannelStream {
+    runtime: Handle,
+    incoming: mpsc::Receiver<Vec<u8>>,
+    outgoing: Option<mpsc::Sender<Vec<u8>>>,
+    pending: Vec<u8>,
+    position: usize,
+    read_timeout: Option<Duration>,
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.pending.len() {
+            let received = match self.read_timeout {
+                Some(timeout) => self
+                    .runtime
+                    .block_on(tokio::time::timeout(timeout, self.incoming.recv()))
+                    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?,
+                None => self.incoming.blocking_recv(),
+            };
+            match received {
+                Some(data) => {
+                    self.pending = data;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.position);
+        buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let closed = || io::Error::from(io::ErrorKind::BrokenPipe);
+        self.outgoing
+            .as_ref()
+            .ok_or_else(closed)?
+            .blocking_send(buf.to_vec())
+            .map_err(|_| closed())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Upgraded for ChannelStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.outgoing = None;
+        self.incoming.close();
+    }
+}
@@ -0,0 +1,1005 @@
+use crate::handler::Handler;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http::httprequest::{HttpMethod, HttpRequest, HttpVersion};
+use http::httpresponse::{HttpResponse, Upgraded};
+use ring::digest::{SHA1_FOR_LEGACY_USE_ONLY, digest};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, str};
+
+/// 计算 `Sec-WebSocket-Accept` 时拼接在密钥后面的固定 GUID
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// 默认的最大消息长度，分片消息按拼接后的总长度计算
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// 发送消息时单个帧的默认最大长度，更长的消息拆分成多个帧
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// 控制帧的最大负载长度
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// 每次从连接读取的最大字节数
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// 一条完整的 WebSocket 消息，分片消息在接收时已经拼接好
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// 收到时已经自动回复了 `Pong`
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// 收到时已经自动回复了关闭帧，之后不能再收发消息
+    Close(Option<CloseFrame>),
+}
+
+/// 关闭帧中的状态码和原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    /// 正常关闭
+    pub const NORMAL: u16 = 1000;
+    /// 服务器停止或页面离开
+    pub const GOING_AWAY: u16 = 1001;
+    /// 对方违反协议
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    /// 文本消息不是合法的 UTF-8
+    pub const INVALID_DATA: u16 = 1007;
+    /// 消息超出长度限制
+    pub const TOO_BIG: u16 = 1009;
+
+    pub fn new(code: u16, reason: &str) -> Self {
+        Self {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    /// 关闭握手已经完成或连接已经断开，不能再收发消息
+    Closed,
+    /// 对方违反协议或消息超出限制，已经发送带有 `code` 的关闭帧并关闭连接
+    Protocol {
+        code: u16,
+        reason: &'static str,
+    },
+    Io(io::Error),
+}
+
+impl WebSocketError {
+    /// 是否是 [`WebSocket::set_read_timeout`] 设置的读超时，超时后连接仍然可用
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            WebSocketError::Io(e)
+                if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+        )
+    }
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Closed => write!(f, "WebSocket connection closed"),
+            WebSocketError::Protocol { code, reason } => {
+                write!(f, "WebSocket protocol error {code}: {reason}")
+            }
+            WebSocketError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WebSocketError::Io(e) => Some(e),
+            WebSocketError::Closed | WebSocketError::Protocol { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(e: io::Error) -> Self {
+        WebSocketError::Io(e)
+    }
+}
+
+/// 帧操作码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xA => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    fin: bool,
+    opcode: OpCode,
+    /// 已经去掉掩码的负载
+    payload: Vec<u8>,
+}
+
+/// 从缓冲区解析帧的结果
+#[derive(Debug, PartialEq)]
+enum Decode {
+    /// 完整的帧和它占用的字节数
+    Complete(Frame, usize),
+    Incomplete,
+    /// 需要用该状态码关闭连接
+    Error(u16, &'static str),
+}
+
+/// 从缓冲区开头解析一个帧
+///
+/// `masked` 为对方发来的帧是否必须带掩码：客户端发给服务器的帧必须带掩码，反之不能带。
+fn decode_frame(buf: &[u8], masked: bool, max_payload: usize) -> Decode {
+    let [first, second, ..] = *buf else {
+        return Decode::Incomplete;
+    };
+    let fin = first & 0x80 != 0;
+    // 没有协商任何扩展，RSV 位必须为 0
+    if first & 0x70 != 0 {
+        return Decode::Error(CloseFrame::PROTOCOL_ERROR, "reserved bits set");
+    }
+    let Some(opcode) = OpCode::from_bits(first & 0x0F) else {
+        return Decode::Error(CloseFrame::PROTOCOL_ERROR, "unknown opcode");
+    };
+    if (second & 0x80 != 0) != masked {
+        return Decode::Error(CloseFrame::PROTOCOL_ERROR, "unexpected masking");
+    }
+
+    let mut offset = 2;
+    let length = match second & 0x7F {
+        126 => {
+            let Some(bytes) = buf.get(2..4) else {
+                return Decode::Incomplete;
+            };
+            offset = 4;
+            u16::from_be_bytes([bytes[0], bytes[1]]) as u64
+        }
+        127 => {
+            let Some(bytes) = buf.get(2..10) else {
+                return Decode::Incomplete;
+            };
+            offset = 10;
+            let length = u64::from_be_bytes(bytes.try_into().unwrap_or_default());
+            if length >> 63 != 0 {
+                return Decode::Error(CloseFrame::PROTOCOL_ERROR, "invalid payload length");
+            }
+            length
+        }
+        length => length as u64,
+    };
+    if opcode.is_control() && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+        return Decode::Error(CloseFrame::PROTOCOL_ERROR, "invalid control frame");
+    }
+    if length > max_payload as u64 {
+        return Decode::Error(CloseFrame::TOO_BIG, "message too big");
+    }
+    let length = length as usize;
+
+    let mut key = [0u8; 4];
+    if masked {
+        let Some(bytes) = buf.get(offset..offset + 4) else {
+            return Decode::Incomplete;
+        };
+        key.copy_from_slice(bytes);
+        offset += 4;
+    }
+    let Some(payload) = buf.get(offset..offset + length) else {
+        return Decode::Incomplete;
+    };
+    let mut payload = payload.to_vec();
+    if masked {
+        apply_mask(&mut payload, key);
+    }
+    Decode::Complete(
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        offset + length,
+    )
+}
+
+/// 编码一个帧，`mask` 为 `None` 时不加掩码
+fn encode_frame(fin: bool, opcode: OpCode, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(if fin { 0x80 } else { 0 } | opcode.bits());
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length if length < 126 => frame.push(mask_bit | length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(key) => {
+            frame.extend_from_slice(&key);
+            let start = frame.len();
+            frame.extend_from_slice(payload);
+            apply_mask(&mut frame[start..], key);
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[index % 4];
+    }
+}
+
+/// 解析关闭帧的负载，负载为空表示没有状态码
+fn decode_close(payload: &[u8]) -> Result<Option<CloseFrame>, (u16, &'static str)> {
+    let (code, reason) = match payload {
+        [] => return Ok(None),
+        [high, low, reason @ ..] => (u16::from_be_bytes([*high, *low]), reason),
+        _ => return Err((CloseFrame::PROTOCOL_ERROR, "invalid close frame")),
+    };
+    // 1005、1006、1015 只用于本地表示，不能出现在关闭帧中
+    let valid = matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999);
+    if !valid {
+        return Err((CloseFrame::PROTOCOL_ERROR, "invalid close code"));
+    }
+    let reason =
+        str::from_utf8(reason).map_err(|_| (CloseFrame::INVALID_DATA, "invalid close reason"))?;
+    Ok(Some(CloseFrame::new(code, reason)))
+}
+
+/// 根据客户端的 `Sec-WebSocket-Key` 计算 `Sec-WebSocket-Accept`
+fn accept_key(key: &str) -> String {
+    let hash = digest(&SHA1_FOR_LEGACY_USE_ONLY, format!("{key}{GUID}").as_bytes());
+    STANDARD.encode(hash.as_ref())
+}
+
+/// 连接的哪一端，决定发送的帧是否加掩码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Server,
+    #[cfg(test)]
+    Client,
+}
+
+/// 消息和帧的长度限制
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_message_size: usize,
+    max_frame_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_message_size: MAX_MESSAGE_SIZE,
+            max_frame_size: MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// 握手完成后的 WebSocket 连接
+///
+/// 收到 `Ping` 和关闭帧时会自动回复。收发都在调用方的线程中阻塞进行，
+/// 需要同时等待消息和定时推送时，用 [`WebSocket::set_read_timeout`] 让 `recv` 定期返回。
+/// 服务器停止时连接会被直接关闭，`recv` 返回错误。
+pub struct WebSocket {
+    connection: Box<dyn Upgraded>,
+    role: Role,
+    limits: Limits,
+    request: HttpRequest,
+    protocol: Option<String>,
+    /// 已经读取但还没有解析成帧的数据
+    buffer: Vec<u8>,
+    /// 正在接收的分片消息的类型和已经收到的数据
+    fragments: Option<(OpCode, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    fn new(
+        connection: Box<dyn Upgraded>,
+        role: Role,
+        limits: Limits,
+        request: HttpRequest,
+        protocol: Option<String>,
+    ) -> Self {
+        Self {
+            connection,
+            role,
+            limits,
+            request,
+            protocol,
+            buffer: Vec::new(),
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// 握手请求，可以读取路径参数、查询参数和请求头
+    pub fn request(&self) -> &HttpRequest {
+        &self.request
+    }
+
+    /// 协商出的子协议
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// 设置 `recv` 等待消息的最长时间，超时返回 [`WebSocketError::is_timeout`] 为真的错误
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), WebSocketError> {
+        Ok(self.connection.set_read_timeout(timeout)?)
+    }
+
+    /// 接收下一条消息，分片消息拼接完整后才返回
+    pub fn recv(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            if self.close_received {
+                return Err(WebSocketError::Closed);
+            }
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                OpCode::Ping => {
+                    if !self.close_sent {
+                        self.write_frame(true, OpCode::Pong, &frame.payload)?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                OpCode::Pong => return Ok(Message::Pong(frame.payload)),
+                OpCode::Close => {
+                    self.close_received = true;
+                    let close = match decode_close(&frame.payload) {
+                        Ok(close) => close,
+                        Err((code, reason)) => return Err(self.fail(code, reason)),
+                    };
+                    if !self.close_sent {
+                        // 回复相同的状态码完成关闭握手
+                        let code = close.as_ref().map(|close| close.code);
+                        self.send_close(code.map(|code| CloseFrame::new(code, "")))?;
+                    }
+                    self.finish_close();
+                    return Ok(Message::Close(close));
+                }
+                OpCode::Text | OpCode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(self.fail(CloseFrame::PROTOCOL_ERROR, "expected continuation"));
+                    }
+                    if frame.fin {
+                        return self.message(frame.opcode, frame.payload);
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+                OpCode::Continuation => {
+                    let Some((opcode, mut data)) = self.fragments.take() else {
+                        return Err(
+                            self.fail(CloseFrame::PROTOCOL_ERROR, "unexpected continuation")
+                        );
+                    };
+                    if data.len() + frame.payload.len() > self.limits.max_message_size {
+                        return Err(self.fail(CloseFrame::TOO_BIG, "message too big"));
+                    }
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return self.message(opcode, data);
+                    }
+                    self.fragments = Some((opcode, data));
+                }
+            }
+        }
+    }
+
+    /// 发送一条消息，超过帧长度限制的消息会拆分成多个帧
+    pub fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        match message {
+            Message::Text(text) => self.write_message(OpCode::Text, text.as_bytes()),
+            Message::Binary(data) => self.write_message(OpCode::Binary, &data),
+            Message::Ping(data) => self.write_control(OpCode::Ping, &data),
+            Message::Pong(data) => self.write_control(OpCode::Pong, &data),
+            Message::Close(close) => self.close(close),
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<(), WebSocketError> {
+        self.write_message(OpCode::Text, text.as_bytes())
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.write_message(OpCode::Binary, data)
+    }
+
+    /// 发送关闭帧，之后继续 `recv` 直到收到对方的关闭帧
+    pub fn close(&mut self, close: Option<CloseFrame>) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        self.send_close(close)?;
+        if self.close_received {
+            self.finish_close();
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, opcode: OpCode, data: Vec<u8>) -> Result<Message, WebSocketError> {
+        if opcode == OpCode::Binary {
+            return Ok(Message::Binary(data));
+        }
+        match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(CloseFrame::INVALID_DATA, "invalid UTF-8 text")),
+        }
+    }
+
+    /// 读取一个完整的帧，读超时时已经读到的数据保留在缓冲区中
+    fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let masked = self.role == Role::Server;
+        loop {
+            match decode_frame(&self.buffer, masked, self.limits.max_message_size) {
+                Decode::Complete(frame, consumed) => {
+                    self.buffer.drain(..consumed);
+                    return Ok(frame);
+                }
+                Decode::Incomplete => {}
+                Decode::Error(code, reason) => return Err(self.fail(code, reason)),
+            }
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            let n = match self.connection.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if n == 0 {
+                // 没有关闭握手就断开了连接
+                self.close_received = true;
+                self.close_sent = true;
+                return Err(WebSocketError::Closed);
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn write_message(&mut self, opcode: OpCode, data: &[u8]) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        let mut chunks = data.chunks(self.limits.max_frame_size.max(1)).peekable();
+        if chunks.peek().is_none() {
+            return self.write_frame(true, opcode, &[]);
+        }
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), opcode, chunk)?;
+            opcode = OpCode::Continuation;
+        }
+        Ok(())
+    }
+
+    fn write_control(&mut self, opcode: OpCode, data: &[u8]) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        if data.len() > MAX_CONTROL_PAYLOAD {
+            let message = "control frame payload exceeds 125 bytes";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+        self.write_frame(true, opcode, data)
+    }
+
+    fn send_close(&mut self, close: Option<CloseFrame>) -> Result<(), WebSocketError> {
+        self.close_sent = true;
+        let mut payload = Vec::new();
+        if let Some(close) = close {
+            payload.extend_from_slice(&close.code.to_be_bytes());
+            // 原因和状态码一起不能超过控制帧的长度限制
+            let mut end = close.reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+            while !close.reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&close.reason.as_bytes()[..end]);
+        }
+        self.write_frame(true, OpCode::Close, &payload)
+    }
+
+    /// 关闭握手完成，服务器负责关闭底层连接
+    fn finish_close(&mut self) {
+        if self.role == Role::Server {
+            self.connection.close();
+        }
+    }
+
+    /// 发送带有状态码的关闭帧后关闭连接，返回对应的错误
+    fn fail(&mut self, code: u16, reason: &'static str) -> WebSocketError {
+        if !self.close_sent {
+            let _ = self.send_close(Some(CloseFrame::new(code, reason)));
+        }
+        self.close_received = true;
+        self.connection.close();
+        WebSocketError::Protocol { code, reason }
+    }
+
+    fn write_frame(
+        &mut self,
+        fin: bool,
+        opcode: OpCode,
+        payload: &[u8],
+    ) -> Result<(), WebSocketError> {
+        let mask = match self.role {
+            Role::Server => None,
+            #[cfg(test)]
+            Role::Client => {
+                use ring::rand::{SecureRandom, SystemRandom};
+
+                let mut key = [0u8; 4];
+                SystemRandom::new()
+                    .fill(&mut key)
+                    .map_err(|_| io::Error::other("failed to generate masking key"))?;
+                Some(key)
+            }
+        };
+        self.connection
+            .write_all(&encode_frame(fin, opcode, payload, mask))?;
+        self.connection.flush()?;
+        Ok(())
+    }
+}
+
+/// 处理 WebSocket 握手的处理器，握手成功后由 `on_connect` 在当前工作线程上接管连接
+///
+/// 像普通处理器一样注册到 [`Router`](crate::route::Router)，路由参数和中间件照常生效：
+///
+/// ```no_run
+/// use httpserver::route::Router;
+/// use httpserver::websocket::{Message, WebSocketHandler};
+///
+/// let router = Router::new().get("/echo", WebSocketHandler::new(|mut socket| {
+///     while let Ok(message) = socket.recv() {
+///         if let Message::Text(_) | Message::Binary(_) = message {
+///             let _ = socket.send(message);
+///         }
+///     }
+/// }));
+/// ```
+///
+/// 每个连接会一直占用一个工作线程，只有阻塞模式的服务器支持 TLS 连接的升级。
+/// 异步服务器上 `on_connect` 在 tokio 的阻塞线程池中运行，同样一直占用一个线程，
+/// 明文和 HTTPS 的 HTTP/1.1 连接都支持；HTTP/2 中没有协议切换，这样的请求返回 `501`。
+pub struct WebSocketHandler {
+    on_connect: Arc<dyn Fn(WebSocket) + Send + Sync>,
+    protocols: Vec<String>,
+    limits: Limits,
+}
+
+impl WebSocketHandler {
+    pub fn new(on_connect: impl Fn(WebSocket) + Send + Sync + 'static) -> Self {
+        Self {
+            on_connect: Arc::new(on_connect),
+            protocols: Vec::new(),
+            limits: Limits::default(),
+        }
+    }
+
+    /// 支持的子协议，按客户端给出的顺序选择第一个支持的
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// 接收消息的最大长度，超出时以 `1009` 关闭连接，默认 16 MiB
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.limits.max_message_size = max_message_size;
+        self
+    }
+
+    /// 发送消息时单个帧的最大长度，默认 64 KiB
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.limits.max_frame_size = max_frame_size;
+        self
+    }
+
+    fn select_protocol(&self, request: &HttpRequest) -> Option<String> {
+        let offered = request.header("Sec-WebSocket-Protocol")?;
+        offered
+            .split(',')
+            .map(str::trim)
+            .find(|offered| self.protocols.iter().any(|p| p == offered))
+            .map(str::to_string)
+    }
+}
+
+impl Handler for WebSocketHandler {
+    fn handle_request(&self, request: HttpRequest) -> HttpResponse<'static> {
+        if let Some(response) = reject_handshake(&request) {
+            return response;
+        }
+        let key = request.header("Sec-WebSocket-Key").unwrap_or("").trim();
+        let accept = accept_key(key);
+        let protocol = self.select_protocol(&request);
+        let on_connect = Arc::clone(&self.on_connect);
+        let limits = self.limits;
+        let selected = protocol.clone();
+        let mut response = HttpResponse::switching_protocols(None, move |connection| {
+            on_connect(WebSocket::new(
+                connection,
+                Role::Server,
+                limits,
+                request,
+                selected,
+            ))
+        });
+        response.set_header("Upgrade", "websocket");
+        response.set_header("Sec-WebSocket-Accept", &accept);
+        if let Some(protocol) = protocol {
+            response.set_header("Sec-WebSocket-Protocol", &protocol);
+        }
+        response
+    }
+}
+
+/// 按 RFC 6455 检查握手请求，不合法时返回错误响应
+fn reject_handshake(request: &HttpRequest) -> Option<HttpResponse<'static>> {
+    if *request.method() != HttpMethod::GET {
+        let mut response = HttpResponse::new("405", None, None);
+        response.set_header("Allow", "GET");
+        return Some(response);
+    }
+    let has_token = |name: &str, token: &str| {
+        request.header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if !has_token("Upgrade", "websocket") {
+        let mut response = HttpResponse::new("426", None, Some("Upgrade Required".to_string()));
+        response.set_header("Upgrade", "websocket");
+        return Some(response);
+    }
+    let bad_request = || HttpResponse::new("400", None, Some("Bad Request".to_string()));
+    if *request.version() != HttpVersion::HTTP11 || !has_token("Connection", "upgrade") {
+        return Some(bad_request());
+    }
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        let mut response = HttpResponse::new("426", None, Some("Upgrade Required".to_string()));
+        response.set_header("Sec-WebSocket-Version", "13");
+        return Some(response);
+    }
+    let key = request.header("Sec-WebSocket-Key").unwrap_or("").trim();
+    // 密钥是 16 字节随机数的 base64 编码
+    match STANDARD.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => None,
+        _ => Some(bad_request()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::httpserver::{Engine, HttpServer};
+    use crate::route::Router;
+    use crate::tls::{Stream, UpgradedStream};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Instant;
+
+    fn handshake(headers: &str) -> HttpRequest {
+        handshake_text(headers).into()
+    }
+
+    fn handshake_text(headers: &str) -> String {
+        format!("GET /echo HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n")
+    }
+
+    #[test]
+    fn test_frame_codec_and_accept_key() {
+        // RFC 6455 中的示例
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        let hello = |fin| Frame {
+            fin,
+            opcode: OpCode::Text,
+            payload: b"Hello".to_vec(),
+        };
+        let unmasked = [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+        assert_eq!(
+            decode_frame(&unmasked, false, 100),
+            Decode::Complete(hello(true), 7)
+        );
+        let masked = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            decode_frame(&masked, true, 100),
+            Decode::Complete(hello(true), 11)
+        );
+        let key = [0x37, 0xfa, 0x21, 0x3d];
+        assert_eq!(
+            encode_frame(true, OpCode::Text, b"Hello", Some(key)),
+            masked
+        );
+        let first = encode_frame(false, OpCode::Text, b"Hello", None);
+        assert_eq!(
+            decode_frame(&first, false, 100),
+            Decode::Complete(hello(false), 7)
+        );
+
+        // 16 位和 64 位长度
+        for length in [256, 70_000] {
+            let frame = encode_frame(true, OpCode::Binary, &vec![7; length], Some(key));
+            let Decode::Complete(decoded, consumed) = decode_frame(&frame, true, 100_000) else {
+                panic!("frame of {length} bytes should decode");
+            };
+            assert_eq!((decoded.payload.len(), consumed), (length, frame.len()));
+            assert_eq!(
+                decode_frame(&frame[..frame.len() - 1], true, 100_000),
+                Decode::Incomplete
+            );
+        }
+
+        let error = |buf: &[u8], masked| match decode_frame(buf, masked, 100) {
+            Decode::Error(code, _) => code,
+            other => panic!("expected error, got {other:?}"),
+        };
+        // 客户端的帧没有掩码、RSV 位、未知操作码、分片或过长的控制帧、超长消息
+        assert_eq!(error(&unmasked, true), CloseFrame::PROTOCOL_ERROR);
+        assert_eq!(error(&[0xC1, 0x00], false), CloseFrame::PROTOCOL_ERROR);
+        assert_eq!(error(&[0x83, 0x00], false), CloseFrame::PROTOCOL_ERROR);
+        assert_eq!(error(&[0x09, 0x00], false), CloseFrame::PROTOCOL_ERROR);
+        assert_eq!(
+            error(&[0x89, 0x7E, 0x00, 0x7E], false),
+            CloseFrame::PROTOCOL_ERROR
+        );
+        assert_eq!(error(&[0x82, 0x7E, 0x01, 0x00], false), CloseFrame::TOO_BIG);
+
+        assert_eq!(decode_close(&[]), Ok(None));
+        assert_eq!(
+            decode_close(&[0x03, 0xE8, b'o', b'k']),
+            Ok(Some(CloseFrame::new(1000, "ok")))
+        );
+        assert!(decode_close(&[0x03]).is_err());
+        assert!(decode_close(&[0x03, 0xED]).is_err());
+    }
+
+    #[test]
+    fn test_handshake_validation() {
+        let handler = WebSocketHandler::new(|_| {}).protocols(&["chat", "json"]);
+        let valid = "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n";
+
+        let response = handler.handle_request(handshake(&format!(
+            "{valid}Sec-WebSocket-Protocol: xml, json, chat\r\n"
+        )));
+        assert_eq!(response.status_code(), "101");
+        assert!(response.is_upgrade());
+        assert_eq!(
+            response.header("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert_eq!(response.header("Sec-WebSocket-Protocol"), Some("json"));
+
+        let status = |headers: &str| {
+            handler
+                .handle_request(handshake(headers))
+                .status_code()
+                .to_string()
+        };
+        assert_eq!(status(""), "426");
+        assert_eq!(
+            status(&valid.replace("keep-alive, Upgrade", "keep-alive")),
+            "400"
+        );
+        assert_eq!(status(&valid.replace("Version: 13", "Version: 8")), "426");
+        assert_eq!(
+            status(&valid.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ=")),
+            "400"
+        );
+        let post = HttpRequest::from(format!("POST /echo HTTP/1.1\r\n{valid}\r\n"));
+        assert_eq!(handler.handle_request(post).status_code(), "405");
+    }
+
+    /// 发送握手请求和紧随其后的帧，返回握手响应和客户端连接
+    fn connect(addr: SocketAddr, early: &[u8]) -> (String, WebSocket) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut request = handshake_text(
+            "Upgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: AAECAwQFBgcICQoLDA0ODw==\r\nSec-WebSocket-Version: 13\r\n",
+        )
+        .into_bytes();
+        request.extend_from_slice(early);
+        stream.write_all(&request).unwrap();
+
+        // 逐字节读取响应头，不读走之后的帧
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let limits = Limits {
+            max_message_size: MAX_MESSAGE_SIZE,
+            max_frame_size: 1000,
+        };
+        let connection = Box::new(UpgradedStream::new(Stream::Plain(stream), Vec::new()));
+        let socket = WebSocket::new(connection, Role::Client, limits, handshake(""), None);
+        (String::from_utf8(head).unwrap(), socket)
+    }
+
+    fn echo(mut socket: WebSocket) {
+        while let Ok(message) = socket.recv() {
+            if let Message::Text(_) | Message::Binary(_) = message
+                && socket.send(message).is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_echo_server_on_both_engines() {
+        for engine in [Engine::Threaded, Engine::Event] {
+            let router = Router::new().get("/echo", WebSocketHandler::new(echo));
+            let server = HttpServer::new("127.0.0.1", 0, ".")
+                .router(router)
+                .engine(engine);
+            let handle = server.spawn().unwrap();
+            let addr = handle.local_addr();
+
+            // 握手请求后面直接跟着的帧不能丢失
+            let early = encode_frame(true, OpCode::Text, b"early", Some([1, 2, 3, 4]));
+            let (head, mut client) = connect(addr, &early);
+            assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+            assert!(head.contains("Sec-WebSocket-Accept: "));
+            assert!(head.contains("Connection: Upgrade"));
+            assert!(!head.contains("Content-Length"));
+            assert_eq!(client.recv().unwrap(), Message::Text("early".to_string()));
+
+            client.send_text("hello").unwrap();
+            assert_eq!(client.recv().unwrap(), Message::Text("hello".to_string()));
+
+            // 客户端按 1000 字节分片发送，服务器按 64 KiB 分片回复
+            let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+            client.send_binary(&data).unwrap();
+            assert_eq!(client.recv().unwrap(), Message::Binary(data));
+
+            // 分片消息中间插入的控制帧
+            client.write_frame(false, OpCode::Text, b"frag").unwrap();
+            client.write_frame(true, OpCode::Ping, b"beat").unwrap();
+            client
+                .write_frame(true, OpCode::Continuation, b"ment")
+                .unwrap();
+            assert_eq!(client.recv().unwrap(), Message::Pong(b"beat".to_vec()));
+            assert_eq!(
+                client.recv().unwrap(),
+                Message::Text("fragment".to_string())
+            );
+
+            client
+                .close(Some(CloseFrame::new(CloseFrame::NORMAL, "done")))
+                .unwrap();
+            let close = Some(CloseFrame::new(CloseFrame::NORMAL, ""));
+            assert_eq!(client.recv().unwrap(), Message::Close(close));
+            assert!(matches!(client.recv(), Err(WebSocketError::Closed)));
+
+            // 非法 UTF-8 和未加掩码的帧都会让服务器带状态码关闭连接
+            let (_, mut client) = connect(addr, &[]);
+            client
+                .write_frame(true, OpCode::Text, &[0xC3, 0x28])
+                .unwrap();
+            let Message::Close(Some(close)) = client.recv().unwrap() else {
+                panic!("server should close the connection");
+            };
+            assert_eq!(close.code, CloseFrame::INVALID_DATA);
+            let unmasked = encode_frame(true, OpCode::Text, b"plain", None);
+            let (_, mut client) = connect(addr, &unmasked);
+            let Message::Close(Some(close)) = client.recv().unwrap() else {
+                panic!("server should close the connection");
+            };
+            assert_eq!(close.code, CloseFrame::PROTOCOL_ERROR);
+
+            // 打开的 WebSocket 连接不会阻塞服务器停止
+            let (_, mut client) = connect(addr, &[]);
+            client.send_text("still here").unwrap();
+            assert_eq!(
+                client.recv().unwrap(),
+                Message::Text("still here".to_string())
+            );
+            let start = Instant::now();
+            handle.shutdown();
+            assert!(start.elapsed() < Duration::from_secs(2));
+            assert!(client.recv().is_err());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_echo_server_on_async_server() {
+        use crate::asyncserver::AsyncHttpServer;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let router = Router::new().get("/echo", WebSocketHandler::new(echo));
+        let handle = runtime
+            .block_on(
+                AsyncHttpServer::new("127.0.0.1", 0, ".")
+                    .router(router)
+                    .spawn(),
+            )
+            .unwrap();
+
+        let early = encode_frame(true, OpCode::Text, b"early", Some([1, 2, 3, 4]));
+        let (head, mut client) = connect(handle.local_addr(), &early);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Connection: Upgrade"));
+        assert_eq!(client.recv().unwrap(), Message::Text("early".to_string()));
+        let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        client.send_binary(&data).unwrap();
+        assert_eq!(client.recv().unwrap(), Message::Binary(data));
+
+        client.close(None).unwrap();
+        assert_eq!(client.recv().unwrap(), Message::Close(None));
+        assert!(matches!(client.recv(), Err(WebSocketError::Closed)));
+
+        // 打开的连接不会阻塞服务器停止
+        let (_, mut client) = connect(handle.local_addr(), &[]);
+        client.send_text("still here").unwrap();
+        assert_eq!(
+            client.recv().unwrap(),
+            Message::Text("still here".to_string())
+        );
+        let start = Instant::now();
+        runtime.block_on(handle.shutdown());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(client.recv().is_err());
+    }
+}
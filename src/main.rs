@@ -1,11 +1,47 @@
 use std::env::{self, args};
+use std::process;
+use std::time::Duration;
 
-use httpserver::httpserver::HttpServer;
-use log::LevelFilter;
+use httpserver::accesslog::{AccessLog, LogFormat};
+use httpserver::httpserver::{Engine, HttpServer};
+use httpserver::logfile::{LogFile, LogWriter};
+use httpserver::tls::{Hsts, TlsConfig};
+use log::{error, LevelFilter};
 
-fn main() {
-    init_log();
+/// 日志文件相关的命令行选项
+#[derive(Default)]
+struct LogOptions {
+    log_file: Option<String>,
+    access_log_file: Option<String>,
+    max_size: Option<u64>,
+    rotate: Option<Duration>,
+    keep: Option<usize>,
+    compress: bool,
+}
+
+impl LogOptions {
+    fn open(&self, path: &str) -> LogWriter {
+        let mut log_file = LogFile::new(path).compress(self.compress);
+        if let Some(max_size) = self.max_size {
+            log_file = log_file.max_size(max_size);
+        }
+        if let Some(interval) = self.rotate {
+            log_file = log_file.rotate_every(interval);
+        }
+        if let Some(keep) = self.keep {
+            log_file = log_file.max_files(keep);
+        }
+        match log_file.open() {
+            Ok(writer) => writer,
+            Err(e) => {
+                eprintln!("Failed to open log file {path}: {e}");
+                process::exit(1);
+            }
+        }
+    }
+}
 
+fn main() {
     // 如果参数为 -h --help 打印帮助信息
     if args().nth(1).unwrap_or("".to_string()) == "-h"
         || args().nth(1).unwrap_or("".to_string()) == "--help"
@@ -13,8 +49,17 @@ fn main() {
         println!("Usage: httpserver [port] [work_dir]");
         println!("Usage: httpserver [options]");
         println!("Options:");
-        println!("  -h, --help     Print this help message");
-        println!("  -v, --version  Print the version number");
+        println!("  -h, --help                Print this help message");
+        println!("  -v, --version             Print the version number");
+        println!("  --log-file <path>         Write the error log to a file instead of stdout");
+        println!("  --access-log-file <path>  Write the access log to a file");
+        println!("  --log-max-size <size>     Rotate log files larger than size, e.g. 10M");
+        println!("  --log-rotate <interval>   Rotate log files hourly or daily");
+        println!(
+            "  --log-keep <count>        Number of rotated files to keep (default: 7, 0 keeps all)"
+        );
+        println!("  --log-compress            Compress rotated files with gzip");
+        println!("Log files are reopened on SIGUSR1.");
         return;
     }
 
@@ -28,28 +73,147 @@ fn main() {
         return;
     }
 
+    let (positional, log_options) = match parse_args(args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{message}, see --help");
+            process::exit(2);
+        }
+    };
+    init_log(
+        log_options
+            .log_file
+            .as_ref()
+            .map(|path| log_options.open(path)),
+    );
+
     let host = env::var("HOST").unwrap_or("127.0.0.1".to_string());
-    let port = args().nth(1).unwrap_or("7878".to_string());
-    // let work_dir = args().nth(2).unwrap_or(".".to_string());
-    let work_dir = "C:\\Users\\11829\\repo\\trpl-zh-cn\\book";
+    let port = positional.first().cloned().unwrap_or("7878".to_string());
+    let work_dir = positional.get(1).cloned().unwrap_or(".".to_string());
+
+    // 收到 SIGINT/SIGTERM 后等待正在处理的请求完成的秒数
+    let drain_timeout = env::var("DRAIN_TIMEOUT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30);
+
+    // event: 所有连接由 epoll 事件循环处理；threaded（默认）: 每个连接一个工作线程
+    let engine = match env::var("ENGINE").as_deref() {
+        Ok("event") => Engine::Event,
+        _ => Engine::Threaded,
+    };
 
-    let server = HttpServer::new(&host, port.parse().unwrap(), work_dir);
-    server.run();
+    let mut server = HttpServer::new(&host, port.parse().unwrap(), &work_dir)
+        .engine(engine)
+        .drain_timeout(Duration::from_secs(drain_timeout))
+        .handle_signals(true);
+    // 同时设置证书和私钥时使用 HTTPS，收到 SIGHUP 时重新读取
+    if let (Ok(cert), Ok(key)) = (env::var("TLS_CERT"), env::var("TLS_KEY")) {
+        server = server.tls(TlsConfig::new(cert, key));
+        // 明文 HTTP 端口，所有请求重定向到 HTTPS
+        if let Some(port) = env::var("HTTP_REDIRECT_PORT")
+            .ok()
+            .and_then(|port| port.parse().ok())
+        {
+            server = server.redirect_http(port);
+        }
+        // HTTPS 响应中 Strict-Transport-Security 的 max-age 秒数
+        if let Some(max_age) = env::var("HSTS_MAX_AGE")
+            .ok()
+            .and_then(|age| age.parse().ok())
+        {
+            server = server.hsts(Hsts::new(Duration::from_secs(max_age)));
+        }
+    }
+    // 访问日志输出到文件，- 表示标准输出；命令行选项优先于环境变量
+    if let Some(path) = log_options
+        .access_log_file
+        .clone()
+        .or_else(|| env::var("ACCESS_LOG").ok())
+    {
+        let access_log = if path == "-" {
+            AccessLog::stdout()
+        } else {
+            AccessLog::writer(log_options.open(&path))
+        };
+        let format = match env::var("ACCESS_LOG_FORMAT").as_deref() {
+            Ok("common") => LogFormat::Common,
+            Ok("json") => LogFormat::Json,
+            Ok("combined") | Err(_) => LogFormat::Combined,
+            Ok(format) => LogFormat::Custom(format.to_string()),
+        };
+        server = server.access_log(access_log.format(format));
+    }
+    if let Err(e) = server.run() {
+        error!("{e}");
+        process::exit(1);
+    }
 }
 
-fn init_log() {
-    if let Ok(log_level) = std::env::var("LOG_LEVEL") {
-        let log_level = log_level
-            .parse::<LevelFilter>()
-            .unwrap_or(LevelFilter::Info);
-        env_logger::Builder::from_default_env()
-            .filter_level(log_level)
-            .target(env_logger::Target::Stdout)
-            .init();
-    } else {
-        env_logger::Builder::from_default_env()
-            .filter_level(LevelFilter::Info)
-            .target(env_logger::Target::Stdout)
-            .init();
+/// 分离出位置参数和日志选项
+fn parse_args(args: impl Iterator<Item = String>) -> Result<(Vec<String>, LogOptions), String> {
+    let mut positional = Vec::new();
+    let mut options = LogOptions::default();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        if arg == "--log-compress" {
+            options.compress = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {arg}"))?;
+        let invalid = || format!("Invalid value for {arg}: {value}");
+        match arg.as_str() {
+            "--log-file" => options.log_file = Some(value),
+            "--access-log-file" => options.access_log_file = Some(value),
+            "--log-max-size" => options.max_size = Some(parse_size(&value).ok_or_else(invalid)?),
+            "--log-rotate" => {
+                options.rotate = Some(match value.as_str() {
+                    "hourly" => Duration::from_secs(3600),
+                    "daily" => Duration::from_secs(86400),
+                    _ => return Err(invalid()),
+                })
+            }
+            "--log-keep" => options.keep = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("Unknown option {arg}")),
+        }
     }
+    Ok((positional, options))
+}
+
+/// 解析 `1048576`、`512K`、`10M`、`1G` 形式的大小
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// 日志默认输出到标准输出，指定了日志文件时写入文件
+fn init_log(log_file: Option<LogWriter>) {
+    let log_level = std::env::var("LOG_LEVEL")
+        .ok()
+        .and_then(|log_level| log_level.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::Info);
+    let target = match log_file {
+        Some(writer) => env_logger::Target::Pipe(Box::new(writer)),
+        None => env_logger::Target::Stdout,
+    };
+    env_logger::Builder::from_default_env()
+        .filter_level(log_level)
+        .target(target)
+        .init();
 }
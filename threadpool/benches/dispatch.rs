@@ -0,0 +1,108 @@
+//! 比较无锁队列调度和原来 `Mutex<Receiver>` 调度的吞吐量
+//!
+//! 运行：`cargo bench -p threadpool`。锁竞争只在多核上明显，单核机器上两者差别主要是每个任务的统计开销。
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use threadpool::threadpool::ThreadPool;
+
+const WORKERS: usize = 4;
+
+/// 原来的实现：所有工作线程竞争同一把锁接收任务
+struct MutexPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Box<dyn FnOnce() + Send>>>,
+}
+
+impl MutexPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || {
+                    loop {
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    }
+                })
+            })
+            .collect();
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for MutexPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+fn wait_for(counter: &AtomicUsize, expected: usize) {
+    while counter.load(Ordering::Acquire) < expected {
+        thread::yield_now();
+    }
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    for jobs in [1_000usize, 10_000] {
+        group.throughput(Throughput::Elements(jobs as u64));
+
+        let pool = ThreadPool::builder()
+            .min_workers(WORKERS)
+            .max_workers(WORKERS)
+            .queue_capacity(jobs)
+            .build();
+        group.bench_with_input(BenchmarkId::new("lock_free", jobs), &jobs, |b, &jobs| {
+            b.iter(|| {
+                let counter = Arc::new(AtomicUsize::new(0));
+                for _ in 0..jobs {
+                    let counter = Arc::clone(&counter);
+                    pool.execute(move || {
+                        counter.fetch_add(1, Ordering::Release);
+                    });
+                }
+                wait_for(&counter, jobs);
+            })
+        });
+
+        let pool = MutexPool::new(WORKERS);
+        group.bench_with_input(
+            BenchmarkId::new("mutex_receiver", jobs),
+            &jobs,
+            |b, &jobs| {
+                b.iter(|| {
+                    let counter = Arc::new(AtomicUsize::new(0));
+                    for _ in 0..jobs {
+                        let counter = Arc::clone(&counter);
+                        pool.execute(move || {
+                            counter.fetch_add(1, Ordering::Release);
+                        });
+                    }
+                    wait_for(&counter, jobs);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
@@ -0,0 +1,116 @@
+use crate::job::Job;
+use crate::threadpool::ThreadPool;
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+
+/// [`ThreadPool::submit`] 返回的任务句柄
+pub struct JobHandle<T> {
+    pub(crate) receiver: mpsc::Receiver<thread::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// 等待任务结束，返回任务的结果；任务 panic 时返回 panic 负载
+    pub fn join(self) -> thread::Result<T> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(Box::new("job was dropped before it finished")),
+        }
+    }
+
+    /// 任务已结束时返回结果，否则返回原句柄
+    pub fn try_join(self) -> Result<thread::Result<T>, Self> {
+        match self.receiver.try_recv() {
+            Ok(result) => Ok(result),
+            Err(mpsc::TryRecvError::Empty) => Err(self),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                Ok(Err(Box::new("job was dropped before it finished")))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    panicked: AtomicBool,
+}
+
+/// 作用域任务结束（执行完、panic 或被丢弃）时减少计数
+struct PendingGuard(Arc<ScopeState>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut pending = self.0.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// [`ThreadPool::scope`] 中用于提交借用任务的作用域
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub(crate) fn new(pool: &'scope ThreadPool) -> Self {
+        Self {
+            pool,
+            state: Arc::default(),
+            scope: PhantomData,
+            env: PhantomData,
+        }
+    }
+
+    /// 提交一个可以借用作用域外数据的任务
+    pub fn execute<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let guard = PendingGuard(Arc::clone(&self.state));
+        let task: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let guard = guard;
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                guard.0.panicked.store(true, Ordering::SeqCst);
+                panic::resume_unwind(payload);
+            }
+        });
+        // SAFETY: `ThreadPool::scope` 返回前会等待所有任务的 PendingGuard 被释放，
+        // 也就是任务闭包已经执行完或被丢弃，借用的数据不会在此之后被访问。
+        let task: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(task) };
+        self.pool.execute_job(Job::new(task));
+    }
+
+    pub(crate) fn wait(&self) {
+        let mut pending = self.state.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.state.done.wait(pending).unwrap();
+        }
+    }
+
+    pub(crate) fn panicked(&self) -> bool {
+        self.state.panicked.load(Ordering::SeqCst)
+    }
+}
+
+/// 从 panic 负载中取出消息文本
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
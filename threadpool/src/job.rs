@@ -1 +1,16 @@
-pub type Job = Box<dyn FnOnce() + Send + 'static>;
+use std::time::Instant;
+
+pub struct Job {
+    pub task: Box<dyn FnOnce() + Send + 'static>,
+    /// 入队时间，用于统计排队等待时长
+    pub enqueued_at: Instant,
+}
+
+impl Job {
+    pub fn new(task: Box<dyn FnOnce() + Send + 'static>) -> Self {
+        Self {
+            task,
+            enqueued_at: Instant::now(),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+mod handle;
 mod job;
+pub mod stats;
 pub mod threadpool;
 mod worker;
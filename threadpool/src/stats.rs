@@ -0,0 +1,151 @@
+use crossbeam_utils::CachePadded;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// 单个工作线程的计数器，只由该线程写入，各线程之间互不争用
+#[derive(Default)]
+pub(crate) struct WorkerCounters {
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    wait_nanos: AtomicU64,
+    run_nanos: AtomicU64,
+}
+
+impl WorkerCounters {
+    pub(crate) fn record(&self, wait: Duration, run: Duration, panicked: bool) {
+        self.wait_nanos
+            .fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+        self.run_nanos
+            .fetch_add(run.as_nanos() as u64, Ordering::Relaxed);
+        // 和 wait_idle 读取等待者数量配对，保证两边至少有一方看到对方的修改
+        if panicked {
+            self.panicked.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub(crate) fn totals(&self) -> Totals {
+        Totals {
+            completed: self.completed.load(Ordering::SeqCst),
+            panicked: self.panicked.load(Ordering::SeqCst),
+            wait_nanos: self.wait_nanos.load(Ordering::Relaxed),
+            run_nanos: self.run_nanos.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 计数器汇总值
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Totals {
+    pub(crate) completed: u64,
+    pub(crate) panicked: u64,
+    pub(crate) wait_nanos: u64,
+    pub(crate) run_nanos: u64,
+}
+
+impl Totals {
+    pub(crate) fn add(&mut self, other: Totals) {
+        self.completed += other.completed;
+        self.panicked += other.panicked;
+        self.wait_nanos += other.wait_nanos;
+        self.run_nanos += other.run_nanos;
+    }
+
+    /// 已经执行完（包括 panic）的任务数
+    pub(crate) fn finished(&self) -> u64 {
+        self.completed + self.panicked
+    }
+}
+
+/// 所有工作线程的计数器，工作线程退出时把计数合并到 `retired`
+#[derive(Default)]
+pub(crate) struct Counters {
+    live: HashMap<usize, Arc<CachePadded<WorkerCounters>>>,
+    retired: Totals,
+}
+
+impl Counters {
+    pub(crate) fn register(&mut self, id: usize) -> Arc<CachePadded<WorkerCounters>> {
+        let counters = Arc::new(CachePadded::new(WorkerCounters::default()));
+        self.live.insert(id, Arc::clone(&counters));
+        counters
+    }
+
+    pub(crate) fn retire(&mut self, id: usize) {
+        if let Some(counters) = self.live.remove(&id) {
+            self.retired.add(counters.totals());
+        }
+    }
+
+    pub(crate) fn totals(&self) -> Totals {
+        let mut totals = self.retired;
+        for counters in self.live.values() {
+            totals.add(counters.totals());
+        }
+        totals
+    }
+}
+
+/// 线程池某一时刻的统计快照
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    /// 存活的工作线程数
+    pub workers: usize,
+    /// 正在执行任务的工作线程数
+    pub active_workers: usize,
+    /// 正在等待任务的工作线程数
+    pub idle_workers: usize,
+    /// 排队等待执行的任务数
+    pub queue_depth: usize,
+    /// 正常完成的任务数
+    pub jobs_completed: u64,
+    /// panic 的任务数
+    pub jobs_panicked: u64,
+    /// 任务从入队到开始执行的平均时长
+    pub average_wait: Duration,
+    /// 任务的平均执行时长
+    pub average_run: Duration,
+}
+
+impl PoolStats {
+    pub(crate) fn snapshot(
+        totals: Totals,
+        workers: usize,
+        idle_workers: usize,
+        queue_depth: usize,
+    ) -> Self {
+        let finished = totals.finished().max(1);
+        let idle_workers = idle_workers.min(workers);
+        Self {
+            workers,
+            active_workers: workers - idle_workers,
+            idle_workers,
+            queue_depth,
+            jobs_completed: totals.completed,
+            jobs_panicked: totals.panicked,
+            average_wait: Duration::from_nanos(totals.wait_nanos / finished),
+            average_run: Duration::from_nanos(totals.run_nanos / finished),
+        }
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "workers={} active={} idle={} queued={} completed={} panicked={} avg_wait={:?} avg_run={:?}",
+            self.workers,
+            self.active_workers,
+            self.idle_workers,
+            self.queue_depth,
+            self.jobs_completed,
+            self.jobs_panicked,
+            self.average_wait,
+            self.average_run
+        )
+    }
+}
@@ -1,31 +1,342 @@
-use crate::{job::Job, worker::Worker};
-use std::sync::{Arc, Mutex, mpsc};
+use crate::handle::panic_message;
+pub use crate::handle::{JobHandle, Scope};
+use crate::{
+    job::Job,
+    stats::PoolStats,
+    worker::{Config, Shared, Worker},
+};
+use crossbeam_channel::{Sender, TrySendError};
+use log::warn;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+/// 每个工作线程默认可以排队的任务数
+const DEFAULT_QUEUE_PER_WORKER: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum ExecuteError {
+    /// 任务队列已满
+    Full,
+    /// 线程池已经关闭
+    Disconnected,
 }
 
-impl ThreadPool {
-    pub fn new(size: usize) -> ThreadPool {
-        let (sender, receiver) = mpsc::channel();
-        let mut workers = Vec::with_capacity(size);
-        let receiver = Arc::new(Mutex::new(receiver));
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Full => write!(f, "thread pool queue is full"),
+            ExecuteError::Disconnected => write!(f, "thread pool is shut down"),
+        }
+    }
+}
+
+impl std::error::Error for ExecuteError {}
+
+/// 线程池构建器
+///
+/// 线程池启动时创建 `min_workers` 个工作线程，任务排队且没有空闲线程时增加线程，
+/// 最多 `max_workers` 个；多出来的线程空闲超过 `keep_alive` 后退出。
+#[derive(Debug, Clone)]
+pub struct Builder {
+    min_workers: usize,
+    max_workers: usize,
+    keep_alive: Duration,
+    queue_capacity: Option<usize>,
+    name_prefix: String,
+    stack_size: Option<usize>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            min_workers: 1,
+            max_workers: 1,
+            keep_alive: Duration::from_secs(60),
+            queue_capacity: None,
+            name_prefix: "worker".to_string(),
+            stack_size: None,
+        }
+    }
+
+    pub fn min_workers(mut self, min_workers: usize) -> Self {
+        self.min_workers = min_workers;
+        self
+    }
+
+    pub fn max_workers(mut self, max_workers: usize) -> Self {
+        self.max_workers = max_workers;
+        self
+    }
+
+    /// 超出 `min_workers` 的线程空闲多久后退出
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// 任务队列容量，默认每个最大工作线程 64 个
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = Some(queue_capacity);
+        self
+    }
+
+    /// 线程名前缀，线程名为 `{prefix}-{id}`
+    pub fn name_prefix(mut self, name_prefix: &str) -> Self {
+        self.name_prefix = name_prefix.to_string();
+        self
+    }
+
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    pub fn build(self) -> ThreadPool {
+        let max_workers = self.max_workers.max(self.min_workers).max(1);
+        let queue_capacity = self
+            .queue_capacity
+            .unwrap_or(max_workers * DEFAULT_QUEUE_PER_WORKER);
+        let (sender, receiver) = crossbeam_channel::bounded(queue_capacity);
+        let shared = Arc::new(Shared::new(
+            receiver,
+            Config {
+                min_workers: self.min_workers,
+                max_workers,
+                keep_alive: self.keep_alive,
+                name_prefix: self.name_prefix,
+                stack_size: self.stack_size,
+            },
+        ));
+        for _ in 0..self.min_workers {
+            shared.try_spawn();
         }
         ThreadPool {
-            workers,
+            shared,
             sender: Some(sender),
         }
     }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    sender: Option<Sender<Job>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        Self::builder().min_workers(size).max_workers(size).build()
+    }
 
+    /// 创建任务队列有上限的线程池，队列满时 `execute` 阻塞，`try_execute` 返回错误
+    pub fn with_queue_capacity(size: usize, queue_capacity: usize) -> ThreadPool {
+        Self::builder()
+            .min_workers(size)
+            .max_workers(size)
+            .queue_capacity(queue_capacity)
+            .build()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// 当前存活的工作线程数
+    pub fn worker_count(&self) -> usize {
+        self.shared.total.load(Ordering::SeqCst)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.monitor().stats()
+    }
+
+    /// 返回可以在其他线程读取统计数据的句柄，不影响线程池的关闭
+    pub fn monitor(&self) -> PoolMonitor {
+        PoolMonitor {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// 提交任务，队列满时阻塞直到有空位
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.execute_job(Job::new(Box::new(f)));
+    }
+
+    pub(crate) fn execute_job(&self, job: Job) {
+        let job = match self.try_send(job) {
+            Ok(()) => {
+                self.grow_if_busy();
+                return;
+            }
+            Err(TrySendError::Full(job)) => {
+                self.shared.try_spawn();
+                job
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("thread pool is shut down"),
+        };
+        self.shared.enqueue();
+        if self.sender.as_ref().unwrap().send(job).is_err() {
+            self.shared.cancel();
+            panic!("thread pool is shut down");
+        }
+    }
+
+    /// 提交有返回值的任务，通过 [`JobHandle::join`] 获取结果或 panic 负载
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.execute(move || {
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(value) => {
+                    let _ = sender.send(Ok(value));
+                }
+                Err(payload) => {
+                    let message = format!(
+                        "submitted job panicked: {}",
+                        panic_message(payload.as_ref())
+                    );
+                    let _ = sender.send(Err(payload));
+                    // 负载已交给 JobHandle，这里重新 panic 让工作线程记录统计
+                    panic::resume_unwind(Box::new(message));
+                }
+            }
+        });
+        JobHandle { receiver }
+    }
+
+    /// 在作用域内提交可以借用栈上数据的任务，返回前等待作用域内的任务全部完成
+    ///
+    /// 作用域内的任务 panic 时，该函数在所有任务结束后 panic。
+    /// 不要在线程池自己的工作线程里调用，否则可能因为没有空闲线程而死锁。
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope::new(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.panicked() => panic!("a scoped job panicked"),
+            Ok(value) => value,
+        }
+    }
+
+    /// 阻塞直到所有已提交的任务都执行完
+    pub fn wait_idle(&self) {
+        self.shared.wait_idle();
+    }
+
+    /// 关闭线程池，最多等待 `timeout`
+    ///
+    /// 已入队的任务仍会被执行；超时后未结束的工作线程被分离，在后台继续运行到队列清空。
+    /// 所有工作线程都在超时前结束时返回 true。
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+        let deadline = Instant::now() + timeout;
+
+        let mut workers = self.take_workers();
+        while !workers.is_empty() && Instant::now() < deadline {
+            let (finished, running): (Vec<Worker>, Vec<Worker>) =
+                workers.into_iter().partition(|worker| worker.is_finished());
+            for worker in finished {
+                worker.join();
+            }
+            workers = running;
+            if !workers.is_empty() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        if !workers.is_empty() {
+            warn!(
+                "{} workers still running after {:?}; detaching",
+                workers.len(),
+                timeout
+            );
+        }
+        workers.is_empty()
+    }
+
+    fn take_workers(&self) -> Vec<Worker> {
+        self.shared
+            .workers
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, worker)| worker)
+            .collect()
+    }
+
+    /// 尝试入队，失败时撤销排队计数
+    fn try_send(&self, job: Job) -> Result<(), TrySendError<Job>> {
+        self.shared.enqueue();
+        let result = self.sender.as_ref().unwrap().try_send(job);
+        if result.is_err() {
+            self.shared.cancel();
+        }
+        result
+    }
+
+    /// 提交任务，队列满时立即返回 [`ExecuteError::Full`]
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Job::new(Box::new(f));
+        let job = match self.try_send(job) {
+            Ok(()) => {
+                self.grow_if_busy();
+                return Ok(());
+            }
+            // 队列满时先尝试扩容再重试一次
+            Err(TrySendError::Full(job)) if self.shared.try_spawn() => job,
+            Err(TrySendError::Full(_)) => return Err(ExecuteError::Full),
+            Err(TrySendError::Disconnected(_)) => return Err(ExecuteError::Disconnected),
+        };
+        match self.try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(ExecuteError::Full),
+            Err(TrySendError::Disconnected(_)) => Err(ExecuteError::Disconnected),
+        }
+    }
+
+    /// 任务入队后没有空闲线程时增加一个工作线程
+    fn grow_if_busy(&self) {
+        if self.shared.idle.load(Ordering::SeqCst) == 0 {
+            self.shared.try_spawn();
+        }
+    }
+}
+
+/// 线程池统计数据的只读句柄
+#[derive(Clone)]
+pub struct PoolMonitor {
+    shared: Arc<Shared>,
+}
+
+impl PoolMonitor {
+    pub fn stats(&self) -> PoolStats {
+        PoolStats::snapshot(
+            self.shared.totals(),
+            self.shared.total.load(Ordering::SeqCst),
+            self.shared.idle.load(Ordering::SeqCst),
+            self.shared.receiver.len(),
+        )
     }
 }
 
@@ -33,8 +344,155 @@ impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
 
-        for worker in self.workers.drain(..) {
+        for worker in self.take_workers() {
             worker.join();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_try_execute_rejects_when_queue_is_full() {
+        let pool = ThreadPool::with_queue_capacity(1, 1);
+        let (release, blocked) = channel::<()>();
+        let (started, wait_started) = channel();
+
+        // 占住唯一的工作线程
+        pool.execute(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        wait_started.recv().unwrap();
+
+        // 队列容量为 1，第二个任务排队，第三个被拒绝
+        assert_eq!(pool.try_execute(|| {}), Ok(()));
+        assert_eq!(pool.try_execute(|| {}), Err(ExecuteError::Full));
+
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_worker_survives_panicking_job() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed"));
+
+        let (sender, receiver) = channel();
+        pool.execute(move || sender.send(42).unwrap());
+        assert_eq!(receiver.recv().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_elastic_pool_grows_and_reaps_idle_workers() {
+        let pool = ThreadPool::builder()
+            .min_workers(1)
+            .max_workers(3)
+            .keep_alive(Duration::from_millis(50))
+            .name_prefix("test-worker")
+            .build();
+        assert_eq!(pool.worker_count(), 1);
+
+        let (release, blocked) = channel::<()>();
+        let blocked = Arc::new(std::sync::Mutex::new(blocked));
+        let (names, received) = channel();
+        for _ in 0..3 {
+            let blocked = Arc::clone(&blocked);
+            let names = names.clone();
+            pool.execute(move || {
+                names
+                    .send(std::thread::current().name().unwrap().to_string())
+                    .unwrap();
+                blocked.lock().unwrap().recv().unwrap();
+            });
+            // 等任务开始运行后再提交下一个，保证线程池看到没有空闲线程
+            let name = received.recv().unwrap();
+            assert!(name.starts_with("test-worker-"));
+        }
+        assert_eq!(pool.worker_count(), 3);
+
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(pool.worker_count(), 1);
+    }
+
+    #[test]
+    fn test_stats_track_jobs_and_queue() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = channel::<()>();
+        let (started, wait_started) = channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        wait_started.recv().unwrap();
+        pool.execute(|| panic!("job failed"));
+
+        let stats = pool.stats();
+        assert_eq!(stats.workers, 1);
+        assert_eq!(stats.active_workers, 1);
+        assert_eq!(stats.queue_depth, 1);
+
+        release.send(()).unwrap();
+        let monitor = pool.monitor();
+        drop(pool);
+        let stats = monitor.stats();
+        assert_eq!(stats.jobs_completed, 1);
+        assert_eq!(stats.jobs_panicked, 1);
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.workers, 0);
+    }
+
+    #[test]
+    fn test_submit_returns_result_or_panic_payload() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.submit(|| 6 * 7);
+        assert_eq!(handle.join().unwrap(), 42);
+
+        let handle = pool.submit(|| -> u32 { panic!("index failed") });
+        let payload = handle.join().unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "index failed");
+    }
+
+    #[test]
+    fn test_scope_borrows_stack_data() {
+        let pool = ThreadPool::new(3);
+        let mut chunks = vec![vec![1, 2], vec![3, 4], vec![5, 6]];
+        let total = std::sync::atomic::AtomicUsize::new(0);
+        pool.scope(|scope| {
+            for chunk in chunks.iter_mut() {
+                let total = &total;
+                scope.execute(move || {
+                    chunk.iter_mut().for_each(|value| *value *= 10);
+                    total.fetch_add(chunk.iter().sum::<usize>(), Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(chunks, vec![vec![10, 20], vec![30, 40], vec![50, 60]]);
+        assert_eq!(total.load(Ordering::SeqCst), 210);
+    }
+
+    #[test]
+    fn test_wait_idle_and_shutdown_timeout() {
+        let pool = ThreadPool::new(2);
+        let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        for _ in 0..10 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(5));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.wait_idle();
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+        assert!(pool.shutdown_timeout(Duration::from_secs(1)));
+
+        let pool = ThreadPool::new(1);
+        pool.execute(|| thread::sleep(Duration::from_millis(300)));
+        assert!(!pool.shutdown_timeout(Duration::from_millis(20)));
+    }
+}
@@ -1,38 +1,221 @@
+use crate::handle::panic_message;
 use crate::job::Job;
-use log::{debug, info};
+use crate::stats::{Counters, Totals, WorkerCounters};
+use crossbeam_channel::{Receiver, RecvTimeoutError, TryRecvError};
+use crossbeam_utils::CachePadded;
+use log::{debug, error, info};
 use std::{
-    sync::{Arc, Mutex, mpsc},
+    collections::HashMap,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
     thread::{self},
+    time::{Duration, Instant},
 };
+
+/// 线程池配置，由 `Builder` 生成
+pub(crate) struct Config {
+    pub(crate) min_workers: usize,
+    pub(crate) max_workers: usize,
+    pub(crate) keep_alive: Duration,
+    pub(crate) name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+}
+
+/// 线程池和所有工作线程共享的状态
+pub(crate) struct Shared {
+    /// 无锁的多生产者多消费者队列，每个工作线程直接从中取任务
+    pub(crate) receiver: Receiver<Job>,
+    pub(crate) config: Config,
+    /// 当前存活的工作线程数
+    pub(crate) total: AtomicUsize,
+    /// 正在等待任务的工作线程数
+    pub(crate) idle: CachePadded<AtomicUsize>,
+    next_id: AtomicUsize,
+    pub(crate) workers: Mutex<HashMap<usize, Worker>>,
+    /// 已提交的任务数，只由提交任务的线程写入
+    submitted: CachePadded<AtomicU64>,
+    pub(crate) counters: Mutex<Counters>,
+    /// 正在 `wait_idle` 里等待的线程数，没有等待者时任务完成不需要加锁
+    idle_waiters: AtomicUsize,
+    idle_lock: Mutex<()>,
+    idle_cv: Condvar,
+}
+
+impl Shared {
+    pub(crate) fn new(receiver: Receiver<Job>, config: Config) -> Self {
+        Self {
+            receiver,
+            config,
+            total: AtomicUsize::new(0),
+            idle: CachePadded::new(AtomicUsize::new(0)),
+            next_id: AtomicUsize::new(0),
+            workers: Mutex::new(HashMap::new()),
+            submitted: CachePadded::new(AtomicU64::new(0)),
+            counters: Mutex::default(),
+            idle_waiters: AtomicUsize::new(0),
+            idle_lock: Mutex::new(()),
+            idle_cv: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn enqueue(&self) {
+        self.submitted.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// 入队失败时撤销计数
+    pub(crate) fn cancel(&self) {
+        self.submitted.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// 汇总所有工作线程（包括已退出的）的计数
+    pub(crate) fn totals(&self) -> Totals {
+        self.counters.lock().unwrap().totals()
+    }
+
+    /// 等待所有已提交的任务执行完
+    pub(crate) fn wait_idle(&self) {
+        let mut guard = self.idle_lock.lock().unwrap();
+        self.idle_waiters.fetch_add(1, Ordering::SeqCst);
+        while self.totals().finished() < self.submitted.load(Ordering::SeqCst) {
+            guard = self.idle_cv.wait(guard).unwrap();
+        }
+        self.idle_waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// 队列已空时唤醒 `wait_idle`，队列非空时后续任务完成时还会再检查
+    fn finish_job(&self) {
+        if self.idle_waiters.load(Ordering::SeqCst) > 0 && self.receiver.is_empty() {
+            let _guard = self.idle_lock.lock().unwrap();
+            self.idle_cv.notify_all();
+        }
+    }
+
+    /// 工作线程数未达到上限时新建一个工作线程
+    pub(crate) fn try_spawn(self: &Arc<Self>) -> bool {
+        let max = self.config.max_workers;
+        if self
+            .total
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                (total < max).then_some(total + 1)
+            })
+            .is_err()
+        {
+            return false;
+        }
+        match Worker::spawn(self) {
+            Ok(()) => true,
+            Err(e) => {
+                self.total.fetch_sub(1, Ordering::SeqCst);
+                error!("Failed to spawn worker: {e}");
+                false
+            }
+        }
+    }
+
+    /// 工作线程数大于下限时允许一个空闲线程退出
+    fn try_retire(&self) -> bool {
+        let min = self.config.min_workers;
+        self.total
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                (total > min).then(|| total - 1)
+            })
+            .is_ok()
+    }
+}
+
 pub struct Worker {
     id: usize,
     thread: thread::JoinHandle<()>,
 }
 
 impl Worker {
-    pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        Worker {
-            id,
-            thread: thread::spawn(move || {
-                loop {
-                    let job = receiver.lock().unwrap().recv();
-                    match job {
-                        Ok(job) => {
-                            debug!("Worker {id} got a job; executing.");
-                            job();
-                        }
-                        Err(_) => {
-                            debug!("Worker {id} disconnected; shutting down.");
-                            break;
-                        }
+    fn spawn(shared: &Arc<Shared>) -> io::Result<()> {
+        let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut builder =
+            thread::Builder::new().name(format!("{}-{id}", shared.config.name_prefix));
+        if let Some(stack_size) = shared.config.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let counters = shared.counters.lock().unwrap().register(id);
+        let worker_shared = Arc::clone(shared);
+        let worker_counters = Arc::clone(&counters);
+        let thread = builder.spawn(move || Worker::run(id, &worker_shared, &worker_counters))?;
+        shared
+            .workers
+            .lock()
+            .unwrap()
+            .insert(id, Worker { id, thread });
+        Ok(())
+    }
+
+    /// 取一个任务，队列里有任务时不修改共享的空闲计数
+    fn next_job(shared: &Shared) -> Result<Job, RecvTimeoutError> {
+        match shared.receiver.try_recv() {
+            Ok(job) => Ok(job),
+            Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+            Err(TryRecvError::Empty) => {
+                shared.idle.fetch_add(1, Ordering::SeqCst);
+                let job = shared.receiver.recv_timeout(shared.config.keep_alive);
+                shared.idle.fetch_sub(1, Ordering::SeqCst);
+                job
+            }
+        }
+    }
+
+    fn run(id: usize, shared: &Shared, counters: &WorkerCounters) {
+        let mut job_count: u64 = 0;
+        loop {
+            match Worker::next_job(shared) {
+                Ok(job) => {
+                    job_count += 1;
+                    debug!("Worker {id} got job #{job_count}; executing.");
+                    let started_at = Instant::now();
+                    // 任务 panic 时只丢弃该任务，工作线程继续处理后续任务
+                    let result = panic::catch_unwind(AssertUnwindSafe(job.task));
+                    if let Err(payload) = &result {
+                        error!(
+                            "Worker {id} job #{job_count} panicked: {}",
+                            panic_message(payload.as_ref())
+                        );
+                    }
+                    counters.record(
+                        started_at - job.enqueued_at,
+                        started_at.elapsed(),
+                        result.is_err(),
+                    );
+                    shared.finish_job();
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if shared.try_retire() {
+                        debug!("Worker {id} idle too long; retiring.");
+                        // 自己无法 join 自己，直接移除句柄让线程分离退出
+                        shared.workers.lock().unwrap().remove(&id);
+                        shared.counters.lock().unwrap().retire(id);
+                        break;
                     }
                 }
-            }),
+                Err(RecvTimeoutError::Disconnected) => {
+                    debug!("Worker {id} disconnected; shutting down.");
+                    shared.total.fetch_sub(1, Ordering::SeqCst);
+                    break;
+                }
+            }
         }
     }
 
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
     pub fn join(self) {
         info!("Shutting down worker {}", self.id);
-        self.thread.join().unwrap();
+        if self.thread.join().is_err() {
+            error!("Worker {} terminated abnormally", self.id);
+        }
     }
 }